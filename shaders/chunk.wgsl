@@ -1,17 +1,30 @@
+#import minecraft_blockfinder::rotation::get_block_rotation
+
 @group(0) @binding(0)
 var<uniform> position: vec2<i32>;
 
 @group(0) @binding(1)
 var<storage, read_write> chunk: array<u32>;
 
+// heights of the tile that are generated, one per invocation along y
+@group(0) @binding(2)
+var<storage, read> layers: array<u32>;
+
+// every thread packs 16 blocks along x
+const PACKED_SIZE: vec3<u32> = vec3(#{TILE_SIZE} / 16, #{TILE_HEIGHT}, #{TILE_SIZE});
+
 
-@compute @workgroup_size(1)
-fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>, @builtin(num_workgroups) workgroups: vec3<u32>) {
-    let index = to_index(workgroups, invocation_id);
-    chunk[index] = get_block_rotations(vec3i64(invocation_id) * vec3(16,1,1) + vec3(i64(position.x),0,i64(position.y)));
+@compute @workgroup_size(#{WORKGROUP_SIZE})
+fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if invocation_id.x >= PACKED_SIZE.x {
+        return;
+    }
+    let packed_pos = vec3(invocation_id.x, layers[invocation_id.y], invocation_id.z);
+    let index = to_index(PACKED_SIZE, packed_pos);
+    chunk[index] = get_block_rotations(vec3<i32>(packed_pos) * vec3(16,1,1) + vec3(position.x,0,position.y));
 }
 
-fn get_block_rotations(pos: vec3<i64>) -> u32 {
+fn get_block_rotations(pos: vec3<i32>) -> u32 {
     return pack16xU2(array<u32, 16>(
         get_block_rotation(pos),
         get_block_rotation(pos + vec3(1,0,0)),
@@ -33,24 +46,6 @@ fn get_block_rotations(pos: vec3<i64>) -> u32 {
 }
 
 
-fn vec3i64(value: vec3<u32>) -> vec3<i64> {
-    return vec3(i64(value.x), i64(value.y), i64(value.z));
-}
-
-fn get_block_rotation(pos: vec3<i64>) -> u32 {
-    return u32(abs(get_rotation_from_seed(get_rendering_seed(pos.x, pos.y, pos.z) >> 16)) & 3);
-}
-fn get_rendering_seed(x: i64, y: i64, z: i64) -> i64 {
-    let l = (x * 3129871) ^ z * 116129781 ^ y;
-    let l2 = (l * l * 42317861) + l * 11;
-    return l2;
-}
-
-fn get_rotation_from_seed(seed: i64) -> i32 {
-    let seed2 = (seed ^ 0x5DEECE66D) & 0xFFFFFFFFFFFF;
-    let value = i64((u64(seed2 * 0xBB20B4600A69 + 0x40942DE6BA) >> 16) );
-    return i32(value);
-}
 
 // helper functions
 
@@ -4,35 +4,62 @@ var<uniform> chunk_size: vec3<u32>;
 @group(0) @binding(1)
 var<storage, read_write> chunk: array<u32>;
 
+// the patterns one after another, 4 rotations per u32
 @group(0) @binding(2)
 var<storage, read> grid: array<u32>;
 
+struct Matches {
+    count: atomic<u32>,
+    // positions with the index of their pattern, the ones past the end are dropped
+    positions: array<vec4<u32>, #{MAX_CANDIDATES}>,
+}
+
 @group(0) @binding(3)
-var<storage, read_write> position: array<u32,3>;
-
-const FIND_SIZE: u32 = 32;
-
-@compute @workgroup_size(1)
-fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>, @builtin(num_workgroups) workgroups: vec3<u32>) {
-    var result = true;
-    for(var x: u32 = 0; x < FIND_SIZE; x+=1u) {
-        for(var y: u32 = 0; y < FIND_SIZE; y+=1u) {
-            for(var z: u32 = 0; z < FIND_SIZE; z+=1u) {
-                let grid_data = get_grid(to_index(vec3<u32>(FIND_SIZE), vec3<u32>(x,y,z)));
-                let chunk_data = get_chunk(to_index(chunk_size, invocation_id+vec3<u32>(x,y,z)));
+var<storage, read_write> matches: Matches;
+
+@group(0) @binding(4)
+var<uniform> pattern_size: vec3<u32>;
+
+// heights of the tile that are checked, one per invocation along y
+@group(0) @binding(5)
+var<storage, read> layers: array<u32>;
+
+@group(0) @binding(6)
+var<uniform> pattern_count: u32;
+
+@compute @workgroup_size(#{WORKGROUP_SIZE})
+fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    // the last workgroup along x may reach past the positions of the tile
+    if invocation_id.x > chunk_size.x - pattern_size.x {
+        return;
+    }
+    let origin = vec3(invocation_id.x, layers[invocation_id.y], invocation_id.z);
+    let volume = pattern_size.x * pattern_size.y * pattern_size.z;
+    for(var pattern: u32 = 0; pattern < pattern_count; pattern+=1u) {
+        if is_match(origin, pattern * volume) {
+            let index = atomicAdd(&matches.count, 1u);
+            if index < #{MAX_CANDIDATES} {
+                matches.positions[index] = vec4(origin, pattern);
+            }
+            return;
+        }
+    }
+}
+
+// whether the pattern whose rotations start at `start` in the grid is at `origin`
+fn is_match(origin: vec3<u32>, start: u32) -> bool {
+    for(var x: u32 = 0; x < pattern_size.x; x+=1u) {
+        for(var y: u32 = 0; y < pattern_size.y; y+=1u) {
+            for(var z: u32 = 0; z < pattern_size.z; z+=1u) {
+                let grid_data = get_grid(start + to_index(pattern_size, vec3<u32>(x,y,z)));
+                let chunk_data = get_chunk(to_index(chunk_size, origin+vec3<u32>(x,y,z)));
                 if !check(grid_data, chunk_data) {
-                    result = false;
-                    return;
+                    return false;
                 }
             }
         }
     }
-    if(result) {
-        position[0] = invocation_id.x;
-        position[1] = invocation_id.y;
-        position[2] = invocation_id.z;
-    }
-    
+    return true;
 }
 
 fn to_index(workgroups: vec3<u32>, position: vec3<u32>) -> u32 {
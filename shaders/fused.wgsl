@@ -0,0 +1,70 @@
+#import minecraft_blockfinder::rotation::get_block_rotation
+
+@group(0) @binding(0)
+var<uniform> position: vec2<i32>;
+
+// xyz: offset of the block inside its pattern
+// w: rotation (8 bits), index of the pattern (8 bits), first block of the next pattern (16 bits)
+@group(0) @binding(1)
+var<storage, read> pattern: array<vec4<i32>>;
+
+struct Matches {
+    count: atomic<u32>,
+    // positions with the index of their pattern, the ones past the end are dropped
+    positions: array<vec4<u32>, #{MAX_CANDIDATES}>,
+}
+
+@group(0) @binding(2)
+var<storage, read_write> matches: Matches;
+
+@group(0) @binding(3)
+var<uniform> pattern_size: vec3<u32>;
+
+// heights of the tile that are checked, one per invocation along y
+@group(0) @binding(4)
+var<storage, read> layers: array<u32>;
+
+
+@compute @workgroup_size(#{WORKGROUP_SIZE})
+fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    // the last workgroup along x may reach past the positions of the tile
+    if invocation_id.x > #{TILE_SIZE} - pattern_size.x {
+        return;
+    }
+    let tile_pos = vec3(invocation_id.x, layers[invocation_id.y], invocation_id.z);
+    let origin = vec3<i32>(tile_pos) + vec3(position.x, 0, position.y);
+    var i = 0u;
+    while i < arrayLength(&pattern) {
+        let block = pattern[i];
+        let data = u32(block.w);
+        let next = data >> 16u;
+        let pos = origin + block.xyz;
+        if !check(data & 255u, get_block_rotation(pos)) {
+            // the rest of this pattern doesn't need to be checked
+            i = next;
+        } else if i + 1u == next {
+            let index = atomicAdd(&matches.count, 1u);
+            if index < #{MAX_CANDIDATES} {
+                matches.positions[index] = vec4(tile_pos, (data >> 8u) & 255u);
+            }
+            return;
+        } else {
+            i += 1u;
+        }
+    }
+}
+
+fn check(grid_rotation: u32, chunk_rotation: u32) -> bool {
+    let max_rotation = get_max_rotation(grid_rotation);
+    let rotation = get_rotation(grid_rotation);
+    return (max_rotation <= 1
+         || (chunk_rotation % max_rotation) == rotation);
+}
+
+fn get_rotation(data: u32) -> u32 {
+    return data & 15;
+}
+
+fn get_max_rotation(data: u32) -> u32 {
+    return (data >> 4) & 15;
+}
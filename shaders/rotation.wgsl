@@ -0,0 +1,69 @@
+#define_import_path minecraft_blockfinder::rotation
+
+// rendered rotation of the block at pos, like `get_block_rotation` in finder/util.rs
+#ifdef SHADER_INT64
+fn get_block_rotation(pos: vec3<i32>) -> u32 {
+    let seed = get_rendering_seed(i64(pos.x), i64(pos.y), i64(pos.z));
+    return u32(abs(get_rotation_from_seed(seed >> 16)) & 3);
+}
+fn get_rendering_seed(x: i64, y: i64, z: i64) -> i64 {
+    let l = (x * 3129871) ^ z * 116129781 ^ y;
+    let l2 = (l * l * 42317861) + l * 11;
+    return l2;
+}
+
+fn get_rotation_from_seed(seed: i64) -> i32 {
+    let seed2 = (seed ^ 0x5DEECE66D) & 0xFFFFFFFFFFFF;
+    let value = i64((u64(seed2 * 0xBB20B4600A69 + 0x40942DE6BA) >> 16) );
+    return i32(value);
+}
+#else
+// the adapter has no 64 bit integers, they are emulated as vec2(low, high)
+fn get_block_rotation(pos: vec3<i32>) -> u32 {
+    return u32(abs(get_rotation_from_seed(shr16(get_rendering_seed(pos.x, pos.y, pos.z))))) & 3;
+}
+fn get_rendering_seed(x: i32, y: i32, z: i32) -> vec2<u32> {
+    let l = mul64(from_i32(x), vec2(3129871u, 0u)) ^ mul64(from_i32(z), vec2(116129781u, 0u)) ^ from_i32(y);
+    let l2 = add64(mul64(mul64(l, l), vec2(42317861u, 0u)), mul64(l, vec2(11u, 0u)));
+    return l2;
+}
+
+fn get_rotation_from_seed(seed: vec2<u32>) -> i32 {
+    let seed2 = (seed ^ vec2(0xDEECE66Du, 0x5u)) & vec2(0xFFFFFFFFu, 0xFFFFu);
+    let value = add64(mul64(seed2, vec2(0xB4600A69u, 0xBB20u)), vec2(0x942DE6BAu, 0x40u));
+    // only the low 32 bits of value >> 16 are kept
+    return i32((value.x >> 16u) | (value.y << 16u));
+}
+
+fn from_i32(value: i32) -> vec2<u32> {
+    return vec2(u32(value), select(0u, 0xFFFFFFFFu, value < 0));
+}
+
+fn add64(a: vec2<u32>, b: vec2<u32>) -> vec2<u32> {
+    let low = a.x + b.x;
+    return vec2(low, a.y + b.y + select(0u, 1u, low < a.x));
+}
+
+// full product of two 32 bit integers, split in 16 bit halves
+fn mul32(a: u32, b: u32) -> vec2<u32> {
+    let a0 = a & 0xFFFFu;
+    let a1 = a >> 16u;
+    let b0 = b & 0xFFFFu;
+    let b1 = b >> 16u;
+    let p00 = a0 * b0;
+    let p01 = a0 * b1;
+    let p10 = a1 * b0;
+    let mid = (p00 >> 16u) + (p01 & 0xFFFFu) + (p10 & 0xFFFFu);
+    return vec2((p00 & 0xFFFFu) | (mid << 16u), a1 * b1 + (p01 >> 16u) + (p10 >> 16u) + (mid >> 16u));
+}
+
+fn mul64(a: vec2<u32>, b: vec2<u32>) -> vec2<u32> {
+    let low = mul32(a.x, b.x);
+    return vec2(low.x, low.y + a.x * b.y + a.y * b.x);
+}
+
+// arithmetic shift like i64 >> 16
+fn shr16(value: vec2<u32>) -> vec2<u32> {
+    return vec2((value.x >> 16u) | (value.y << 16u), u32(i32(value.y) >> 16u));
+}
+#endif
@@ -0,0 +1,198 @@
+use std::{fs, path::PathBuf, time::Duration};
+
+use bevy::{math::UVec3, prelude::Resource};
+
+use crate::{
+    constants::WORLD_HEIGHT,
+    finder::{plugin::FinderJob, util::check_rotation, Rotation},
+    paths::data_dir,
+};
+
+const THROUGHPUT_FILE: &str = "finder_throughput.txt";
+
+/// Radius around 0,0 used for the estimates in the builder.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct SearchRadius(pub u64);
+
+impl Default for SearchRadius {
+    fn default() -> Self {
+        Self(100_000)
+    }
+}
+
+/// How much a pattern narrows down the search.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PatternAnalysis {
+    /// blocks with a known rotation out of more than one variant, in the least constrained
+    /// pattern of the job
+    pub constrained_blocks: usize,
+    /// bits of information, assuming the rotations of different blocks are independent, and
+    /// that any of the patterns of the job may match, see `variant_probability`
+    pub bits: f64,
+    pub size: UVec3,
+    /// heights the pattern is searched at, see `FinderJob::y_levels`
+    pub layers: u32,
+}
+
+impl PatternAnalysis {
+    pub fn new(job: &FinderJob) -> Self {
+        // constrained blocks and bits of each pattern
+        let patterns: Vec<(usize, f64)> = job
+            .patterns()
+            .map(|pattern| {
+                pattern
+                    .iter()
+                    .map(|v| Rotation(*v))
+                    .filter(|rotation| rotation.get_max_rotation() > 1)
+                    .fold((0, 0.0), |(blocks, bits), rotation| {
+                        (blocks + 1, bits - variant_probability(rotation).log2())
+                    })
+            })
+            .collect();
+        let match_probability: f64 = patterns.iter().map(|(_, bits)| (-bits).exp2()).sum();
+        Self {
+            constrained_blocks: patterns.iter().map(|v| v.0).min().unwrap_or(0),
+            bits: match_probability.recip().log2(),
+            size: job.size,
+            layers: job.layers(WORLD_HEIGHT as u32).len() as u32,
+        }
+    }
+
+    /// Number of pattern positions the finder checks within `radius` blocks of 0,0.
+    pub fn search_positions(&self, radius: u64) -> f64 {
+        (2.0 * radius as f64).powi(2) * self.layers()
+    }
+
+    fn layers(&self) -> f64 {
+        self.layers as f64
+    }
+
+    /// Chance of a random position matching the pattern.
+    pub fn match_probability(&self) -> f64 {
+        (-self.bits).exp2()
+    }
+
+    /// Expected number of positions within `radius` that match by coincidence.
+    pub fn expected_false_positives(&self, radius: u64) -> f64 {
+        self.search_positions(radius) * self.match_probability()
+    }
+
+    /// Radius up to which a match is expected to be the only one.
+    pub fn unique_radius(&self) -> f64 {
+        (self.bits.exp2() / self.layers()).sqrt() / 2.0
+    }
+
+    /// Time to search the whole `radius` with `throughput` checked positions per second.
+    pub fn eta(&self, radius: u64, throughput: f64) -> Option<Duration> {
+        let seconds = self.search_positions(radius) / throughput;
+        (throughput > 0.0 && seconds.is_finite()).then(|| Duration::from_secs_f64(seconds))
+    }
+}
+
+/// Chance of a block being rendered with the variant of `rotation`.
+///
+/// The rendered rotation takes 4 equally likely values and the variant is the rotation modulo
+/// the number of variants, so with 3 variants the first one is twice as likely as the others.
+pub fn variant_probability(rotation: Rotation) -> f64 {
+    (0..4)
+        .filter(|value| check_rotation(rotation, *value))
+        .count() as f64
+        / 4.0
+}
+
+fn throughput_file() -> PathBuf {
+    data_dir().join(THROUGHPUT_FILE)
+}
+
+/// Throughput in positions per second last measured on the adapter with this name.
+pub fn load_throughput(backend: &str) -> Option<f64> {
+    fs::read_to_string(throughput_file())
+        .ok()?
+        .lines()
+        .filter_map(|line| line.rsplit_once('\t'))
+        .find(|(name, _)| *name == backend)
+        .and_then(|(_, value)| value.parse().ok())
+}
+
+pub fn save_throughput(backend: &str, throughput: f64) {
+    let path = throughput_file();
+    let mut lines: Vec<String> = fs::read_to_string(&path)
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| line.rsplit_once('\t').map(|v| v.0) != Some(backend))
+        .map(str::to_owned)
+        .collect();
+    lines.push(format!("{backend}\t{throughput}"));
+    let written = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::write(&path, lines.join("\n")));
+    if let Err(err) = written {
+        bevy::log::warn!("couldn't save finder throughput: {err}");
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..=59 => format!("{seconds}s"),
+        60..=3599 => format!("{}m {}s", seconds / 60, seconds % 60),
+        3600..=86399 => format!("{}h {}m", seconds / 3600, seconds % 3600 / 60),
+        _ => format!("{}d {}h", seconds / 86400, seconds % 86400 / 3600),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use bevy::math::{IVec3, UVec3};
+
+    use super::{format_duration, variant_probability, PatternAnalysis};
+    use crate::finder::{plugin::FinderJob, util::get_block_rotation, Rotation};
+
+    #[test]
+    fn test_variant_probability() {
+        assert_eq!(variant_probability(Rotation::new(1, 4)), 0.25);
+        assert_eq!(variant_probability(Rotation::new(1, 2)), 0.5);
+        assert_eq!(variant_probability(Rotation::new(0, 3)), 0.5);
+        assert_eq!(variant_probability(Rotation::new(2, 3)), 0.25);
+        // the rendered rotations are close to uniform
+        let first_of_three = (0..40_000)
+            .filter(|i| get_block_rotation(i % 200, 64, i / 200).is_multiple_of(3))
+            .count();
+        assert!((19_000..21_000).contains(&first_of_three));
+    }
+
+    #[test]
+    fn test_pattern_analysis() {
+        let job = FinderJob {
+            size: UVec3::new(5, 1, 1),
+            offset: IVec3::ZERO,
+            rotations: [(1, 4), (0, 2), (0, 3), (1, 3), (0, 0)]
+                .map(|(rotation, max_rotation)| Rotation::new(rotation, max_rotation).0)
+                .to_vec(),
+            y_levels: vec![64],
+        };
+        let analysis = PatternAnalysis::new(&job);
+        assert_eq!(analysis.constrained_blocks, 4);
+        assert_eq!(analysis.bits, 2.0 + 1.0 + 1.0 + 2.0);
+        assert_eq!(analysis.layers, 1);
+        // 64 positions in a radius of 4, one of which is expected to match
+        assert_eq!(analysis.unique_radius(), 4.0);
+        assert_eq!(analysis.expected_false_positives(4), 1.0);
+        assert_eq!(analysis.eta(4, 64.0), Some(Duration::from_secs(1)));
+        assert_eq!(analysis.eta(4, 0.0), None);
+        // either of two equal patterns matches twice as often
+        let both = FinderJob::combine(&[job.clone(), job]).unwrap();
+        assert_eq!(PatternAnalysis::new(&both).bits, 5.0);
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(59)), "59s");
+        assert_eq!(format_duration(Duration::from_secs(61)), "1m 1s");
+        assert_eq!(format_duration(Duration::from_secs(3600)), "1h 0m");
+        assert_eq!(format_duration(Duration::from_secs(90_000)), "1d 1h");
+    }
+}
@@ -0,0 +1,399 @@
+use std::{
+    hint::black_box,
+    ops::Range,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use bevy::math::{IVec3, UVec3};
+
+use crate::{
+    constants::{CHUNK_SIZE, WORLD_HEIGHT},
+    finder::{
+        chunk::generate_grid,
+        compute::{ComputeRunner, TileSource},
+        distributed::search_cpu,
+        plugin::{FinderJob, FinderKernel, FinderStatus, PatternMatch},
+        region::{solve_region, ChunkPattern, SeedTable, SparsePattern},
+        tile::TileSettings,
+        util::{block_rotations_fns, get_block_rotation},
+    },
+    server::json_string,
+};
+
+/// How long the work of a benchmark is repeated, at least one round is always finished.
+const MIN_TIME: Duration = Duration::from_secs(1);
+
+/// Patterns copied from the world near 0,0, so every backend finds them in the first tile.
+const STANDARD_PATTERNS: [(&str, IVec3, UVec3); 3] = [
+    ("flat_6x4", IVec3::new(37, 64, 21), UVec3::new(6, 1, 4)),
+    ("flat_8x8", IVec3::new(90, 66, 140), UVec3::new(8, 1, 8)),
+    ("box_4x3x4", IVec3::new(200, 61, 12), UVec3::new(4, 3, 4)),
+];
+
+/// Heights the standard patterns are searched at, like a user who roughly knows them.
+const STANDARD_Y_LEVELS: Range<i32> = 60..71;
+
+/// Tiles of the CPU backend, smaller than the GPU ones so a tile takes seconds, not minutes.
+const CPU_TILE: TileSettings = TileSettings {
+    size: 256,
+    height: WORLD_HEIGHT as u32,
+    workgroup_size: 64,
+};
+
+/// Result of one benchmark.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Measurement {
+    /// `rotations`, `matchers` or `end_to_end`
+    pub group: &'static str,
+    pub name: String,
+    /// `cpu`, the CPU features like `avx2`, or the GPU kernel
+    pub backend: &'static str,
+    /// rotations generated, candidates checked or blocks searched in `time`
+    pub count: u64,
+    pub time: Duration,
+    /// why it couldn't be measured, or what the search found instead of the pattern
+    pub error: Option<String>,
+}
+
+impl Measurement {
+    fn new(
+        group: &'static str,
+        name: &str,
+        backend: &'static str,
+        result: Result<(u64, Duration), String>,
+    ) -> Self {
+        let ((count, time), error) = match result {
+            Ok(measured) => (measured, None),
+            Err(err) => ((0, Duration::ZERO), Some(err)),
+        };
+        Self {
+            group,
+            name: name.to_owned(),
+            backend,
+            count,
+            time,
+            error,
+        }
+    }
+
+    pub fn per_second(&self) -> f64 {
+        match self.time.is_zero() {
+            true => 0.0,
+            false => self.count as f64 / self.time.as_secs_f64(),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"group\":{},\"name\":{},\"backend\":{},\"count\":{},\"seconds\":{:.6},\
+             \"per_second\":{:.0},\"error\":{}}}",
+            json_string(self.group),
+            json_string(&self.name),
+            json_string(self.backend),
+            self.count,
+            self.time.as_secs_f64(),
+            self.per_second(),
+            self.error
+                .as_deref()
+                .map(json_string)
+                .unwrap_or("null".to_owned())
+        )
+    }
+}
+
+/// Throughput of every finder backend, for comparing them and catching regressions.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    /// adapters the GPU kernels ran on, empty without a GPU
+    pub adapters: Vec<String>,
+    pub threads: usize,
+    pub measurements: Vec<Measurement>,
+}
+
+impl Report {
+    /// Runs every benchmark, calling `on_measurement` after each of them.
+    ///
+    /// The GPU kernels are skipped with an error if no adapter can be opened.
+    pub fn run(mut on_measurement: impl FnMut(&Measurement)) -> Self {
+        let mut runners = ComputeRunner::from_env();
+        let mut report = Self {
+            adapters: match &runners {
+                Ok(runners) => runners
+                    .iter()
+                    .map(|runner| runner.adapter_name().to_owned())
+                    .collect(),
+                Err(_) => Vec::new(),
+            },
+            threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+            measurements: Vec::new(),
+        };
+        let mut record = |measurement: Measurement| {
+            on_measurement(&measurement);
+            report.measurements.push(measurement);
+        };
+
+        record(Measurement::new(
+            "rotations",
+            "get_block_rotation",
+            "cpu",
+            Ok(repeat(|round| {
+                let z = round as i64;
+                let rotations = (0..4096).map(|x| get_block_rotation(x, 64, z) as u32);
+                black_box(rotations.sum::<u32>());
+                4096
+            })),
+        ));
+        for (backend, get_block_rotations) in block_rotations_fns() {
+            record(Measurement::new(
+                "rotations",
+                "get_block_rotations",
+                backend,
+                Ok(repeat(|round| {
+                    let z = round as i64;
+                    let packed = (0..4096).step_by(16).map(|x| get_block_rotations(x, 64, z));
+                    black_box(packed.fold(0, |a, b| a ^ b));
+                    4096
+                })),
+            ));
+        }
+        record(Measurement::new(
+            "rotations",
+            "generate_grid",
+            "cpu",
+            Ok(repeat(|round| {
+                let size = (CHUNK_SIZE, WORLD_HEIGHT, CHUNK_SIZE);
+                black_box(generate_grid(round as i64 * CHUNK_SIZE as i64, 0, 0, size));
+                (CHUNK_SIZE * WORLD_HEIGHT * CHUNK_SIZE) as u64
+            })),
+        ));
+
+        // a pattern from far away, so the matchers check every candidate
+        let (name, _, size) = STANDARD_PATTERNS[0];
+        let job = FinderJob::from_world(IVec3::new(1_000_000, 64, -1_000_000), size);
+        let pattern = &SparsePattern::from_job(&job)[0];
+        let (offset_min, offset_max) = pattern.bounds();
+        record(Measurement::new(
+            "matchers",
+            name,
+            "sparse_pattern",
+            Ok(repeat(|round| {
+                let candidates = candidate_box(round, IVec3::new(64, 8, 64));
+                let matches = candidates.filter(|pos| pattern.mismatches(*pos, 0) == 0);
+                black_box(matches.count());
+                64 * 8 * 64
+            })),
+        ));
+        record(Measurement::new(
+            "matchers",
+            name,
+            "seed_table",
+            Ok(repeat(|round| {
+                let (min, max) = (round as i32 * 64, round as i32 * 64 + 63);
+                let table = SeedTable::new(
+                    IVec3::new(min, 60, 0) + offset_min,
+                    IVec3::new(max, 67, 63) + offset_max,
+                );
+                let candidates = candidate_box(round, IVec3::new(64, 8, 64));
+                let matches = candidates.filter(|pos| pattern.mismatches_in(&table, *pos, 0) == 0);
+                black_box(matches.count());
+                64 * 8 * 64
+            })),
+        ));
+        // the candidates are relative to a chunk that holds every block of the pattern at them
+        let chunk_size = (128, 16, 128);
+        let chunk = generate_grid(0, 56, 0, chunk_size);
+        let chunk_pattern = ChunkPattern::new(pattern, chunk_size);
+        record(Measurement::new(
+            "matchers",
+            name,
+            "chunk_pattern",
+            Ok(repeat(|_| {
+                let candidates = candidate_box(0, IVec3::new(64, 8, 64))
+                    .map(|pos| pos - IVec3::new(0, 60, 0) - offset_min);
+                let matches =
+                    candidates.filter(|pos| chunk_pattern.mismatches(&chunk, *pos, 0) == 0);
+                black_box(matches.count());
+                64 * 8 * 64
+            })),
+        ));
+        record(Measurement::new(
+            "matchers",
+            name,
+            "solve_region",
+            Ok(repeat(|round| {
+                let min = IVec3::new(round as i32 * 256, 60, 0);
+                black_box(solve_region(
+                    pattern,
+                    min,
+                    min + IVec3::new(255, 15, 255),
+                    0,
+                ));
+                256 * 16 * 256
+            })),
+        ));
+        for (backend, kernel) in [
+            ("two_pass", FinderKernel::TwoPass),
+            ("fused", FinderKernel::Fused),
+        ] {
+            let result = gpu(&mut runners, kernel, |runners| {
+                let status = Mutex::new(FinderStatus::WaitingForJob);
+                let mut result = Ok(());
+                let measured = repeat(|round| {
+                    let tiles = round as u32 * 4 + 1..round as u32 * 4 + 5;
+                    result = ComputeRunner::run_tiles(runners, &job, tiles, &status).map(|_| ());
+                    status.lock().unwrap().searched_blocks()
+                });
+                result.map(|()| measured)
+            });
+            record(Measurement::new("matchers", name, backend, result));
+        }
+
+        for (name, pos, size) in STANDARD_PATTERNS {
+            let job = FinderJob::from_world(pos, size).with_y_levels(STANDARD_Y_LEVELS.collect());
+            let status = Mutex::new(FinderStatus::WaitingForJob);
+            let blocks = job.blocks_per_tile(&CPU_TILE);
+            let result = end_to_end(pos, blocks, || {
+                search_cpu(CPU_TILE, &job, &TileSource::range(0..1), &status)
+            });
+            record(Measurement::new("end_to_end", name, "cpu", result));
+            for (backend, kernel) in [
+                ("two_pass", FinderKernel::TwoPass),
+                ("fused", FinderKernel::Fused),
+            ] {
+                let result = gpu(&mut runners, kernel, |runners| {
+                    let blocks = job.blocks_per_tile(&runners[0].tile());
+                    end_to_end(pos, blocks, || {
+                        ComputeRunner::run_tiles(runners, &job, 0..1, &status)
+                    })
+                });
+                record(Measurement::new("end_to_end", name, backend, result));
+            }
+        }
+        report
+    }
+
+    pub fn to_json(&self) -> String {
+        let adapters: Vec<String> = self.adapters.iter().map(|name| json_string(name)).collect();
+        let measurements: Vec<String> =
+            self.measurements.iter().map(Measurement::to_json).collect();
+        format!(
+            "{{\"version\":{},\"threads\":{},\"adapters\":[{}],\"measurements\":[{}]}}",
+            json_string(env!("CARGO_PKG_VERSION")),
+            self.threads,
+            adapters.join(","),
+            measurements.join(",")
+        )
+    }
+}
+
+/// Repeats `round` for at least `MIN_TIME`, it's passed the number of the round and returns
+/// how much work it did.
+fn repeat(mut round: impl FnMut(u64) -> u64) -> (u64, Duration) {
+    let start = Instant::now();
+    let mut count = 0;
+    let mut rounds = 0;
+    while rounds == 0 || start.elapsed() < MIN_TIME {
+        count += round(rounds);
+        rounds += 1;
+    }
+    (count, start.elapsed())
+}
+
+/// Positions of a box of `size` that moves along x with every round.
+fn candidate_box(round: u64, size: IVec3) -> impl Iterator<Item = IVec3> {
+    let min = IVec3::new(round as i32 * size.x, 60, 0);
+    (0..size.x).flat_map(move |x| {
+        (0..size.z).flat_map(move |z| (0..size.y).map(move |y| min + IVec3::new(x, y, z)))
+    })
+}
+
+/// Runs `bench` on the GPUs with `kernel`, or returns why there aren't any.
+fn gpu(
+    runners: &mut Result<Vec<ComputeRunner>, String>,
+    kernel: FinderKernel,
+    bench: impl FnOnce(&mut [ComputeRunner]) -> Result<(u64, Duration), String>,
+) -> Result<(u64, Duration), String> {
+    let runners = runners.as_mut().map_err(|err| err.clone())?;
+    for runner in runners.iter_mut() {
+        runner.set_kernel(kernel);
+    }
+    bench(runners)
+}
+
+/// Times a single search of the first tile with `blocks` positions, which has to find the
+/// pattern at `pos`.
+fn end_to_end(
+    pos: IVec3,
+    blocks: u64,
+    search: impl FnOnce() -> Result<Option<PatternMatch>, String>,
+) -> Result<(u64, Duration), String> {
+    let start = Instant::now();
+    let found = search()?;
+    let time = start.elapsed();
+    match found {
+        Some(found) if found.pos == pos => Ok((blocks, time)),
+        Some(found) => Err(format!("found at {} instead of {pos}", found.pos)),
+        None => Err(format!("not found at {pos}")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Mutex, time::Duration};
+
+    use super::{Measurement, Report, CPU_TILE, STANDARD_PATTERNS, STANDARD_Y_LEVELS};
+    use crate::finder::{
+        compute::TileSource,
+        distributed::search_cpu,
+        plugin::{FinderJob, FinderStatus},
+    };
+
+    #[test]
+    fn test_report_json() {
+        let report = Report {
+            adapters: vec!["GPU".to_owned()],
+            threads: 8,
+            measurements: vec![
+                Measurement::new(
+                    "rotations",
+                    "generate_grid",
+                    "cpu",
+                    Ok((1000, Duration::from_millis(500))),
+                ),
+                Measurement::new(
+                    "matchers",
+                    "flat_6x4",
+                    "fused",
+                    Err("couldn't find a GPU".to_owned()),
+                ),
+            ],
+        };
+        assert_eq!(
+            report.to_json(),
+            format!(
+                "{{\"version\":\"{}\",\"threads\":8,\"adapters\":[\"GPU\"],\"measurements\":[\
+                 {{\"group\":\"rotations\",\"name\":\"generate_grid\",\"backend\":\"cpu\",\
+                 \"count\":1000,\"seconds\":0.500000,\"per_second\":2000,\"error\":null}},\
+                 {{\"group\":\"matchers\",\"name\":\"flat_6x4\",\"backend\":\"fused\",\
+                 \"count\":0,\"seconds\":0.000000,\"per_second\":0,\
+                 \"error\":\"couldn't find a GPU\"}}]}}",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+
+    #[test]
+    fn test_standard_patterns() {
+        // the CPU backend finds them in the first tile
+        let (_, pos, size) = STANDARD_PATTERNS[0];
+        let job = FinderJob::from_world(pos, size).with_y_levels(STANDARD_Y_LEVELS.collect());
+        let status = Mutex::new(FinderStatus::WaitingForJob);
+        let found = search_cpu(CPU_TILE, &job, &TileSource::range(0..1), &status).unwrap();
+        assert_eq!(found.map(|found| found.pos), Some(pos));
+        assert!(STANDARD_PATTERNS
+            .iter()
+            .all(|(_, pos, size)| CPU_TILE.positions(*size).cmpgt(pos.as_uvec3()).all()));
+    }
+}
@@ -59,6 +59,11 @@ impl BlockList {
             blocks: load_models(path),
         }
     }
+
+    /// Number of random variants of a block, `None` if it isn't part of the block list.
+    pub fn get_variants(&self, id: &str) -> Option<u8> {
+        self.blocks.get(id).map(|block| block.0.len() as u8)
+    }
 }
 
 fn load_models(path: &str) -> HashMap<String, Block> {
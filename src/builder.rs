@@ -1,6 +1,6 @@
 use bevy::{
     prelude::*,
-    render::{renderer::RenderDevice, settings::WgpuFeatures},
+    render::renderer::RenderAdapterInfo,
     window::{PrimaryWindow, WindowResized},
 };
 use bevy_flycam::FlyCam;
@@ -8,9 +8,11 @@ use bevy_meshem::prelude::*;
 use bevy_mod_raycast::prelude::*;
 
 use crate::{
-    finder::{plugin::FinderJob, util::get_block_rotation},
+    analysis::{format_duration, load_throughput, PatternAnalysis, SearchRadius},
+    finder::util::get_block_rotation,
     game_assets::{BlockId, MinecraftBlockProvider},
     grid::{Grid, GridMesh},
+    import::{load_grid, PatternImport},
     AppState,
 };
 
@@ -21,49 +23,43 @@ pub struct BuilderPlugin;
 #[derive(Component)]
 struct BuilderGui;
 
+#[derive(Component)]
+struct AnalysisLabel;
+
 impl Plugin for BuilderPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.add_systems(
             OnEnter(AppState::Building),
-            (setup_raycast, setup_builder, setup_builder_gui, test_gpu),
+            (setup_raycast, setup_builder, setup_builder_gui),
         )
         .add_systems(
             Update,
-            (handle_raycasts, on_resize, handle_keyboard_inputs)
+            (
+                handle_raycasts,
+                on_resize,
+                handle_keyboard_inputs,
+                update_analysis,
+            )
                 .run_if(in_state(AppState::Building)),
         )
+        .init_resource::<SearchRadius>()
         .add_systems(OnExit(AppState::Building), remove_builder_gui);
     }
 }
 
-fn test_gpu(mut render_device: ResMut<RenderDevice>) {
-    let render_device = render_device.as_mut();
-    assert!(render_device
-        .features()
-        .contains(WgpuFeatures::SHADER_INT64));
-}
-
 fn handle_keyboard_inputs(
     inputs: Res<ButtonInput<KeyCode>>,
     voxel_registry: Res<MinecraftBlockProvider>,
     mut grid: ResMut<Grid>,
-    mut commands: Commands,
-    mut state: ResMut<NextState<AppState>>,
     grid_mesh: Query<&mut Handle<Mesh>, With<GridMesh>>,
     mut meshes: ResMut<Assets<Mesh>>,
 ) {
-    if inputs.just_pressed(KeyCode::Enter) {
-        commands.insert_resource(FinderJob(grid.as_ref().as_u8(voxel_registry.as_ref())));
-        *state.as_mut() = NextState::Pending(AppState::Searching)
-    }
     if inputs.just_pressed(KeyCode::KeyR) {
         let mesh = meshes.get_mut(grid_mesh.single().id()).unwrap();
         let block_meta = voxel_registry.get_meta("grass_block");
         grid.as_mut().reset(mesh, voxel_registry.as_ref());
-        let index = one_d_cords(
-            [GRID_SIZE.0 / 2, GRID_SIZE.1 / 2, GRID_SIZE.2 / 2],
-            GRID_SIZE,
-        );
+        let size = grid.size;
+        let index = one_d_cords([size.0 / 2, size.1 / 2, size.2 / 2], size);
         grid.as_mut().add_block(
             index,
             BlockId(block_meta.id, 0),
@@ -100,16 +96,33 @@ fn setup_builder(
     mut grid: ResMut<Grid>,
     grid_mesh: Query<&mut Handle<Mesh>, With<GridMesh>>,
     mut meshes: ResMut<Assets<Mesh>>,
+    import: Option<Res<PatternImport>>,
     // temporary
 ) {
+    if let Some(import) = import {
+        let mesh = meshes.get_mut(grid_mesh.single().id()).unwrap();
+        match load_grid(&import.0, voxel_registry.as_ref()) {
+            Ok((blocks, report)) => {
+                if report.is_complete() {
+                    info!("{report}");
+                } else {
+                    warn!("{report}");
+                }
+                grid.as_mut().load(blocks, mesh, voxel_registry.as_ref());
+            }
+            Err(err) => error!("couldn't import {}: {err}", import.0.display()),
+        }
+        return;
+    }
     for mesh in grid_mesh.into_iter() {
         info!("{mesh:?}");
         let mesh = meshes.get_mut(mesh.id()).unwrap();
-        for position in 0..32 * 32 * 32 {
+        let size = grid.size;
+        for position in 0..size.0 * size.1 * size.2 {
             //let block_meta = voxel_registry.get_random_block();
             let block_meta = voxel_registry.get_meta("grass_block");
             //let position = rand::random::<usize>() % (GRID_SIZE.0 * GRID_SIZE.1 * GRID_SIZE.2);
-            let (x, y, z) = three_d_cords(position, GRID_SIZE);
+            let (x, y, z) = three_d_cords(position, size);
             grid.as_mut().add_block(
                 position,
                 BlockId(
@@ -170,11 +183,65 @@ fn setup_builder_gui(voxel_registry: Res<MinecraftBlockProvider>, mut commands:
                             Label,
                         ));
                     }
+                    parent.spawn((
+                        TextBundle::from_section("", TextStyle::default()).with_style(Style {
+                            margin: UiRect::all(Val::Px(5.)),
+                            ..default()
+                        }),
+                        AnalysisLabel,
+                    ));
                 });
         });
     info!("gui setup complete")
 }
 
+fn update_analysis(
+    inputs: Res<ButtonInput<KeyCode>>,
+    voxel_registry: Res<MinecraftBlockProvider>,
+    grid: Res<Grid>,
+    adapter_info: Res<RenderAdapterInfo>,
+    mut radius: ResMut<SearchRadius>,
+    mut label: Query<(&mut Text, Ref<AnalysisLabel>)>,
+) {
+    if inputs.just_pressed(KeyCode::Equal) {
+        radius.0 = radius.0.saturating_mul(2);
+    }
+    if inputs.just_pressed(KeyCode::Minus) {
+        radius.0 = (radius.0 / 2).max(CHUNK_SIZE as u64);
+    }
+    let Ok((mut label, marker)) = label.get_single_mut() else {
+        return;
+    };
+    if !(grid.is_changed() || radius.is_changed() || marker.is_added()) {
+        return;
+    }
+    let analysis = PatternAnalysis::new(
+        &grid
+            .to_job(voxel_registry.as_ref())
+            .with_y_levels_from_env(),
+    );
+    let mut formatter = human_format::Formatter::new();
+    formatter.with_decimals(1);
+    let eta = load_throughput(&adapter_info.name)
+        .and_then(|throughput| analysis.eta(radius.0, throughput))
+        .map(format_duration)
+        .unwrap_or_else(|| "unknown until the first search".to_owned());
+    *label.as_mut() = Text::from_section(
+        format!(
+            "{} constrained blocks, {:.1} bits\nunique up to ~{} blocks\nexpected false positives within {} (+/-): {}\nsearch time: {eta}",
+            analysis.constrained_blocks,
+            analysis.bits,
+            formatter.format(analysis.unique_radius()),
+            formatter.format(radius.0 as f64),
+            formatter.format(analysis.expected_false_positives(radius.0)),
+        ),
+        TextStyle {
+            font_size: 20.0,
+            ..default()
+        },
+    );
+}
+
 fn remove_builder_gui(mut commands: Commands, gui: Query<Entity, With<BuilderGui>>) {
     info!("removing gui");
     for entity in gui.iter() {
@@ -203,8 +270,9 @@ fn handle_raycasts(
         if let Some((_, hit)) = source.get_nearest_intersection() {
             let add_block = hit.position() + (hit.normal() / 2.0);
             let remove_block = hit.position() - (hit.normal() / 2.0);
-            let add_block = position_to_chunk_position(add_block, GRID_SIZE);
-            let remove_block = position_to_chunk_position(remove_block, GRID_SIZE);
+            let size = grid.size;
+            let add_block = position_to_chunk_position(add_block, size);
+            let remove_block = position_to_chunk_position(remove_block, size);
             gizmos.cuboid(
                 Transform::from_translation(Vec3::from_array(add_block.1.map(|v| v as f32)))
                     .with_scale(Vec3::splat(1.)),
@@ -222,7 +290,7 @@ fn handle_raycasts(
                 info!("{:?},{:?},{}", chunk, block, valid);
                 if chunk == [0, 0] && valid {
                     grid.as_mut().add_block(
-                        one_d_cords(block, GRID_SIZE),
+                        one_d_cords(block, size),
                         BlockId(block_meta.id, 0),
                         meshes.get_mut(mesh).unwrap(),
                         voxel_registry.as_ref(),
@@ -233,7 +301,7 @@ fn handle_raycasts(
                 let (chunk, block, valid) = remove_block;
                 if chunk == [0, 0] && valid {
                     grid.as_mut().rotate_block(
-                        one_d_cords(block, GRID_SIZE),
+                        one_d_cords(block, size),
                         meshes.get_mut(mesh).unwrap(),
                         voxel_registry.as_ref(),
                     );
@@ -243,7 +311,7 @@ fn handle_raycasts(
                 let (chunk, block, valid) = remove_block;
                 if chunk == [0, 0] && valid {
                     grid.as_mut().remove_block(
-                        one_d_cords(block, GRID_SIZE),
+                        one_d_cords(block, size),
                         meshes.get_mut(mesh).unwrap(),
                         voxel_registry.as_ref(),
                     );
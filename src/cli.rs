@@ -0,0 +1,392 @@
+use std::{fs, path::Path, sync::Mutex, thread, time::Duration};
+
+use bevy::math::IVec3;
+
+use crate::{
+    bench::Report,
+    block_list::BlockList,
+    finder::{
+        cluster::ClusterJob,
+        compute::{self, ComputeRunner},
+        distributed::{self, Backend, Coordinator},
+        plugin::{parse_y_levels, FinderJob, FinderStatus, PatternMatch},
+        queue::JobQueue,
+        region::{solve_region, SparsePattern},
+    },
+    pattern::Pattern,
+    server,
+    world::World,
+};
+
+const USAGE: &str = "usage:
+  minecraft_blockfinder [pattern]
+  minecraft_blockfinder extract <world> <x1> <y1> <z1> <x2> <y2> <z2> <output.pattern>
+  minecraft_blockfinder solve <pattern> <x1> <y1> <z1> <x2> <y2> <z2> [max mismatches]
+  minecraft_blockfinder search <patterns, like a.pattern,b.pattern> [radius] [y levels, like 62,64..=70]
+  minecraft_blockfinder coordinate <patterns> <host:port or unix:path> [radius] [y levels]
+  minecraft_blockfinder clusters <patterns> <patterns@offset from the first, like 15..=25,0,-5..5>... [radius] [y levels]
+  minecraft_blockfinder work <host:port or unix:path> [gpu|cpu]
+  minecraft_blockfinder serve [port] [allowed origins, like http://localhost:3000]
+  minecraft_blockfinder bench [output.json]
+  minecraft_blockfinder adapters";
+
+/// Runs the subcommand given on the command line.
+///
+/// Returns `None` if there is none and the GUI should be started instead.
+pub fn run(args: &[String]) -> Option<i32> {
+    let result = match args.first().map(String::as_str) {
+        Some("extract") => extract(&args[1..]),
+        Some("solve") => solve(&args[1..]),
+        Some("search") => search(&args[1..]),
+        Some("coordinate") => coordinate(&args[1..]),
+        Some("clusters") => clusters(&args[1..]),
+        Some("work") => work(&args[1..]),
+        Some("serve") => serve(&args[1..]),
+        Some("bench") => bench(&args[1..]),
+        Some("adapters") => adapters(),
+        Some("help" | "--help" | "-h") => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        _ => return None,
+    };
+    match result {
+        Ok(()) => Some(0),
+        Err(err) => {
+            eprintln!("{err}");
+            Some(1)
+        }
+    }
+}
+
+fn extract(args: &[String]) -> Result<(), String> {
+    let [world, x1, y1, z1, x2, y2, z2, output] = args else {
+        return Err(USAGE.to_owned());
+    };
+    let min = parse_pos([x1, y1, z1])?;
+    let max = parse_pos([x2, y2, z2])?;
+    crate::ensure_minecraft_assets();
+    let block_list = BlockList::new(".");
+    let (pattern, report) = Pattern::extract(&mut World::open(Path::new(world)), min, max, |id| {
+        block_list.get_variants(id)
+    })
+    .map_err(|err| err.to_string())?;
+    println!("{report}");
+    pattern
+        .save(Path::new(output))
+        .map_err(|err| err.to_string())?;
+    println!(
+        "wrote {} blocks with origin {} to {output}",
+        pattern.blocks.len(),
+        min.min(max)
+    );
+    Ok(())
+}
+
+fn solve(args: &[String]) -> Result<(), String> {
+    let [pattern, x1, y1, z1, x2, y2, z2, rest @ ..] = args else {
+        return Err(USAGE.to_owned());
+    };
+    let max_mismatches = match rest {
+        [] => 0,
+        [value] => value
+            .parse()
+            .map_err(|_| format!("invalid mismatch count {value}"))?,
+        _ => return Err(USAGE.to_owned()),
+    };
+    let pattern = Pattern::load(Path::new(pattern)).map_err(|err| err.to_string())?;
+    let pattern = SparsePattern::from_pattern(&pattern);
+    if pattern.0.is_empty() {
+        return Err("the pattern doesn't contain any blocks with a known rotation".to_owned());
+    }
+    let matches = solve_region(
+        &pattern,
+        parse_pos([x1, y1, z1])?,
+        parse_pos([x2, y2, z2])?,
+        max_mismatches,
+    );
+    for found in matches.iter() {
+        println!(
+            "{} {} {} ({} of {} blocks differ)",
+            found.pos.x,
+            found.pos.y,
+            found.pos.z,
+            found.mismatches,
+            pattern.0.len()
+        );
+    }
+    println!("{} positions found", matches.len());
+    Ok(())
+}
+
+/// Searches the world on the GPU without opening a window.
+fn search(args: &[String]) -> Result<(), String> {
+    let [patterns, rest @ ..] = args else {
+        return Err(USAGE.to_owned());
+    };
+    let (job, radius) = load_job(patterns, rest)?;
+    let mut runners = ComputeRunner::from_env()?;
+    for runner in &runners {
+        println!("searching on {}", runner.adapter_name());
+    }
+    let max_tiles = radius.map(|radius| runners[0].tile().tiles_for_radius(radius, job.size));
+    let status = Mutex::new(FinderStatus::WaitingForJob);
+    let found = with_progress(&status, || {
+        ComputeRunner::run_all(&mut runners, &job, max_tiles, &status)
+    })?;
+    print_found(found, patterns);
+    Ok(())
+}
+
+/// Hands out the tiles of the search to the workers connecting to the address.
+fn coordinate(args: &[String]) -> Result<(), String> {
+    let [patterns, address, rest @ ..] = args else {
+        return Err(USAGE.to_owned());
+    };
+    let (job, radius) = load_job(patterns, rest)?;
+    let coordinator = Coordinator::new(job, radius)?;
+    println!("waiting for workers on {address}");
+    let status = Mutex::new(FinderStatus::WaitingForJob);
+    let found = with_progress(&status, || coordinator.run(address, &status))?;
+    print_found(found, patterns);
+    Ok(())
+}
+
+/// Searches several patches of terrain whose offsets to the first one are only roughly known.
+fn clusters(args: &[String]) -> Result<(), String> {
+    let [first, rest @ ..] = args else {
+        return Err(USAGE.to_owned());
+    };
+    let count = rest.iter().take_while(|arg| arg.contains('@')).count();
+    if count == 0 {
+        return Err(USAGE.to_owned());
+    }
+    let (job, radius) = load_job(first, &rest[count..])?;
+    let mut job = ClusterJob::new(job);
+    for cluster in &rest[..count] {
+        let (patterns, offset) = cluster.split_once('@').unwrap();
+        let (min, max) = parse_offset_range(offset)?;
+        job = job.with_cluster(load_job(patterns, &[])?.0, min, max);
+    }
+    let mut runners = ComputeRunner::from_env()?;
+    for runner in &runners {
+        println!("searching on {}", runner.adapter_name());
+    }
+    let status = Mutex::new(FinderStatus::WaitingForJob);
+    let positions = with_progress(&status, || job.search(&mut runners, radius, &status))?;
+    let Some(positions) = positions else {
+        println!("not found");
+        return Ok(());
+    };
+    let names = std::iter::once(first.as_str()).chain(
+        rest[..count]
+            .iter()
+            .map(|cluster| cluster.split('@').next().unwrap()),
+    );
+    for (pos, name) in positions.iter().zip(names) {
+        println!("{} {} {} {name}", pos.x, pos.y, pos.z);
+    }
+    Ok(())
+}
+
+/// Searches the tiles leased by the coordinator at the address.
+fn work(args: &[String]) -> Result<(), String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let (address, backend) = match args[..] {
+        [address] | [address, "gpu"] => (address, Backend::Gpu),
+        [address, "cpu"] => (address, Backend::Cpu),
+        _ => return Err(USAGE.to_owned()),
+    };
+    distributed::work(address, backend)
+}
+
+/// Runs the JSON API for submitting and monitoring searches, see `server::serve`.
+fn serve(args: &[String]) -> Result<(), String> {
+    let (port, origins) = match args {
+        [] => ("8080", ""),
+        [port] => (port.as_str(), ""),
+        [port, origins] => (port.as_str(), origins.as_str()),
+        _ => return Err(USAGE.to_owned()),
+    };
+    let port = port.parse().map_err(|_| format!("invalid port {port}"))?;
+    let origins = origins
+        .split(',')
+        .filter(|origin| !origin.is_empty())
+        .map(str::to_owned)
+        .collect();
+    println!("serving the finder API on http://localhost:{port}");
+    server::serve(port, origins, JobQueue::new())
+}
+
+/// Loads the comma separated patterns, which are searched together, with the optional radius
+/// and y levels following them on the command line.
+fn load_job(patterns: &str, args: &[String]) -> Result<(FinderJob, Option<u64>), String> {
+    let (radius, y_levels) = match args {
+        [] => (None, Vec::new()),
+        [radius] => (Some(radius), Vec::new()),
+        [radius, y_levels] => (Some(radius), parse_y_levels(y_levels)?),
+        _ => return Err(USAGE.to_owned()),
+    };
+    let radius = radius
+        .map(|value| {
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("invalid radius {value}"))
+        })
+        .transpose()?;
+    let jobs = patterns
+        .split(',')
+        .map(|pattern| {
+            Pattern::load(Path::new(pattern))
+                .and_then(|pattern| pattern.to_job())
+                .map_err(|err| format!("{pattern}: {err}"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let job = match jobs.len() {
+        1 => jobs.into_iter().next().unwrap(),
+        _ => FinderJob::combine(&jobs)?,
+    };
+    Ok((job.with_y_levels(y_levels), radius))
+}
+
+/// Runs the search on another thread, printing the searched blocks until it's done.
+fn with_progress<T: Send>(status: &Mutex<FinderStatus>, search: impl FnOnce() -> T + Send) -> T {
+    thread::scope(|scope| {
+        let search = scope.spawn(search);
+        while !search.is_finished() {
+            thread::sleep(Duration::from_millis(100));
+            if let FinderStatus::Running { blocks, start_time } = *status.lock().unwrap() {
+                eprint!(
+                    "\r{blocks} blocks searched in {}s",
+                    start_time.elapsed().as_secs()
+                );
+            }
+        }
+        eprintln!();
+        search.join().unwrap()
+    })
+}
+
+/// Prints the found position, followed by the pattern found there if there are several.
+fn print_found(found: Option<PatternMatch>, patterns: &str) {
+    let Some(PatternMatch { pattern, pos }) = found else {
+        println!("not found");
+        return;
+    };
+    match patterns.contains(',') {
+        true => println!(
+            "{} {} {} {}",
+            pos.x,
+            pos.y,
+            pos.z,
+            patterns.split(',').nth(pattern).unwrap_or_default()
+        ),
+        false => println!("{} {} {}", pos.x, pos.y, pos.z),
+    }
+}
+
+/// Measures the throughput of the finder backends and writes it as JSON to the file, or prints
+/// it if there is none.
+fn bench(args: &[String]) -> Result<(), String> {
+    let output = match args {
+        [] => None,
+        [output] => Some(output),
+        _ => return Err(USAGE.to_owned()),
+    };
+    let mut formatter = human_format::Formatter::new();
+    formatter.with_decimals(1);
+    let report = Report::run(|measurement| match &measurement.error {
+        Some(err) => eprintln!(
+            "{} {} on {}: {err}",
+            measurement.group, measurement.name, measurement.backend
+        ),
+        None => eprintln!(
+            "{} {} on {}: {}/s",
+            measurement.group,
+            measurement.name,
+            measurement.backend,
+            formatter.format(measurement.per_second())
+        ),
+    });
+    match output {
+        Some(output) => fs::write(output, report.to_json()).map_err(|err| err.to_string()),
+        None => {
+            println!("{}", report.to_json());
+            Ok(())
+        }
+    }
+}
+
+/// Lists the adapters searched on with `FINDER_ADAPTERS=all`.
+fn adapters() -> Result<(), String> {
+    let adapters = compute::adapters();
+    if adapters.is_empty() {
+        return Err("couldn't find a GPU".to_owned());
+    }
+    for adapter in adapters {
+        let info = adapter.get_info();
+        let int64 = if adapter.features().contains(wgpu::Features::SHADER_INT64) {
+            ""
+        } else {
+            ", emulated 64 bit integers"
+        };
+        println!(
+            "{} ({:?}, {:?}{int64})",
+            info.name, info.backend, info.device_type
+        );
+    }
+    Ok(())
+}
+
+/// Parses the smallest and largest offset of a cluster, every axis being a number or a range
+/// like `parse_y_levels` takes.
+fn parse_offset_range(value: &str) -> Result<(IVec3, IVec3), String> {
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<i32>()
+            .map_err(|_| format!("invalid offset {value}"))
+    };
+    let axes = value
+        .split(',')
+        .map(|axis| {
+            if let Some((start, end)) = axis.split_once("..=") {
+                Ok((parse(start)?, parse(end)?))
+            } else if let Some((start, end)) = axis.split_once("..") {
+                Ok((parse(start)?, parse(end)? - 1))
+            } else {
+                parse(axis).map(|offset| (offset, offset))
+            }
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    match axes[..] {
+        [(x1, x2), (y1, y2), (z1, z2)] if x1 <= x2 && y1 <= y2 && z1 <= z2 => {
+            Ok((IVec3::new(x1, y1, z1), IVec3::new(x2, y2, z2)))
+        }
+        _ => Err(format!("invalid offset {value}")),
+    }
+}
+
+fn parse_pos(values: [&String; 3]) -> Result<IVec3, String> {
+    let [x, y, z] = values.map(|v| {
+        v.parse::<i32>()
+            .map_err(|_| format!("invalid coordinate {v}"))
+    });
+    Ok(IVec3::new(x?, y?, z?))
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::math::IVec3;
+
+    use super::parse_offset_range;
+
+    #[test]
+    fn test_parse_offset_range() {
+        assert_eq!(
+            parse_offset_range("15..=25,0,-5..5"),
+            Ok((IVec3::new(15, 0, -5), IVec3::new(25, 0, 4)))
+        );
+        assert!(parse_offset_range("15,0").is_err());
+        assert!(parse_offset_range("5..=1,0,0").is_err());
+    }
+}
@@ -1,13 +1,15 @@
 pub const AIR: crate::game_assets::BlockId = crate::game_assets::BlockId(usize::MAX, 0);
+/// Variant of random-rotation blocks whose rotation isn't known, matches any rotation.
+pub const UNKNOWN_ROTATION: u8 = u8::MAX;
 
 pub const VOXEL_DIMS: [f32; 3] = [1.0; 3];
 pub const VOXEL_CENTER: [f32; 3] = [0.0; 3];
 pub const GRID_SIZE: bevy_meshem::Dimensions = (32, 32, 32);
 pub const CHUNK_SIZE: usize = 2000;
-pub const CHUNK_MARGIN: usize = 40;
 pub const WORLD_HEIGHT: usize = 320;
-
-pub type Chunk = [u8; CHUNK_SIZE * CHUNK_SIZE * WORLD_HEIGHT];
+/// Largest pattern a finder job may contain, tiles overlap by the size of the pattern so that
+/// patterns crossing tile borders are still found.
+pub const MAX_PATTERN_SIZE: bevy_meshem::Dimensions = (128, WORLD_HEIGHT, 128);
 
 const _: () = match CHUNK_SIZE % 16 == 0 {
     true => (),
@@ -0,0 +1,270 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use bevy::log::{info, warn};
+use bevy_meshem::Dimensions;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+use super::{chunk::RotationChunk, util::env_u32};
+use crate::paths::cache_dir;
+
+/// Bumped whenever the generated rotations or their layout change, so older files aren't read.
+const GENERATOR_VERSION: u32 = 3;
+const EXTENSION: &str = "chunk.gz";
+/// words converted at once while reading and writing
+const BLOCK_WORDS: usize = 1 << 14;
+/// Unfinished files older than this were left behind by a search that was interrupted, writing
+/// a chunk only takes seconds.
+const STALE_PARTIAL: Duration = Duration::from_secs(600);
+
+/// Generated chunks kept on disk, so searching the same area again skips generating them.
+///
+/// Files are keyed by the origin and size of the chunk and the generator version, the least
+/// recently used ones are removed once the cache grows past its size limit.
+pub struct ChunkCache {
+    dir: PathBuf,
+    /// bytes the files may take up together
+    max_size: u64,
+}
+
+impl ChunkCache {
+    /// Also removes the unfinished files of interrupted searches.
+    pub fn new(dir: impl Into<PathBuf>, max_size: u64) -> Self {
+        let cache = Self {
+            dir: dir.into(),
+            max_size,
+        };
+        if let Err(err) = cache.remove_partial() {
+            warn!("couldn't clean up {}: {err}", cache.dir.display());
+        }
+        cache
+    }
+
+    /// Only caches chunks with `FINDER_CACHE_SIZE` set to the size in MiB, in `FINDER_CACHE_DIR`
+    /// or the `chunks` directory of the user's cache directory.
+    pub fn from_env() -> Option<Self> {
+        let max_size = env_u32("FINDER_CACHE_SIZE").unwrap_or(0) as u64 * 1024 * 1024;
+        if max_size == 0 {
+            return None;
+        }
+        let dir = std::env::var_os("FINDER_CACHE_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| cache_dir().join("chunks"));
+        Some(Self::new(dir, max_size))
+    }
+
+    fn path(&self, x: i64, y: i64, z: i64, size: Dimensions) -> PathBuf {
+        let (width, height, depth) = size;
+        self.dir.join(format!(
+            "{x}_{y}_{z}_{width}x{height}x{depth}.v{GENERATOR_VERSION}.{EXTENSION}"
+        ))
+    }
+
+    /// Reads the chunk of `size` at `x`, `y`, `z` if it has been cached.
+    pub fn get(&self, x: i64, y: i64, z: i64, size: Dimensions) -> Option<RotationChunk> {
+        let path = self.path(x, y, z, size);
+        let file = File::open(&path).ok()?;
+        match read_chunk(file, size) {
+            Ok(chunk) => {
+                // the modification time orders the files for eviction
+                if let Err(err) = File::options()
+                    .write(true)
+                    .open(&path)
+                    .and_then(|file| file.set_modified(SystemTime::now()))
+                {
+                    warn!("couldn't update {}: {err}", path.display());
+                }
+                info!("read chunk {x}, {y}, {z} from the cache");
+                Some(chunk)
+            }
+            Err(err) => {
+                warn!("removing broken cache file {}: {err}", path.display());
+                let _ = fs::remove_file(&path);
+                None
+            }
+        }
+    }
+
+    pub fn insert(&self, x: i64, y: i64, z: i64, chunk: &RotationChunk) {
+        if let Err(err) = self.write(x, y, z, chunk).and_then(|_| self.evict()) {
+            warn!("couldn't cache chunk {x}, {y}, {z}: {err}");
+        }
+    }
+
+    fn write(&self, x: i64, y: i64, z: i64, chunk: &RotationChunk) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(x, y, z, chunk.size());
+        // renamed once complete, so other searches never read half a file
+        let partial = path.with_extension("partial");
+        let mut encoder =
+            GzEncoder::new(BufWriter::new(File::create(&partial)?), Compression::fast());
+        let mut bytes = Vec::with_capacity(BLOCK_WORDS * 4);
+        for words in chunk.words().chunks(BLOCK_WORDS) {
+            bytes.clear();
+            bytes.extend(words.iter().flat_map(|word| word.to_le_bytes()));
+            encoder.write_all(&bytes)?;
+        }
+        encoder.finish()?.flush()?;
+        fs::rename(partial, path)
+    }
+
+    /// Removes the files of older generators and the least recently used ones above the size
+    /// limit.
+    fn evict(&self) -> io::Result<()> {
+        let current = format!(".v{GENERATOR_VERSION}.{EXTENSION}");
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.ends_with(EXTENSION) {
+                continue;
+            }
+            if !name.ends_with(&current) {
+                remove_file(&entry.path())?;
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            files.push((metadata.modified()?, metadata.len(), entry.path()));
+        }
+        files.sort();
+        let mut size: u64 = files.iter().map(|(_, len, _)| len).sum();
+        for (_, len, path) in files {
+            if size <= self.max_size {
+                break;
+            }
+            remove_file(&path)?;
+            size -= len;
+        }
+        Ok(())
+    }
+
+    /// Removes the files that are still being written after `STALE_PARTIAL`.
+    fn remove_partial(&self) -> io::Result<()> {
+        let entries = match fs::read_dir(&self.dir) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            entries => entries?,
+        };
+        for entry in entries {
+            let entry = entry?;
+            if entry.path().extension().is_some_and(|v| v == "partial")
+                && entry.metadata()?.modified()?.elapsed().unwrap_or_default() > STALE_PARTIAL
+            {
+                remove_file(&entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Removes a file unless another generator thread already has.
+fn remove_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+fn read_chunk(file: File, size: Dimensions) -> io::Result<RotationChunk> {
+    let mut decoder = GzDecoder::new(BufReader::new(file));
+    let mut chunk = RotationChunk::new(size);
+    let mut bytes = vec![0; BLOCK_WORDS * 4];
+    for words in chunk.words_mut().chunks_mut(BLOCK_WORDS) {
+        let bytes = &mut bytes[..words.len() * 4];
+        decoder.read_exact(bytes)?;
+        for (word, bytes) in words.iter_mut().zip(bytes.chunks_exact(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+    }
+    if decoder.read(&mut [0])? != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "file is larger than a chunk",
+        ));
+    }
+    Ok(chunk)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::{self, File},
+        thread,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{ChunkCache, STALE_PARTIAL};
+    use crate::{
+        constants::{CHUNK_SIZE, WORLD_HEIGHT},
+        finder::chunk::RotationChunk,
+    };
+
+    #[test]
+    fn test_chunk_cache() {
+        let dir = std::env::temp_dir().join(format!("chunk_cache_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let size = (CHUNK_SIZE, WORLD_HEIGHT, CHUNK_SIZE);
+        let mut chunk = RotationChunk::new(size);
+        chunk.set(0, 3);
+        chunk.set(chunk.len() - 1, 2);
+        // a compressed chunk of mostly zeroes is far below 64 MiB
+        let cache = ChunkCache::new(&dir, 64 * 1024 * 1024);
+        assert!(cache.get(0, 0, 0, size).is_none());
+        cache.insert(0, 0, 0, &chunk);
+        // a tile of another size or height is another file
+        assert!(cache.get(0, 0, 0, (16, WORLD_HEIGHT, 16)).is_none());
+        assert!(cache.get(0, 64, 0, size).is_none());
+        assert!(cache.get(0, 0, 0, size) == Some(chunk));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_chunk_cache_eviction() {
+        let dir = std::env::temp_dir().join(format!("chunk_eviction_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let cache = ChunkCache::new(&dir, 250);
+        let old_version = dir.join("0_0.v0.chunk.gz");
+        let other = dir.join("notes.txt");
+        fs::write(&old_version, [0; 10]).unwrap();
+        fs::write(&other, [0; 1000]).unwrap();
+        let size = (16, 1, 16);
+        for (x, z) in [(0, 0), (1996, 0), (0, 1996)] {
+            fs::write(cache.path(x, 0, z, size), [0; 100]).unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+        cache.evict().unwrap();
+        assert!(!old_version.exists());
+        assert!(other.exists());
+        // the least recently used file is removed to get below the limit
+        assert!(!cache.path(0, 0, 0, size).exists());
+        assert!(cache.path(1996, 0, 0, size).exists());
+        assert!(cache.path(0, 0, 1996, size).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_partial_cleanup() {
+        let dir = std::env::temp_dir().join(format!("chunk_partial_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let stale = dir.join("0_0_0_16x1x16.v3.chunk.partial");
+        let writing = dir.join("16_0_0_16x1x16.v3.chunk.partial");
+        fs::write(&stale, [0; 10]).unwrap();
+        fs::write(&writing, [0; 10]).unwrap();
+        File::options()
+            .write(true)
+            .open(&stale)
+            .unwrap()
+            .set_modified(SystemTime::now() - STALE_PARTIAL * 2)
+            .unwrap();
+        ChunkCache::new(&dir, 250);
+        assert!(!stale.exists());
+        // another search may still be writing this one
+        assert!(writing.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,120 +1,304 @@
 use std::{
-    fmt::Debug,
-    sync::{Arc, Condvar, Mutex},
-    thread::{self, JoinHandle},
+    ops::{Bound, Range, RangeBounds},
+    thread,
     time::Instant,
 };
 
-use crate::{constants::*, finder::util::spiral};
-
-use bevy::log::info;
-
-use bevy_meshem::prelude::three_d_cords;
+use bevy::{
+    log::{debug, warn},
+    math::UVec3,
+};
 
-use super::util::get_block_rotation;
+use bevy_meshem::{
+    prelude::{one_d_cords, three_d_cords},
+    Dimensions,
+};
 
-pub fn create_box<T: Default + Debug, const N: usize>() -> Box<[T; N]> {
-    let mut array = Vec::with_capacity(N);
-    for _ in 0..N {
-        array.push(T::default());
-    }
-    array.into_boxed_slice().try_into().unwrap()
-}
+use super::{
+    cache::ChunkCache,
+    prefetch::{PrefetchMetrics, Prefetcher},
+    tile::TileSettings,
+    util::{block_rotations_fn, env_u32},
+};
 
-pub fn generate_grid(start_x: i64, start_z: i64) -> Box<Chunk> {
+pub fn generate_grid(start_x: i64, start_y: i64, start_z: i64, size: Dimensions) -> RotationChunk {
     let start = Instant::now();
-    let mut data = create_box::<u8, { CHUNK_SIZE * CHUNK_SIZE * WORLD_HEIGHT }>();
-    for i in 0..(CHUNK_SIZE * CHUNK_SIZE * WORLD_HEIGHT) {
-        let (x, y, z) = three_d_cords(i, (CHUNK_SIZE, WORLD_HEIGHT, CHUNK_SIZE));
-        data[i] = get_block_rotation(x as i64 + start_x, y as i64, z as i64 + start_z);
+    let mut chunk = RotationChunk::new(size);
+    let get_block_rotations = block_rotations_fn();
+    // every word holds the rotations of 16 blocks along x
+    for (i, word) in chunk.words.iter_mut().enumerate() {
+        let (x, y, z) = three_d_cords(i * 16, size);
+        *word = get_block_rotations(x as i64 + start_x, y as i64 + start_y, z as i64 + start_z);
     }
-    info!(
+    debug!(
         "took {} seconds to generate chunk",
         start.elapsed().as_secs_f32()
     );
-    data
+    chunk
 }
 
-pub struct CPUChunkProvider {
-    thread: JoinHandle<()>,
-    buffer: Arc<(Mutex<Option<((i64, i64), Box<Chunk>)>>, Condvar)>,
+/// Rotations of the blocks of a chunk, packed 16 to a `u32` along x like the tiles of
+/// `chunk.wgsl`.
+///
+/// Blocks are indexed like `three_d_cords`, x first, then z and y.
+#[derive(Clone, PartialEq, Eq)]
+pub struct RotationChunk {
+    size: Dimensions,
+    words: Box<[u32]>,
 }
-impl CPUChunkProvider {
-    pub fn new(max_chunks: i32) -> Self {
-        if max_chunks < 1 {
-            panic!("max_chunks cannot be smaller than 1")
+
+impl RotationChunk {
+    /// A chunk whose blocks all have rotation 0, `size.0` has to be a multiple of 16 like the
+    /// size of a tile.
+    pub fn new(size: Dimensions) -> Self {
+        assert!(
+            size.0.is_multiple_of(16),
+            "rows of {} blocks can't be packed",
+            size.0
+        );
+        Self {
+            size,
+            words: vec![0; size.0 * size.1 * size.2 / 16].into_boxed_slice(),
+        }
+    }
+
+    /// The blocks of a tile at `heights`, see `generate_grid`.
+    pub fn tile_size(tile: &TileSettings, heights: &Range<u32>) -> Dimensions {
+        (tile.size as usize, heights.len(), tile.size as usize)
+    }
+
+    pub fn size(&self) -> Dimensions {
+        self.size
+    }
+
+    /// number of blocks
+    pub fn len(&self) -> usize {
+        self.words.len() * 16
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> u8 {
+        self.slice(..).get(index)
+    }
+
+    pub fn get_pos(&self, x: usize, y: usize, z: usize) -> u8 {
+        self.get(one_d_cords([x, y, z], self.size))
+    }
+
+    pub fn set(&mut self, index: usize, rotation: u8) {
+        let shift = index % 16 * 2;
+        let word = &mut self.words[index / 16];
+        *word = *word & !(3 << shift) | (rotation as u32 & 3) << shift;
+    }
+
+    /// The packed rotations, laid out like the tile buffer of the GPU.
+    pub fn words(&self) -> &[u32] {
+        &self.words
+    }
+
+    pub fn words_mut(&mut self) -> &mut [u32] {
+        &mut self.words
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        self.slice(..).iter()
+    }
+
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> RotationSlice<'_> {
+        RotationSlice {
+            words: &self.words,
+            start: 0,
+            len: self.len(),
+        }
+        .slice(range)
+    }
+
+    /// The blocks at height `y` and `z` along x.
+    pub fn row(&self, y: usize, z: usize) -> RotationSlice<'_> {
+        let start = one_d_cords([0, y, z], self.size);
+        self.slice(start..start + self.size.0)
+    }
+}
+
+/// Consecutive blocks of a `RotationChunk`.
+#[derive(Clone, Copy)]
+pub struct RotationSlice<'a> {
+    words: &'a [u32],
+    /// index of the first block in the chunk
+    start: usize,
+    len: usize,
+}
+
+impl<'a> RotationSlice<'a> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> u8 {
+        assert!(index < self.len(), "index {index} out of range");
+        let index = self.start + index;
+        (self.words[index / 16] >> (index % 16 * 2)) as u8 & 3
+    }
+
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> RotationSlice<'a> {
+        let start = match range.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => end + 1,
+            Bound::Excluded(end) => *end,
+            Bound::Unbounded => self.len(),
+        };
+        assert!(
+            start <= end && end <= self.len(),
+            "range {start}..{end} out of range"
+        );
+        RotationSlice {
+            words: self.words,
+            start: self.start + start,
+            len: end - start,
         }
-        let buffer = Arc::new((Mutex::new(None), Condvar::new()));
-        let buffer_copy = buffer.clone();
-        let thread = thread::spawn(move || {
-            for (start_x, start_z) in (0..max_chunks).map(spiral) {
-                let value = (
-                    (
-                        start_x as i64 * (CHUNK_SIZE - CHUNK_MARGIN) as i64,
-                        start_z as i64 * (CHUNK_SIZE - CHUNK_MARGIN) as i64,
-                    ),
-                    /*
-                    match Self::get_cache(start_x, start_z) {
-                        Some(chunk) => chunk,
-                        None => {
-                            let chunk = generate_grid(
-                                start_x * CHUNK_SIZE as i64,
-                                start_z * CHUNK_SIZE as i64,
-                                );
-                                Self::write_cache(start_x, start_z, chunk.as_ref());
-                                chunk
-                                }
-                            },
-                    */
-                    generate_grid(
-                        start_x as i64 * CHUNK_SIZE as i64,
-                        start_z as i64 * CHUNK_SIZE as i64,
-                    ),
-                );
-                let mut lock = buffer_copy.0.lock().unwrap();
-                if lock.is_some() {
-                    lock = buffer_copy.1.wait(lock).unwrap();
-                }
-                *lock = Some(value);
-                info!("sent chunk");
-                buffer_copy.1.notify_one();
-            }
-        });
-        buffer.1.notify_one();
-        CPUChunkProvider { thread, buffer }
-    }
-    pub fn try_next(&mut self) -> Option<((i64, i64), Box<Chunk>)> {
-        let mut lock = self.buffer.0.lock().unwrap();
-        if !lock.is_some() {
-            None
-        } else {
-            let mut value = None;
-            std::mem::swap(&mut value, &mut lock);
-            self.buffer.1.notify_one();
-            value
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u8> + 'a {
+        let slice = *self;
+        (0..self.len()).map(move |index| slice.get(index))
+    }
+}
+
+/// Generates the tiles of the spiral on background threads, a few of them ahead of the search.
+pub struct CPUChunkProvider(Prefetcher<(u32, RotationChunk)>);
+
+impl CPUChunkProvider {
+    /// Generates the blocks at `heights` of the tiles in `tiles`, overlapping so that patterns
+    /// of `pattern_size` crossing tile borders are still contained in one of them.
+    ///
+    /// `FINDER_PREFETCH_CHUNKS` tiles are kept ready, 4 by default, generated on
+    /// `FINDER_GENERATOR_THREADS` threads, one per core by default. Every thread needs a tile
+    /// of its own, so there are never more of them than tiles kept ready.
+    pub fn new(
+        tile: TileSettings,
+        tiles: Range<u32>,
+        heights: Range<u32>,
+        pattern_size: UVec3,
+    ) -> Self {
+        let slots = env_u32("FINDER_PREFETCH_CHUNKS").unwrap_or(4).max(1);
+        let threads = match env_u32("FINDER_GENERATOR_THREADS") {
+            Some(threads) => threads,
+            None => thread::available_parallelism()
+                .map_or(1, |threads| threads.get() as u32)
+                .min(slots),
+        };
+        Self::with_capacity(tile, tiles, heights, pattern_size, slots, threads)
+    }
+
+    /// Keeps up to `slots` tiles ready or being generated, on at most `threads` threads.
+    pub fn with_capacity(
+        tile: TileSettings,
+        tiles: Range<u32>,
+        heights: Range<u32>,
+        pattern_size: UVec3,
+        slots: u32,
+        threads: u32,
+    ) -> Self {
+        if threads > slots {
+            warn!(
+                "only {slots} of the {threads} generator threads are used, one per prefetched tile"
+            );
         }
+        let cache = ChunkCache::from_env();
+        let size = RotationChunk::tile_size(&tile, &heights);
+        let start_y = heights.start as i64;
+        Self(Prefetcher::new(
+            tiles.len() as u32,
+            slots.max(1),
+            threads,
+            move |index| {
+                let tile_index = tiles.start + index;
+                let origin = tile.origin(tile_index, pattern_size);
+                let (start_x, start_z) = (origin.x as i64, origin.y as i64);
+                let cached = cache
+                    .as_ref()
+                    .and_then(|cache| cache.get(start_x, start_y, start_z, size));
+                let chunk = match cached {
+                    Some(chunk) => chunk,
+                    None => {
+                        let chunk = generate_grid(start_x, start_y, start_z, size);
+                        if let Some(cache) = &cache {
+                            cache.insert(start_x, start_y, start_z, &chunk);
+                        }
+                        chunk
+                    }
+                };
+                (tile_index, chunk)
+            },
+        ))
+    }
+
+    pub fn try_next(&mut self) -> Option<(u32, RotationChunk)> {
+        self.0.try_next()
     }
+
     pub fn is_finished(&self) -> bool {
-        self.thread.is_finished()
+        self.0.is_finished()
+    }
+
+    /// Chunks waiting to be searched and how fast they are generated.
+    pub fn metrics(&self) -> PrefetchMetrics {
+        self.0.metrics()
     }
 }
 
 impl Iterator for CPUChunkProvider {
-    type Item = ((i64, i64), Box<Chunk>);
+    type Item = (u32, RotationChunk);
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.thread.is_finished() {
-            None
-        } else {
-            let mut lock = self.buffer.0.lock().unwrap();
-            if !lock.is_some() {
-                info!("waiting on chunk...");
-                lock = self.buffer.1.wait(lock).unwrap();
-            }
-            let mut value = None;
-            std::mem::swap(&mut value, &mut lock);
-            self.buffer.1.notify_one();
-            value
+        if let Some(chunk) = self.0.try_next() {
+            return Some(chunk);
+        }
+        debug!("waiting on chunk...");
+        self.0.next()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy_meshem::prelude::one_d_cords;
+
+    use super::RotationChunk;
+    use crate::constants::{CHUNK_SIZE, WORLD_HEIGHT};
+
+    #[test]
+    fn test_rotation_chunk() {
+        let size = (CHUNK_SIZE, WORLD_HEIGHT, CHUNK_SIZE);
+        let mut chunk = RotationChunk::new(size);
+        assert_eq!(chunk.len(), CHUNK_SIZE * WORLD_HEIGHT * CHUNK_SIZE);
+        let index = |x| one_d_cords([x, 5, 7], size);
+        for x in 0..40 {
+            chunk.set(index(x), x as u8 % 4);
         }
+        chunk.set(index(CHUNK_SIZE - 1), 3);
+        // packed like pack16xU2, the first block in the lowest bits
+        assert_eq!(chunk.words()[index(0) / 16], 0xE4E4E4E4);
+        let row = chunk.row(5, 7);
+        assert_eq!(row.len(), CHUNK_SIZE);
+        assert!(row.slice(..40).iter().eq((0..40).map(|x| x as u8 % 4)));
+        assert!(row
+            .slice(40..CHUNK_SIZE - 1)
+            .iter()
+            .all(|rotation| rotation == 0));
+        assert_eq!(row.slice(30..).get(CHUNK_SIZE - 31), 3);
+        chunk.set(index(3), 1);
+        assert_eq!(chunk.get_pos(3, 5, 7), 1);
+        assert_eq!(chunk.get_pos(3, 5, 8), 0);
     }
 }
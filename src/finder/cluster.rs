@@ -0,0 +1,273 @@
+use std::sync::{Arc, Mutex};
+
+use bevy::math::IVec3;
+
+use crate::analysis::PatternAnalysis;
+
+use super::{
+    compute::{ComputeRunner, TileSource},
+    plugin::{FinderJob, FinderStatus, PatternMatch},
+    region::{solve_region, SparsePattern},
+    tile::TileSettings,
+};
+
+/// Separate patches of terrain whose offsets to each other are only roughly known, like two
+/// spots "about 20 blocks apart" on a screenshot.
+///
+/// The most selective cluster is searched on the GPU, the others are looked for on the CPU
+/// around every match of it.
+#[derive(Clone, Debug)]
+pub struct ClusterJob {
+    /// the found position is reported for the first one, which also has the y levels
+    pub clusters: Vec<FinderJob>,
+    /// smallest and largest offset (inclusive) of every cluster's found position from the one of
+    /// the first cluster, starting with zero for the first one
+    pub offsets: Vec<(IVec3, IVec3)>,
+}
+
+impl ClusterJob {
+    pub fn new(first: FinderJob) -> Self {
+        Self {
+            clusters: vec![first],
+            offsets: vec![(IVec3::ZERO, IVec3::ZERO)],
+        }
+    }
+
+    /// Adds a cluster that is between `min` and `max` blocks away from the first one.
+    pub fn with_cluster(mut self, job: FinderJob, min: IVec3, max: IVec3) -> Self {
+        self.clusters.push(job);
+        self.offsets.push((min.min(max), min.max(max)));
+        self
+    }
+
+    /// The cluster whose matches are the least likely to be a coincidence.
+    pub fn anchor(&self) -> usize {
+        let bits: Vec<f64> = self
+            .clusters
+            .iter()
+            .map(|job| PatternAnalysis::new(job).bits)
+            .collect();
+        (0..bits.len())
+            .max_by(|a, b| bits[*a].total_cmp(&bits[*b]))
+            .unwrap_or(0)
+    }
+
+    /// The anchor, searched at every height its offset allows for the y levels of the first
+    /// cluster.
+    fn anchor_job(&self, anchor: usize) -> FinderJob {
+        let job = self.clusters[anchor].clone();
+        let y_levels = &self.clusters[0].y_levels;
+        if y_levels.is_empty() {
+            return job.with_y_levels(Vec::new());
+        }
+        let (min, max) = self.offsets[anchor];
+        let mut anchor_levels: Vec<i32> = y_levels
+            .iter()
+            .flat_map(|y| y + min.y..=y + max.y)
+            .collect();
+        anchor_levels.sort_unstable();
+        anchor_levels.dedup();
+        job.with_y_levels(anchor_levels)
+    }
+
+    /// The position of every cluster if all of them are around `pos`, where the anchor is.
+    ///
+    /// `patterns` holds the patterns of every cluster, see `SparsePattern::from_job`.
+    pub fn verify(
+        &self,
+        anchor: usize,
+        patterns: &[Vec<SparsePattern>],
+        pos: IVec3,
+    ) -> Option<Vec<IVec3>> {
+        let (anchor_min, anchor_max) = self.offsets[anchor];
+        let y_levels = &self.clusters[0].y_levels;
+        find(&patterns[0], pos - anchor_max, pos - anchor_min)
+            .into_iter()
+            .filter(|first| y_levels.is_empty() || y_levels.contains(&first.y))
+            .find_map(|first| {
+                self.offsets
+                    .iter()
+                    .zip(patterns)
+                    .enumerate()
+                    .map(|(index, ((min, max), patterns))| match index == anchor {
+                        true => Some(pos),
+                        false => find(patterns, first + *min, first + *max).first().copied(),
+                    })
+                    .collect()
+            })
+    }
+
+    /// The first position in a tile of `job`, the anchor job, where every cluster is found,
+    /// checked on the CPU for the tiles with more matches of the anchor than the GPU keeps.
+    fn recheck(
+        &self,
+        anchor: usize,
+        patterns: &[Vec<SparsePattern>],
+        job: &FinderJob,
+        tile: TileSettings,
+        tile_index: u32,
+    ) -> Option<PatternMatch> {
+        let layers = job.layers(tile.height);
+        let (first, last) = (*layers.first()?, *layers.last()?);
+        let origin = tile.origin(tile_index, job.size);
+        let positions = tile.positions(job.size).as_ivec3();
+        // found positions are reported for the cell at -offset, like on the GPU
+        let min = IVec3::new(origin.x, first as i32, origin.y) - job.offset;
+        let max = IVec3::new(origin.x, last as i32, origin.y) + positions.with_y(0)
+            - IVec3::new(1, 0, 1)
+            - job.offset;
+        patterns[anchor]
+            .iter()
+            .enumerate()
+            .find_map(|(index, pattern)| {
+                solve_region(pattern, min, max, 0)
+                    .into_iter()
+                    .map(|found| found.pos)
+                    .filter(|pos| {
+                        layers
+                            .binary_search(&((pos.y + job.offset.y) as u32))
+                            .is_ok()
+                    })
+                    .find(|pos| self.verify(anchor, patterns, *pos).is_some())
+                    .map(|pos| PatternMatch {
+                        pattern: index,
+                        pos,
+                    })
+            })
+    }
+
+    /// Searches the tiles of the anchor within `radius` blocks of 0,0, or all of them, and
+    /// returns the position of every cluster.
+    pub fn search(
+        &self,
+        runners: &mut [ComputeRunner],
+        radius: Option<u64>,
+        status: &Mutex<FinderStatus>,
+    ) -> Result<Option<Vec<IVec3>>, String> {
+        let anchor = self.anchor();
+        let job = self.anchor_job(anchor);
+        let tile = runners.first().ok_or("couldn't find a GPU")?.tile();
+        let max_tiles = radius.map(|radius| tile.tiles_for_radius(radius, job.size));
+        let patterns: Vec<Vec<SparsePattern>> =
+            self.clusters.iter().map(SparsePattern::from_job).collect();
+        let source = TileSource::new(max_tiles)
+            .with_filter({
+                let clusters = self.clone();
+                let patterns = patterns.clone();
+                move |found| clusters.verify(anchor, &patterns, found.pos).is_some()
+            })
+            .with_recheck({
+                let clusters = self.clone();
+                let patterns = patterns.clone();
+                let job = job.clone();
+                move |tile_index| clusters.recheck(anchor, &patterns, &job, tile, tile_index)
+            });
+        let found = ComputeRunner::run_source(runners, &job, Arc::new(source), status)?;
+        let positions = found.and_then(|found| self.verify(anchor, &patterns, found.pos));
+        if let (Some(positions), FinderStatus::Finished { pos, .. }) =
+            (&positions, &mut *status.lock().unwrap())
+        {
+            *pos = positions[0];
+        }
+        Ok(positions)
+    }
+}
+
+/// Positions between `min` and `max` where any of the patterns is, ordered like
+/// `solve_region`. The windows around a match are small, so they are checked on the calling
+/// thread, which reads the tiles of the GPU.
+fn find(patterns: &[SparsePattern], min: IVec3, max: IVec3) -> Vec<IVec3> {
+    let (min, max) = (min.min(max), min.max(max));
+    let mut found = Vec::new();
+    for pattern in patterns {
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    let pos = IVec3::new(x, y, z);
+                    if pattern.mismatches(pos, 0) == 0 {
+                        found.push(pos);
+                    }
+                }
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::math::{IVec3, UVec3};
+
+    use super::ClusterJob;
+    use crate::finder::{
+        plugin::{FinderJob, PatternMatch},
+        region::SparsePattern,
+        tile::TileSettings,
+    };
+
+    #[test]
+    fn test_clusters() {
+        let first = IVec3::new(-1203, 70, 455);
+        let second = first + IVec3::new(18, 0, -3);
+        let job = ClusterJob::new(
+            FinderJob::from_world(first, UVec3::new(4, 1, 3)).with_y_levels(vec![70]),
+        )
+        .with_cluster(
+            FinderJob::from_world(second, UVec3::new(6, 1, 4)),
+            IVec3::new(15, -1, -5),
+            IVec3::new(20, 1, 0),
+        );
+        // the second cluster has more blocks
+        assert_eq!(job.anchor(), 1);
+        assert_eq!(job.anchor_job(1).y_levels, vec![69, 70, 71]);
+        let patterns: Vec<Vec<SparsePattern>> =
+            job.clusters.iter().map(SparsePattern::from_job).collect();
+        assert_eq!(job.verify(1, &patterns, second), Some(vec![first, second]));
+        assert_eq!(job.verify(0, &patterns, first), Some(vec![first, second]));
+        // the anchor is trusted, but the first cluster is too far from this one
+        assert_eq!(
+            job.verify(1, &patterns, second + IVec3::new(10, 0, 0)),
+            None
+        );
+        // the second cluster is further away than allowed
+        let job = ClusterJob::new(job.clusters[0].clone()).with_cluster(
+            job.clusters[1].clone(),
+            IVec3::new(-5, 0, -5),
+            IVec3::new(5, 0, 5),
+        );
+        assert_eq!(job.verify(1, &patterns, second), None);
+    }
+
+    #[test]
+    fn test_cluster_recheck() {
+        let first = IVec3::new(20, 70, 30);
+        let second = first + IVec3::new(18, 0, -3);
+        let job = ClusterJob::new(
+            FinderJob::from_world(first, UVec3::new(4, 1, 3)).with_y_levels(vec![70]),
+        )
+        .with_cluster(
+            FinderJob::from_world(second, UVec3::new(6, 1, 4)),
+            IVec3::new(15, -1, -5),
+            IVec3::new(20, 1, 0),
+        );
+        let patterns: Vec<Vec<SparsePattern>> =
+            job.clusters.iter().map(SparsePattern::from_job).collect();
+        let tile = TileSettings {
+            size: 64,
+            height: 128,
+            workgroup_size: 64,
+        };
+        // the first tile of the spiral starts at 0,0
+        let anchor_job = job.anchor_job(1);
+        assert_eq!(
+            job.recheck(1, &patterns, &anchor_job, tile, 0),
+            Some(PatternMatch {
+                pattern: 0,
+                pos: second
+            })
+        );
+        assert_eq!(job.recheck(1, &patterns, &anchor_job, tile, 1), None);
+        let other_levels = anchor_job.with_y_levels(vec![100]);
+        assert_eq!(job.recheck(1, &patterns, &other_levels, tile, 0), None);
+    }
+}
@@ -0,0 +1,1513 @@
+use std::{
+    collections::VecDeque,
+    env,
+    mem::size_of,
+    num::NonZeroU64,
+    ops::Range,
+    slice,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    log::{debug, info, warn},
+    math::{IVec2, IVec3, IVec4, UVec3},
+    render::{
+        render_resource::{
+            binding_types::{
+                storage_buffer_read_only_sized, storage_buffer_sized, uniform_buffer_sized,
+            },
+            *,
+        },
+        renderer::{RenderDevice, RenderQueue, WgpuWrapper},
+    },
+    tasks::block_on,
+};
+
+use super::{
+    plugin::{FinderJob, FinderKernel, FinderStatus, PatternMatch},
+    region::SparsePattern,
+    tile::TileSettings,
+};
+
+/// Tiles that are searched at the same time, each one has its own slot.
+const TILES_IN_FLIGHT: usize = 4;
+/// Matches kept per tile, so the ones a `TileSource` filters out don't hide the others.
+const MAX_CANDIDATES: u32 = 64;
+/// Match count, padded to the alignment of the matches, followed by the matches.
+const RESULT_SIZE: u64 = 16 * (MAX_CANDIDATES as u64 + 1);
+/// GPU timestamps at the start and the end of a tile, read back after its matches.
+const TIMESTAMPS_SIZE: u64 = 2 * wgpu::QUERY_SIZE as u64;
+
+/// Buffers and bind groups of the finder shaders.
+pub struct FinderBuffers {
+    pub chunk_layout: BindGroupLayout,
+    pub find_layout: BindGroupLayout,
+    pub fused_layout: BindGroupLayout,
+    /// created once the job is known, like the fused bind groups of the slots
+    find_bind_group: Option<BindGroup>,
+    slots: Vec<TileSlot>,
+    chunk: Buffer,
+    chunk_size: UniformBuffer<UVec3>,
+    /// matches in the tile, positions with the index of their pattern
+    result_gpu: Buffer,
+    /// copied over `result_gpu` after every tile
+    empty_result: Buffer,
+    /// rotations of every pattern, packed 4 per `u32`
+    grid: StorageBuffer<Vec<u32>>,
+    pattern_size: UniformBuffer<UVec3>,
+    pattern_count: UniformBuffer<u32>,
+    /// constrained blocks of the patterns for the fused kernel, see `sparse_patterns`
+    sparse_pattern: StorageBuffer<Vec<IVec4>>,
+    /// heights in the tile at which the pattern is checked, see `FinderJob::layers`
+    layers: StorageBuffer<Vec<u32>>,
+    layer_count: u32,
+    /// heights the chunk kernel generates, the ones covered by the pattern at any of `layers`
+    chunk_layers: StorageBuffer<Vec<u32>>,
+    chunk_layer_count: u32,
+    /// subtracted from found positions, see `FinderJob`
+    offset: IVec3,
+    pub tile: TileSettings,
+    pub kernel: FinderKernel,
+    /// 64 bit integers are emulated in the shaders without it
+    pub shader_int64: bool,
+    /// nanoseconds per timestamp tick, if the device supports timestamp queries
+    timestamp_period: Option<f32>,
+}
+
+/// Buffers of a tile that is being searched.
+struct TileSlot {
+    /// the tile using the slot, until its result has been read
+    tile_index: Option<u32>,
+    /// two-pass or fused, the kernel that checks the tile
+    kernel: FinderKernel,
+    position: UniformBuffer<IVec2>,
+    chunk_bind_group: BindGroup,
+    /// created once the job is known, the pattern buffer is sized to it
+    fused_bind_group: Option<BindGroup>,
+    /// matches, followed by the timestamps when benchmarking
+    result: Buffer,
+    /// queries timing the tile and the buffer they are resolved into
+    timestamps: Option<(wgpu::QuerySet, Buffer)>,
+}
+
+pub struct FinderPipelines<'a> {
+    pub chunk: &'a ComputePipeline,
+    pub find: &'a ComputePipeline,
+    pub fused: &'a ComputePipeline,
+}
+
+impl FinderBuffers {
+    pub fn new(
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+        tile: TileSettings,
+        kernel: FinderKernel,
+    ) -> Self {
+        // LAYOUTS
+        let chunk_layout = chunk_layout(render_device, &tile);
+        let find_layout = find_layout(render_device, &tile);
+        let fused_layout = fused_layout(render_device, &tile);
+        // BUFFERS
+        let mut chunk_size = UniformBuffer::from(UVec3::new(tile.size, tile.height, tile.size));
+        let empty_result = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: None,
+            contents: &[0; RESULT_SIZE as usize],
+            usage: BufferUsages::COPY_SRC,
+        });
+        let result_gpu = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("finder matches"),
+            contents: &[0; RESULT_SIZE as usize],
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+        });
+        let timestamp_period = render_device
+            .features()
+            .contains(WgpuFeatures::TIMESTAMP_QUERY)
+            .then(|| render_queue.get_timestamp_period());
+        if kernel == FinderKernel::Benchmark && timestamp_period.is_none() {
+            warn!("the adapter doesn't support timestamp queries, the kernels can't be timed");
+        }
+        let mut layers = StorageBuffer::from((0..tile.height).collect::<Vec<_>>());
+        let mut chunk_layers = StorageBuffer::from((0..tile.height).collect::<Vec<_>>());
+        let chunk = render_device.create_buffer(&BufferDescriptor {
+            label: Some("finder tile"),
+            size: tile.buffer_size(),
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        chunk_size.write_buffer(render_device, render_queue);
+        layers.write_buffer(render_device, render_queue);
+        chunk_layers.write_buffer(render_device, render_queue);
+        let slots = (0..TILES_IN_FLIGHT)
+            .map(|_| {
+                let mut position = UniformBuffer::from(IVec2::ZERO);
+                position.write_buffer(render_device, render_queue);
+                let chunk_bind_group = render_device.create_bind_group(
+                    None,
+                    &chunk_layout,
+                    &BindGroupEntries::sequential((
+                        &position,
+                        chunk.as_entire_binding(),
+                        &chunk_layers,
+                    )),
+                );
+                let result = render_device.create_buffer(&BufferDescriptor {
+                    label: None,
+                    size: RESULT_SIZE + TIMESTAMPS_SIZE,
+                    usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                let timestamps = timestamp_period.map(|_| {
+                    let queries =
+                        render_device
+                            .wgpu_device()
+                            .create_query_set(&wgpu::QuerySetDescriptor {
+                                label: None,
+                                ty: wgpu::QueryType::Timestamp,
+                                count: 2,
+                            });
+                    let resolved = render_device.create_buffer(&BufferDescriptor {
+                        label: None,
+                        size: TIMESTAMPS_SIZE,
+                        usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+                        mapped_at_creation: false,
+                    });
+                    (queries, resolved)
+                });
+                TileSlot {
+                    tile_index: None,
+                    kernel: FinderKernel::TwoPass,
+                    position,
+                    chunk_bind_group,
+                    fused_bind_group: None,
+                    result,
+                    timestamps,
+                }
+            })
+            .collect();
+        Self {
+            chunk_layout,
+            find_layout,
+            fused_layout,
+            find_bind_group: None,
+            slots,
+            chunk,
+            chunk_size,
+            result_gpu,
+            empty_result,
+            grid: StorageBuffer::default(),
+            pattern_size: UniformBuffer::from(UVec3::ONE),
+            pattern_count: UniformBuffer::from(1),
+            sparse_pattern: StorageBuffer::default(),
+            layer_count: tile.height,
+            layers,
+            chunk_layer_count: tile.height,
+            chunk_layers,
+            offset: IVec3::ZERO,
+            tile,
+            kernel,
+            shader_int64: render_device
+                .features()
+                .contains(WgpuFeatures::SHADER_INT64),
+            timestamp_period,
+        }
+    }
+
+    pub fn shader_defs(&self) -> Vec<ShaderDefVal> {
+        let mut shader_defs = vec![
+            ShaderDefVal::UInt("WORKGROUP_SIZE".into(), self.tile.workgroup_size),
+            ShaderDefVal::UInt("TILE_SIZE".into(), self.tile.size),
+            ShaderDefVal::UInt("TILE_HEIGHT".into(), self.tile.height),
+            ShaderDefVal::UInt("MAX_CANDIDATES".into(), MAX_CANDIDATES),
+        ];
+        if self.shader_int64 {
+            shader_defs.push("SHADER_INT64".into());
+        }
+        shader_defs
+    }
+
+    pub fn set_job(
+        &mut self,
+        job: &FinderJob,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+    ) -> Result<(), String> {
+        if !self.tile.fits(job.size) {
+            return Err(format!(
+                "pattern of size {} doesn't fit into {:?}",
+                job.size, self.tile
+            ));
+        }
+        let sparse_pattern = sparse_patterns(job)?;
+        let mut layers = job.layers(self.tile.height);
+        if layers.is_empty() {
+            return Err(format!(
+                "none of the y levels {:?} fit a pattern of size {}",
+                job.y_levels, job.size
+            ));
+        }
+        let mut chunk_layers: Vec<u32> = layers
+            .iter()
+            .flat_map(|layer| *layer..layer + job.size.y)
+            .collect();
+        chunk_layers.sort_unstable();
+        chunk_layers.dedup();
+        self.layer_count = layers.len() as u32;
+        self.chunk_layer_count = chunk_layers.len() as u32;
+        // the buffers keep their maximum size, so the bind groups stay valid
+        layers.resize(self.tile.height as usize, 0);
+        chunk_layers.resize(self.tile.height as usize, 0);
+        self.layers.set(layers);
+        self.layers.write_buffer(render_device, render_queue);
+        self.chunk_layers.set(chunk_layers);
+        self.chunk_layers.write_buffer(render_device, render_queue);
+        let mut grid = job.rotations.clone();
+        grid.resize(grid.len().next_multiple_of(4).max(4), 0);
+        let grid = grid
+            .chunks_exact(4)
+            .map(|v| u32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+            .collect();
+        self.grid.set(grid);
+        self.grid.write_buffer(render_device, render_queue);
+        self.pattern_size.set(job.size);
+        self.pattern_size.write_buffer(render_device, render_queue);
+        self.pattern_count.set(job.pattern_count() as u32);
+        self.pattern_count.write_buffer(render_device, render_queue);
+        // a new buffer, the fused kernel checks every block of it
+        self.sparse_pattern = StorageBuffer::from(sparse_pattern);
+        self.sparse_pattern
+            .write_buffer(render_device, render_queue);
+        self.offset = job.offset;
+        self.find_bind_group = Some(render_device.create_bind_group(
+            None,
+            &self.find_layout,
+            &BindGroupEntries::sequential((
+                &self.chunk_size,
+                self.chunk.as_entire_binding(),
+                &self.grid,
+                self.result_gpu.as_entire_binding(),
+                &self.pattern_size,
+                &self.layers,
+                &self.pattern_count,
+            )),
+        ));
+        for slot in self.slots.iter_mut() {
+            slot.fused_bind_group = Some(render_device.create_bind_group(
+                None,
+                &self.fused_layout,
+                &BindGroupEntries::sequential((
+                    &slot.position,
+                    &self.sparse_pattern,
+                    self.result_gpu.as_entire_binding(),
+                    &self.pattern_size,
+                    &self.layers,
+                )),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn blocks_per_tile(&self) -> u64 {
+        let positions = self.searched_positions();
+        positions.x as u64 * positions.y as u64 * positions.z as u64
+    }
+
+    /// Pattern origins checked per tile along each axis, only at the layers of the job.
+    fn searched_positions(&self) -> UVec3 {
+        let positions = self.tile.positions(*self.pattern_size.get());
+        UVec3::new(positions.x, self.layer_count, positions.z)
+    }
+
+    fn slot(&self, tile_index: u32) -> &TileSlot {
+        self.slots
+            .iter()
+            .find(|slot| slot.tile_index == Some(tile_index))
+            .expect("tile should have a slot")
+    }
+
+    /// Gives the tile a free slot, the tiles of a shared queue aren't consecutive.
+    fn set_position(
+        &mut self,
+        tile_index: u32,
+        kernel: FinderKernel,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+    ) {
+        let pos = self.tile.origin(tile_index, *self.pattern_size.get());
+        let slot = self
+            .slots
+            .iter_mut()
+            .find(|slot| slot.tile_index.is_none())
+            .expect("every slot is in use");
+        slot.tile_index = Some(tile_index);
+        slot.kernel = kernel;
+        slot.position.set(pos);
+        slot.position.write_buffer(render_device, render_queue);
+    }
+
+    /// Records the passes that search the tile and move its result into the slot.
+    pub fn encode_tile(
+        &self,
+        encoder: &mut CommandEncoder,
+        pipelines: &FinderPipelines,
+        tile_index: u32,
+    ) {
+        let tile = self.tile;
+        let slot = self.slot(tile_index);
+        // neighbouring tiles cover the positions closer to the border
+        let positions = self.searched_positions();
+        // when benchmarking, the first pass of the tile takes the first timestamp and the last
+        // one the second
+        let timestamps = slot
+            .timestamps
+            .as_ref()
+            .filter(|_| self.kernel == FinderKernel::Benchmark);
+        let pass_descriptor = |first: bool, last: bool| ComputePassDescriptor {
+            label: None,
+            timestamp_writes: timestamps.map(|(queries, _)| wgpu::ComputePassTimestampWrites {
+                query_set: queries,
+                beginning_of_pass_write_index: first.then_some(0),
+                end_of_pass_write_index: last.then_some(1),
+            }),
+        };
+        if slot.kernel == FinderKernel::Fused {
+            let mut pass = encoder.begin_compute_pass(&pass_descriptor(true, true));
+            pass.set_bind_group(
+                0,
+                slot.fused_bind_group
+                    .as_ref()
+                    .expect("bind group should be created with the job"),
+                &[],
+            );
+            pass.set_pipeline(pipelines.fused);
+            let workgroups = tile.workgroups(positions);
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, workgroups.z);
+        } else {
+            let mut pass = encoder.begin_compute_pass(&pass_descriptor(true, false));
+            pass.set_bind_group(0, &slot.chunk_bind_group, &[]);
+            pass.set_pipeline(pipelines.chunk);
+            // every thread packs 16 blocks along x
+            let workgroups = tile.workgroups(UVec3::new(
+                tile.size / 16,
+                self.chunk_layer_count,
+                tile.size,
+            ));
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, workgroups.z);
+            drop(pass);
+            let mut pass = encoder.begin_compute_pass(&pass_descriptor(false, true));
+            pass.set_bind_group(
+                0,
+                self.find_bind_group
+                    .as_ref()
+                    .expect("bind group should be created with the job"),
+                &[],
+            );
+            pass.set_pipeline(pipelines.find);
+            let workgroups = tile.workgroups(positions);
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, workgroups.z);
+        }
+        encoder.copy_buffer_to_buffer(&self.result_gpu, 0, &slot.result, 0, RESULT_SIZE);
+        encoder.copy_buffer_to_buffer(&self.empty_result, 0, &self.result_gpu, 0, RESULT_SIZE);
+        if let Some((queries, resolved)) = timestamps {
+            encoder.resolve_query_set(queries, 0..2, resolved, 0);
+            encoder.copy_buffer_to_buffer(resolved, 0, &slot.result, RESULT_SIZE, TIMESTAMPS_SIZE);
+        }
+    }
+
+    /// Reads and unmaps the result of a tile and frees its slot.
+    fn read_result(&mut self, tile_index: u32) -> TileResult {
+        let slot = self.slot(tile_index);
+        let timed = self.kernel == FinderKernel::Benchmark && slot.timestamps.is_some();
+        let kernel = slot.kernel;
+        let buffer = &slot.result;
+        let buffer_view = buffer.slice(..).get_mapped_range();
+        let data: Vec<u32> = buffer_view
+            .chunks_exact(4)
+            .map(|v| u32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+            .collect();
+        drop(buffer_view);
+        buffer.unmap();
+        for slot in self.slots.iter_mut() {
+            if slot.tile_index == Some(tile_index) {
+                slot.tile_index = None;
+            }
+        }
+        let count = data[0];
+        let origin = self.tile.origin(tile_index, *self.pattern_size.get());
+        let (matches, timestamps) = data.split_at(RESULT_SIZE as usize / 4);
+        let matches = matches[4..]
+            .chunks_exact(4)
+            .take(count as usize)
+            .map(|v| PatternMatch {
+                pattern: v[3] as usize,
+                pos: IVec3::new(v[0] as i32 + origin.x, v[1] as i32, v[2] as i32 + origin.y)
+                    - self.offset,
+            })
+            .collect();
+        let gpu_time = self.timestamp_period.filter(|_| timed).map(|period| {
+            let ticks = |v: &[u32]| v[0] as u64 | (v[1] as u64) << 32;
+            let elapsed = ticks(&timestamps[2..]).saturating_sub(ticks(timestamps));
+            Duration::from_nanos((elapsed as f64 * period as f64) as u64)
+        });
+        TileResult {
+            matches,
+            count,
+            kernel,
+            gpu_time,
+        }
+    }
+}
+
+/// What `FinderBuffers::read_result` read back for a tile.
+struct TileResult {
+    matches: Vec<PatternMatch>,
+    /// matches the GPU found, only the first `MAX_CANDIDATES` of them are kept
+    count: u32,
+    kernel: FinderKernel,
+    /// how long the GPU took for the tile, only measured when benchmarking
+    gpu_time: Option<Duration>,
+}
+
+/// Hands out the tiles of the spiral to every queue searching the same job, so several GPUs
+/// can work on one search.
+#[derive(Default)]
+pub struct TileSource {
+    /// index of the next tile in the spiral
+    next_tile: AtomicU32,
+    /// stop after this many tiles
+    end: Option<u32>,
+    /// the first tile of the spiral with a match, and what was found in it
+    found: Mutex<Option<(u32, PatternMatch)>>,
+    cancelled: AtomicBool,
+    /// decides which matches count, see `with_filter`
+    filter: Option<MatchFilter>,
+    /// searches the tiles with too many matches, see `with_recheck`
+    recheck: Option<TileRecheck>,
+}
+
+type MatchFilter = Box<dyn Fn(&PatternMatch) -> bool + Send + Sync>;
+type TileRecheck = Box<dyn Fn(u32) -> Option<PatternMatch> + Send + Sync>;
+
+impl TileSource {
+    pub fn new(end: Option<u32>) -> Self {
+        Self {
+            end,
+            ..Default::default()
+        }
+    }
+
+    /// Only hands out the tiles in `tiles`, like the ones leased by a coordinator.
+    pub fn range(tiles: Range<u32>) -> Self {
+        Self {
+            next_tile: AtomicU32::new(tiles.start),
+            end: Some(tiles.end),
+            ..Default::default()
+        }
+    }
+
+    /// Only keeps the matches `filter` accepts, like the ones whose surroundings are checked
+    /// on the CPU. Up to `MAX_CANDIDATES` matches per tile are passed to it, when it rejects
+    /// all of them and the tile has more the tile is searched again with `with_recheck`.
+    pub fn with_filter(
+        mut self,
+        filter: impl Fn(&PatternMatch) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Searches the tiles whose kept matches were all rejected by the filter with `recheck`,
+    /// which gets the index of the tile and returns the first match the filter would accept.
+    /// Without it the search fails on such a tile.
+    pub fn with_recheck(
+        mut self,
+        recheck: impl Fn(u32) -> Option<PatternMatch> + Send + Sync + 'static,
+    ) -> Self {
+        self.recheck = Some(Box::new(recheck));
+        self
+    }
+
+    fn accepts(&self, found: &PatternMatch) -> bool {
+        self.filter.as_ref().is_none_or(|filter| filter(found))
+    }
+
+    /// The first of the `count` matches of a tile that is accepted, from the `matches` the GPU
+    /// kept. The matches it dropped were never checked, so they can't be skipped.
+    fn accept(
+        &self,
+        tile_index: u32,
+        matches: Vec<PatternMatch>,
+        count: u32,
+    ) -> Result<Option<PatternMatch>, String> {
+        let kept = matches.len();
+        match matches.into_iter().find(|found| self.accepts(found)) {
+            None if count as usize > kept => match &self.recheck {
+                Some(recheck) => {
+                    debug!("{count} matches in tile {tile_index}, searching it on the CPU");
+                    Ok(recheck(tile_index))
+                }
+                None => Err(format!(
+                    "{count} matches in tile {tile_index}, the filter rejected the {kept} that \
+                     were kept"
+                )),
+            },
+            accepted => Ok(accepted),
+        }
+    }
+
+    /// Tiles that haven't been handed out yet.
+    pub fn tiles(&self) -> Range<u32> {
+        self.next_tile.load(Ordering::Relaxed)..self.end.unwrap_or(u32::MAX)
+    }
+
+    /// Stops handing out tiles, the ones in flight are still read.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Takes the next tile, unless the spiral ended, an earlier tile has a match or the search
+    /// was cancelled.
+    fn take(&self) -> Option<u32> {
+        if self.is_cancelled() {
+            return None;
+        }
+        let tile_index = self.next_tile.fetch_add(1, Ordering::Relaxed);
+        let end = match *self.found.lock().unwrap() {
+            Some((found_tile, _)) => found_tile,
+            None => self.end.unwrap_or(u32::MAX),
+        };
+        (tile_index < end).then_some(tile_index)
+    }
+
+    /// Keeps the match if no earlier tile of the spiral has one.
+    fn report(&self, tile_index: u32, pattern_match: PatternMatch) {
+        let mut found = self.found.lock().unwrap();
+        if !matches!(*found, Some((found_tile, _)) if found_tile < tile_index) {
+            *found = Some((tile_index, pattern_match));
+        }
+    }
+
+    /// The match in the first tile of the spiral, once every queue has read its tiles.
+    pub fn found(&self) -> Option<PatternMatch> {
+        self.found.lock().unwrap().map(|(_, found)| found)
+    }
+}
+
+/// Tiles of the spiral that are being searched, in order.
+#[derive(Default)]
+pub struct TileQueue {
+    source: Arc<TileSource>,
+    /// tiles encoded but not submitted yet
+    queued: Vec<u32>,
+    /// submitted tiles with a flag that is set once their result is mapped
+    in_flight: VecDeque<(u32, Arc<AtomicBool>)>,
+    /// batches queued so far, the benchmark alternates the kernel between them
+    batches: u32,
+    kernel_times: KernelTimes,
+}
+
+impl TileQueue {
+    pub fn new(end: Option<u32>) -> Self {
+        Self::shared(Arc::new(TileSource::new(end)))
+    }
+
+    /// Takes its tiles from `source`, together with the other queues using it.
+    pub fn shared(source: Arc<TileSource>) -> Self {
+        Self {
+            source,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty() && self.in_flight.is_empty()
+    }
+
+    pub fn queued(&self) -> &[u32] {
+        &self.queued
+    }
+
+    /// Queues the next tiles of the spiral until every slot is in use.
+    ///
+    /// When benchmarking, a batch of tiles is only queued once the last one has been read, so
+    /// the kernels are timed on their own instead of next to the other one.
+    pub fn fill(
+        &mut self,
+        buffers: &mut FinderBuffers,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+    ) {
+        let kernel = match buffers.kernel {
+            FinderKernel::Benchmark if !self.is_empty() => return,
+            FinderKernel::Benchmark => {
+                self.batches += 1;
+                FinderKernel::Benchmark.for_batch(self.batches)
+            }
+            kernel => kernel,
+        };
+        while self.in_flight.len() + self.queued.len() < TILES_IN_FLIGHT {
+            let Some(tile_index) = self.source.take() else {
+                break;
+            };
+            buffers.set_position(tile_index, kernel, render_device, render_queue);
+            self.queued.push(tile_index);
+        }
+    }
+
+    /// Starts mapping the results of the queued tiles, call once they have been submitted.
+    pub fn submitted(&mut self, buffers: &FinderBuffers) {
+        for tile_index in self.queued.drain(..) {
+            let mapped = Arc::new(AtomicBool::new(false));
+            let flag = mapped.clone();
+            buffers.slot(tile_index).result.slice(..).map_async(
+                bevy::render::render_resource::MapMode::Read,
+                move |v| match v {
+                    Ok(_) => flag.store(true, Ordering::Release),
+                    Err(err) => panic!("couldn't read data from gpu: {err:?}"),
+                },
+            );
+            self.in_flight.push_back((tile_index, mapped));
+        }
+    }
+
+    /// Processes the tiles whose results have been mapped without waiting for the others,
+    /// calling `on_tile` for every one of them, and returns the match.
+    ///
+    /// Fails and cancels the source when a tile has more matches than were kept and the filter
+    /// rejected all of those.
+    pub fn read(
+        &mut self,
+        buffers: &mut FinderBuffers,
+        mut on_tile: impl FnMut(u32),
+    ) -> Result<Option<PatternMatch>, String> {
+        // tiles are read in order, so the first match is also the first one of this queue
+        while let Some((tile_index, mapped)) = self.in_flight.front() {
+            if !mapped.load(Ordering::Acquire) {
+                break;
+            }
+            let tile_index = *tile_index;
+            self.in_flight.pop_front();
+            on_tile(tile_index);
+            let TileResult {
+                matches,
+                count,
+                kernel,
+                gpu_time,
+            } = buffers.read_result(tile_index);
+            if let Some(gpu_time) = gpu_time {
+                self.kernel_times.record(kernel, gpu_time);
+                if self.kernel_times.tiles() % 16 == 15 {
+                    self.kernel_times.log();
+                }
+            }
+            let accepted = self
+                .source
+                .accept(tile_index, matches, count)
+                .inspect_err(|_| self.source.cancel())?;
+            if let Some(found) = accepted {
+                self.source.report(tile_index, found);
+                return Ok(Some(found));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// GPU time of the tiles from their timestamp queries, split by the kernel that checked them.
+#[derive(Default)]
+struct KernelTimes {
+    two_pass: (Duration, u32),
+    fused: (Duration, u32),
+}
+
+impl KernelTimes {
+    fn record(&mut self, kernel: FinderKernel, gpu_time: Duration) {
+        let (time, tiles) = match kernel {
+            FinderKernel::Fused => &mut self.fused,
+            _ => &mut self.two_pass,
+        };
+        *time += gpu_time;
+        *tiles += 1;
+    }
+
+    fn tiles(&self) -> u32 {
+        self.two_pass.1 + self.fused.1
+    }
+
+    fn log(&self) {
+        let average = |(time, tiles): (Duration, u32)| time.as_secs_f64() * 1000.0 / tiles as f64;
+        let (two_pass, fused) = (average(self.two_pass), average(self.fused));
+        info!(
+            "two-pass: {two_pass:.2} ms/tile, fused: {fused:.2} ms/tile, speedup {:.2}x",
+            two_pass / fused
+        );
+    }
+}
+
+/// Runs the finder shaders on a device of its own, so tiles are searched as fast as the GPU
+/// allows instead of once per frame.
+pub struct ComputeRunner {
+    render_device: RenderDevice,
+    render_queue: RenderQueue,
+    adapter_name: String,
+    buffers: FinderBuffers,
+    chunk_pipeline: ComputePipeline,
+    find_pipeline: ComputePipeline,
+    fused_pipeline: ComputePipeline,
+}
+
+impl ComputeRunner {
+    /// Opens the preferred adapter, `WGPU_BACKEND` and `WGPU_POWER_PREF` work like in bevy.
+    pub fn new() -> Result<Self, String> {
+        let mut runners = Self::open(vec![preferred_adapter()?], None)?;
+        Ok(runners.remove(0))
+    }
+
+    /// Opens every adapter to search on all of them with `run_all`.
+    pub fn all() -> Result<Vec<Self>, String> {
+        Self::open(adapters(), None)
+    }
+
+    /// Opens every adapter with `FINDER_ADAPTERS=all`, and the preferred one otherwise.
+    pub fn from_env() -> Result<Vec<Self>, String> {
+        Self::open(adapters_from_env()?, None)
+    }
+
+    /// Like `from_env`, with tiles of `size` blocks, so the tiles of the spiral are in the same
+    /// place as on the other machines searching the job.
+    pub fn from_env_with_tile_size(size: u32) -> Result<Vec<Self>, String> {
+        Self::open(adapters_from_env()?, Some(size))
+    }
+
+    /// Uses the largest tile size every adapter supports unless `size` is given.
+    fn open(adapters: Vec<wgpu::Adapter>, size: Option<u32>) -> Result<Vec<Self>, String> {
+        let devices = adapters
+            .into_iter()
+            .map(|adapter| {
+                request_device(&adapter).map(|(device, queue)| (device, queue, adapter.get_info()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        // the tiles of the spiral have to be in the same place on every device
+        let max_size = devices
+            .iter()
+            .map(|(device, _, _)| TileSettings::from_limits(&device.limits()).size)
+            .min()
+            .ok_or("couldn't find a GPU")?;
+        let size = match size {
+            Some(size) if size > max_size => {
+                return Err(format!(
+                    "tiles of size {size} don't fit, the adapters support up to {max_size}"
+                ))
+            }
+            Some(size) => size,
+            None => max_size,
+        };
+        Ok(devices
+            .into_iter()
+            .map(|(render_device, render_queue, info)| {
+                let tile = TileSettings {
+                    size,
+                    ..TileSettings::from_limits(&render_device.limits())
+                };
+                if !render_device
+                    .features()
+                    .contains(WgpuFeatures::SHADER_INT64)
+                {
+                    warn!(
+                        "{} doesn't support 64 bit integers in shaders, the finder will emulate them",
+                        info.name
+                    );
+                }
+                Self::with_device(render_device, render_queue, info.name, tile)
+            })
+            .collect())
+    }
+
+    fn with_device(
+        render_device: RenderDevice,
+        render_queue: RenderQueue,
+        adapter_name: String,
+        tile: TileSettings,
+    ) -> Self {
+        let buffers = FinderBuffers::new(
+            &render_device,
+            &render_queue,
+            tile,
+            FinderKernel::from_env(),
+        );
+        let shader_defs = buffers.shader_defs();
+        let pipeline = |layout: &BindGroupLayout, source: &str| {
+            create_pipeline(&render_device, layout, source, &shader_defs)
+        };
+        Self {
+            chunk_pipeline: pipeline(
+                &buffers.chunk_layout,
+                include_str!("../../shaders/chunk.wgsl"),
+            ),
+            find_pipeline: pipeline(
+                &buffers.find_layout,
+                include_str!("../../shaders/find.wgsl"),
+            ),
+            fused_pipeline: pipeline(
+                &buffers.fused_layout,
+                include_str!("../../shaders/fused.wgsl"),
+            ),
+            render_device,
+            render_queue,
+            adapter_name,
+            buffers,
+        }
+    }
+
+    pub fn adapter_name(&self) -> &str {
+        &self.adapter_name
+    }
+
+    pub fn tile(&self) -> TileSettings {
+        self.buffers.tile
+    }
+
+    /// Checks the following tiles with `kernel` instead of the one from `FINDER_KERNEL`.
+    pub fn set_kernel(&mut self, kernel: FinderKernel) {
+        self.buffers.kernel = kernel;
+    }
+
+    /// Searches the first `max_tiles` tiles of the spiral, or all of them, and keeps `status`
+    /// up to date.
+    pub fn run(
+        &mut self,
+        job: &FinderJob,
+        max_tiles: Option<u32>,
+        status: &Mutex<FinderStatus>,
+    ) -> Result<Option<PatternMatch>, String> {
+        Self::run_all(slice::from_mut(self), job, max_tiles, status)
+    }
+
+    /// Like `run`, with a thread per runner taking the next tile of the spiral whenever it has
+    /// a free slot, so faster GPUs search more of them.
+    pub fn run_all(
+        runners: &mut [Self],
+        job: &FinderJob,
+        max_tiles: Option<u32>,
+        status: &Mutex<FinderStatus>,
+    ) -> Result<Option<PatternMatch>, String> {
+        Self::run_tiles(runners, job, 0..max_tiles.unwrap_or(u32::MAX), status)
+    }
+
+    /// Like `run_all`, only searching the tiles in `tiles`.
+    pub fn run_tiles(
+        runners: &mut [Self],
+        job: &FinderJob,
+        tiles: Range<u32>,
+        status: &Mutex<FinderStatus>,
+    ) -> Result<Option<PatternMatch>, String> {
+        Self::run_source(runners, job, Arc::new(TileSource::range(tiles)), status)
+    }
+
+    /// Like `run_all`, with the tiles taken from `source`, which can cancel the search.
+    pub fn run_source(
+        runners: &mut [Self],
+        job: &FinderJob,
+        source: Arc<TileSource>,
+        status: &Mutex<FinderStatus>,
+    ) -> Result<Option<PatternMatch>, String> {
+        let start_time = Instant::now();
+        *status.lock().unwrap() = FinderStatus::Running {
+            blocks: 0,
+            start_time,
+        };
+        thread::scope(|scope| {
+            let workers: Vec<_> = runners
+                .iter_mut()
+                .map(|runner| {
+                    let source = source.clone();
+                    let tiles = TileQueue::shared(source.clone());
+                    scope.spawn(move || {
+                        let result = runner.search(job, tiles, status);
+                        // the other workers would keep searching, and the scope waits for them
+                        if result.is_err() {
+                            source.cancel();
+                        }
+                        result
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .try_for_each(|worker| worker.join().unwrap())
+        })?;
+        let found = source.found();
+        if let Some(found) = found {
+            let mut status = status.lock().unwrap();
+            *status = FinderStatus::Finished {
+                searched_blocks: status.searched_blocks(),
+                pos: found.pos,
+                pattern: found.pattern,
+                time: start_time.elapsed(),
+            };
+        }
+        Ok(found)
+    }
+
+    /// Searches the tiles of `tiles` until the spiral ends or a match is found, and reads the
+    /// tiles left in flight so the buffers can be reused.
+    fn search(
+        &mut self,
+        job: &FinderJob,
+        mut tiles: TileQueue,
+        status: &Mutex<FinderStatus>,
+    ) -> Result<(), String> {
+        self.buffers
+            .set_job(job, &self.render_device, &self.render_queue)?;
+        let pipelines = FinderPipelines {
+            chunk: &self.chunk_pipeline,
+            find: &self.find_pipeline,
+            fused: &self.fused_pipeline,
+        };
+        let blocks_per_tile = self.buffers.blocks_per_tile();
+        let mut submissions = VecDeque::new();
+        let mut failed = None;
+        loop {
+            tiles.fill(&mut self.buffers, &self.render_device, &self.render_queue);
+            if !tiles.queued().is_empty() {
+                let mut encoder = self
+                    .render_device
+                    .create_command_encoder(&CommandEncoderDescriptor::default());
+                for tile_index in tiles.queued() {
+                    self.buffers
+                        .encode_tile(&mut encoder, &pipelines, *tile_index);
+                }
+                submissions.push_back(self.render_queue.submit([encoder.finish()]));
+                tiles.submitted(&self.buffers);
+            }
+            if tiles.is_empty() {
+                return failed.map_or(Ok(()), Err);
+            }
+            // the later submissions keep the GPU busy while the oldest one is read
+            let maintain = match submissions.pop_front() {
+                Some(submission) => Maintain::WaitForSubmissionIndex(submission),
+                None => Maintain::Wait,
+            };
+            self.render_device.poll(maintain).panic_on_timeout();
+            let read = tiles.read(&mut self.buffers, |_| {
+                if let FinderStatus::Running { blocks, .. } = &mut *status.lock().unwrap() {
+                    *blocks += blocks_per_tile;
+                }
+            });
+            // the tiles still in flight are read before failing
+            if let Err(err) = read {
+                failed.get_or_insert(err);
+            }
+        }
+    }
+}
+
+fn instance() -> wgpu::Instance {
+    wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::all()),
+        ..Default::default()
+    })
+}
+
+fn preferred_adapter() -> Result<wgpu::Adapter, String> {
+    block_on(
+        instance().request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::util::power_preference_from_env()
+                .unwrap_or(wgpu::PowerPreference::HighPerformance),
+            ..Default::default()
+        }),
+    )
+    .ok_or("couldn't find a GPU".to_owned())
+}
+
+/// Every adapter with `FINDER_ADAPTERS=all`, and the preferred one otherwise.
+fn adapters_from_env() -> Result<Vec<wgpu::Adapter>, String> {
+    match env::var("FINDER_ADAPTERS").as_deref() {
+        Ok("all") => Ok(adapters()),
+        Ok(value) if value != "preferred" => {
+            warn!("unknown FINDER_ADAPTERS {value}, using the preferred adapter");
+            Ok(vec![preferred_adapter()?])
+        }
+        _ => Ok(vec![preferred_adapter()?]),
+    }
+}
+
+/// The adapters `ComputeRunner::all` searches on, software ones only if there is no GPU.
+pub fn adapters() -> Vec<wgpu::Adapter> {
+    // with every backend, a GPU would show up once per backend
+    let backends = wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::PRIMARY);
+    let adapters = instance().enumerate_adapters(backends);
+    if adapters
+        .iter()
+        .all(|adapter| adapter.get_info().device_type == wgpu::DeviceType::Cpu)
+    {
+        return adapters;
+    }
+    adapters
+        .into_iter()
+        .filter(|adapter| adapter.get_info().device_type != wgpu::DeviceType::Cpu)
+        .collect()
+}
+
+fn request_device(adapter: &wgpu::Adapter) -> Result<(RenderDevice, RenderQueue), String> {
+    let (device, queue) = block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            label: None,
+            // timestamps are only written when benchmarking the kernels
+            required_features: adapter.features()
+                & (wgpu::Features::SHADER_INT64 | wgpu::Features::TIMESTAMP_QUERY),
+            required_limits: adapter.limits(),
+        },
+        None,
+    ))
+    .map_err(|err| err.to_string())?;
+    Ok((
+        RenderDevice::from(device),
+        RenderQueue(Arc::new(WgpuWrapper::new(queue))),
+    ))
+}
+
+fn create_pipeline(
+    render_device: &RenderDevice,
+    layout: &BindGroupLayout,
+    source: &str,
+    shader_defs: &[ShaderDefVal],
+) -> ComputePipeline {
+    let module = render_device.create_shader_module(ShaderModuleDescriptor {
+        label: None,
+        source: ShaderSource::Wgsl(preprocess(source, shader_defs).into()),
+    });
+    let layout = render_device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: None,
+        bind_group_layouts: &[layout.value()],
+        push_constant_ranges: &[],
+    });
+    render_device.create_compute_pipeline(&RawComputePipelineDescriptor {
+        label: None,
+        layout: Some(&layout),
+        module: &module,
+        entry_point: "main",
+        compilation_options: Default::default(),
+    })
+}
+
+/// Shader modules the other shaders `#import`, by their `#define_import_path`.
+const SHADER_IMPORTS: [(&str, &str); 1] = [(
+    "minecraft_blockfinder::rotation",
+    include_str!("../../shaders/rotation.wgsl"),
+)];
+
+/// Pastes the `#import`ed modules in, handles `#ifdef`/`#ifndef`/`#else`/`#endif` and
+/// substitutes the `#{NAME}` shader defs like bevy does for shaders loaded as assets.
+fn preprocess(source: &str, shader_defs: &[ShaderDefVal]) -> String {
+    let source = resolve_imports(source);
+    let defined = |name: &str| {
+        shader_defs.iter().any(|def| match def {
+            ShaderDefVal::Bool(def, value) => def == name && *value,
+            ShaderDefVal::Int(def, _) | ShaderDefVal::UInt(def, _) => def == name,
+        })
+    };
+    // whether the lines of each enclosing block are kept
+    let mut kept = vec![true];
+    let mut lines = Vec::new();
+    for line in source.lines() {
+        let directive = line.trim();
+        let outer = kept[kept.len() - 1];
+        if let Some(name) = directive.strip_prefix("#ifdef ") {
+            kept.push(outer && defined(name.trim()));
+        } else if let Some(name) = directive.strip_prefix("#ifndef ") {
+            kept.push(outer && !defined(name.trim()));
+        } else if directive == "#else" {
+            let branch = kept.pop().unwrap();
+            kept.push(kept[kept.len() - 1] && !branch);
+        } else if directive == "#endif" {
+            kept.pop();
+        } else if outer {
+            lines.push(line);
+        }
+    }
+    shader_defs.iter().fold(lines.join("\n"), |source, def| {
+        let (name, value) = match def {
+            ShaderDefVal::Bool(name, value) => (name, value.to_string()),
+            ShaderDefVal::Int(name, value) => (name, value.to_string()),
+            ShaderDefVal::UInt(name, value) => (name, format!("{value}u")),
+        };
+        source.replace(&format!("#{{{name}}}"), &value)
+    })
+}
+
+/// The imports of the shaders only name items of the module, so its whole source is pasted in.
+fn resolve_imports(source: &str) -> String {
+    source
+        .lines()
+        .flat_map(|line| match line.trim().strip_prefix("#import ") {
+            Some(path) => {
+                let (_, module) = SHADER_IMPORTS
+                    .iter()
+                    .find(|(name, _)| path.starts_with(name))
+                    .unwrap_or_else(|| panic!("unknown shader import {path}"));
+                module
+                    .lines()
+                    .filter(|line| !line.starts_with("#define_import_path"))
+                    .collect()
+            }
+            None => vec![line],
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn chunk_layout(render_device: &RenderDevice, tile: &TileSettings) -> BindGroupLayout {
+    render_device.create_bind_group_layout(
+        None,
+        &BindGroupLayoutEntries::sequential(
+            ShaderStages::COMPUTE,
+            (
+                uniform_buffer_sized(false, NonZeroU64::new(size_of::<i32>() as u64 * 2)),
+                storage_buffer_sized(false, NonZeroU64::new(tile.buffer_size())),
+                storage_buffer_read_only_sized(false, NonZeroU64::new(layers_size(tile))),
+            ),
+        ),
+    )
+}
+
+fn find_layout(render_device: &RenderDevice, tile: &TileSettings) -> BindGroupLayout {
+    render_device.create_bind_group_layout(
+        None,
+        &BindGroupLayoutEntries::sequential(
+            ShaderStages::COMPUTE,
+            (
+                uniform_buffer_sized(false, NonZeroU64::new(size_of::<u32>() as u64 * 3)),
+                storage_buffer_sized(false, NonZeroU64::new(tile.buffer_size())),
+                storage_buffer_read_only_sized(false, None),
+                storage_buffer_sized(false, NonZeroU64::new(RESULT_SIZE)),
+                uniform_buffer_sized(false, NonZeroU64::new(size_of::<u32>() as u64 * 3)),
+                storage_buffer_read_only_sized(false, NonZeroU64::new(layers_size(tile))),
+                uniform_buffer_sized(false, NonZeroU64::new(size_of::<u32>() as u64)),
+            ),
+        ),
+    )
+}
+
+fn fused_layout(render_device: &RenderDevice, tile: &TileSettings) -> BindGroupLayout {
+    render_device.create_bind_group_layout(
+        None,
+        &BindGroupLayoutEntries::sequential(
+            ShaderStages::COMPUTE,
+            (
+                uniform_buffer_sized(false, NonZeroU64::new(size_of::<i32>() as u64 * 2)),
+                storage_buffer_read_only_sized(false, None),
+                storage_buffer_sized(false, NonZeroU64::new(RESULT_SIZE)),
+                uniform_buffer_sized(false, NonZeroU64::new(size_of::<u32>() as u64 * 3)),
+                storage_buffer_read_only_sized(false, NonZeroU64::new(layers_size(tile))),
+            ),
+        ),
+    )
+}
+
+/// Constrained blocks of every pattern for the fused kernel, one pattern after another.
+///
+/// `xyz` is the offset of the block inside the pattern, `w` holds its rotation in the lowest
+/// byte, the index of its pattern in the next one and the index of the first block of the next
+/// pattern in the upper 16 bits, where the kernel continues once a block doesn't match.
+fn sparse_patterns(job: &FinderJob) -> Result<Vec<IVec4>, String> {
+    let patterns = SparsePattern::from_job(job);
+    if patterns.len() > 256 {
+        return Err(format!(
+            "{} patterns can't be searched at once, the limit is 256",
+            patterns.len()
+        ));
+    }
+    let mut blocks = Vec::new();
+    for (index, pattern) in patterns.into_iter().enumerate() {
+        let mut pattern: Vec<IVec4> = pattern
+            .0
+            .into_iter()
+            .map(|(pos, rotation)| (pos - job.offset).extend(rotation.0 as i32))
+            .collect();
+        if pattern.is_empty() {
+            // a max rotation of 0 matches everything
+            pattern.push(IVec4::ZERO);
+        }
+        let next = blocks.len() + pattern.len();
+        if next > u16::MAX as usize {
+            return Err(format!(
+                "the patterns have more than {} constrained blocks",
+                u16::MAX
+            ));
+        }
+        blocks.extend(pattern.into_iter().map(|mut block| {
+            block.w |= (index as i32) << 8 | (next as i32) << 16;
+            block
+        }));
+    }
+    Ok(blocks)
+}
+
+/// Layer lists hold an entry for every layer of the tile, the rest of them is unused.
+fn layers_size(tile: &TileSettings) -> u64 {
+    tile.height as u64 * size_of::<u32>() as u64
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use bevy::{
+        math::{IVec3, IVec4, UVec3},
+        render::render_resource::{
+            binding_types::{storage_buffer, storage_buffer_read_only},
+            *,
+        },
+    };
+
+    use super::{
+        create_pipeline, preferred_adapter, preprocess, request_device, sparse_patterns,
+        ComputeRunner, TileSource, SHADER_IMPORTS,
+    };
+    use crate::finder::{
+        plugin::{FinderJob, FinderKernel, FinderStatus, PatternMatch},
+        tile::TileSettings,
+        util::get_block_rotation,
+        Rotation,
+    };
+
+    fn shader_defs(shader_int64: bool) -> Vec<ShaderDefVal> {
+        let mut shader_defs = vec![
+            ShaderDefVal::UInt("WORKGROUP_SIZE".into(), 64),
+            ShaderDefVal::UInt("TILE_SIZE".into(), 256),
+            ShaderDefVal::UInt("TILE_HEIGHT".into(), 64),
+            ShaderDefVal::UInt("MAX_CANDIDATES".into(), 64),
+        ];
+        if shader_int64 {
+            shader_defs.push("SHADER_INT64".into());
+        }
+        shader_defs
+    }
+
+    #[test]
+    fn test_shaders_validate() {
+        let shaders = [
+            ("find", include_str!("../../shaders/find.wgsl")),
+            ("chunk", include_str!("../../shaders/chunk.wgsl")),
+            ("fused", include_str!("../../shaders/fused.wgsl")),
+        ];
+        for (name, source) in shaders {
+            for shader_int64 in [false, true] {
+                let source = preprocess(source, &shader_defs(shader_int64));
+                let module = wgpu::naga::front::wgsl::parse_str(&source)
+                    .unwrap_or_else(|err| panic!("{name}: {}", err.emit_to_string(&source)));
+                wgpu::naga::valid::Validator::new(
+                    wgpu::naga::valid::ValidationFlags::all(),
+                    wgpu::naga::valid::Capabilities::all(),
+                )
+                .validate(&module)
+                .unwrap_or_else(|err| panic!("{name}, SHADER_INT64 {shader_int64}: {err:?}"));
+            }
+        }
+    }
+
+    /// Runs the shared rotation module on the GPU, with and without 64 bit integers.
+    #[test]
+    fn test_shader_rotations() {
+        let Ok(adapter) = preferred_adapter() else {
+            eprintln!("no adapter, skipping the shader rotations");
+            return;
+        };
+        let (render_device, render_queue) = request_device(&adapter).unwrap();
+        let data: &[(i64, i64, i64, i64, u8)] = &include!("../testdata.txt");
+        let positions: Vec<[i32; 4]> = data
+            .iter()
+            .map(|(x, y, z, _, _)| [*x as i32, *y as i32, *z as i32, 0])
+            .chain([[-30_000_000, 319, 29_999_984, 0], [-8, -64, -1, 0], [0; 4]])
+            .collect();
+        let source = format!(
+            "#import {}::get_block_rotation
+            @group(0) @binding(0) var<storage, read> positions: array<vec4<i32>>;
+            @group(0) @binding(1) var<storage, read_write> rotations: array<u32>;
+            @compute @workgroup_size(64)
+            fn main(@builtin(global_invocation_id) id: vec3<u32>) {{
+                if id.x < arrayLength(&rotations) {{
+                    rotations[id.x] = get_block_rotation(positions[id.x].xyz);
+                }}
+            }}",
+            SHADER_IMPORTS[0].0
+        );
+        let layout = render_device.create_bind_group_layout(
+            None,
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    storage_buffer_read_only::<Vec<[i32; 4]>>(false),
+                    storage_buffer::<Vec<u32>>(false),
+                ),
+            ),
+        );
+        let size = positions.len() as u64 * 4;
+        let input = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: None,
+            contents: &positions
+                .iter()
+                .flatten()
+                .flat_map(|v| v.to_le_bytes())
+                .collect::<Vec<_>>(),
+            usage: BufferUsages::STORAGE,
+        });
+        let output = render_device.create_buffer(&BufferDescriptor {
+            label: None,
+            size,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback = render_device.create_buffer(&BufferDescriptor {
+            label: None,
+            size,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = render_device.create_bind_group(
+            None,
+            &layout,
+            &BindGroupEntries::sequential((input.as_entire_binding(), output.as_entire_binding())),
+        );
+        let shader_int64 = render_device
+            .features()
+            .contains(WgpuFeatures::SHADER_INT64);
+        for shader_int64 in [false, true].into_iter().filter(|v| !v || shader_int64) {
+            let pipeline =
+                create_pipeline(&render_device, &layout, &source, &shader_defs(shader_int64));
+            let mut encoder =
+                render_device.create_command_encoder(&CommandEncoderDescriptor::default());
+            let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups((positions.len() as u32).div_ceil(64), 1, 1);
+            drop(pass);
+            encoder.copy_buffer_to_buffer(&output, 0, &readback, 0, size);
+            render_queue.submit([encoder.finish()]);
+            readback.slice(..).map_async(MapMode::Read, |v| v.unwrap());
+            render_device.poll(Maintain::Wait).panic_on_timeout();
+            let rotations: Vec<u32> = readback
+                .slice(..)
+                .get_mapped_range()
+                .chunks_exact(4)
+                .map(|v| u32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+                .collect();
+            readback.unmap();
+            for (pos, rotation) in positions.iter().zip(rotations) {
+                let [x, y, z, _] = pos.map(|v| v as i64);
+                assert_eq!(
+                    rotation as u8,
+                    get_block_rotation(x, y, z),
+                    "wrong rotation at {x},{y},{z} with SHADER_INT64 {shader_int64}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_tile_source() {
+        let found = |pattern, pos| PatternMatch { pattern, pos };
+        let source = TileSource::new(Some(8));
+        assert_eq!(source.take(), Some(0));
+        assert_eq!(source.take(), Some(1));
+        source.report(5, found(0, IVec3::X));
+        source.report(3, found(1, IVec3::Y));
+        // a later match doesn't replace an earlier one
+        source.report(4, found(0, IVec3::Z));
+        assert_eq!(source.found(), Some(found(1, IVec3::Y)));
+        // the tiles past the match are skipped
+        assert_eq!(source.take(), Some(2));
+        assert_eq!(source.take(), None);
+        let source = TileSource::range(4..8);
+        assert_eq!(source.take(), Some(4));
+        source.cancel();
+        assert_eq!(source.take(), None);
+        let source = TileSource::new(None).with_filter(|found| found.pattern == 1);
+        assert!(!source.accepts(&found(0, IVec3::X)));
+        assert!(source.accepts(&found(1, IVec3::X)));
+        let matches = vec![found(0, IVec3::X), found(1, IVec3::Y)];
+        assert_eq!(
+            source.accept(0, matches.clone(), 2),
+            Ok(Some(found(1, IVec3::Y)))
+        );
+        // an accepted match is enough, even when some were dropped
+        assert_eq!(
+            source.accept(0, matches.clone(), 70),
+            Ok(Some(found(1, IVec3::Y)))
+        );
+        assert_eq!(source.accept(0, matches[..1].to_vec(), 1), Ok(None));
+        // the dropped matches could have been accepted
+        assert!(source.accept(0, matches[..1].to_vec(), 70).is_err());
+        let source =
+            source.with_recheck(move |tile_index| Some(found(1, IVec3::splat(tile_index as i32))));
+        assert_eq!(
+            source.accept(3, matches[..1].to_vec(), 70),
+            Ok(Some(found(1, IVec3::splat(3))))
+        );
+        assert_eq!(source.accept(3, matches[..1].to_vec(), 1), Ok(None));
+        // without a filter the first kept match is taken
+        let source = TileSource::new(None);
+        assert_eq!(source.accept(0, matches, 70), Ok(Some(found(0, IVec3::X))));
+    }
+
+    #[test]
+    fn test_failed_worker() {
+        let Ok(adapter) = preferred_adapter() else {
+            eprintln!("no adapter, skipping the failed worker");
+            return;
+        };
+        let runner = |size| {
+            let (render_device, render_queue) = request_device(&adapter).unwrap();
+            let tile = TileSettings {
+                size,
+                ..TileSettings::from_limits(&render_device.limits())
+            };
+            let mut runner =
+                ComputeRunner::with_device(render_device, render_queue, String::new(), tile);
+            runner.set_kernel(FinderKernel::Fused);
+            runner
+        };
+        // the pattern doesn't fit into the tiles of the second runner, and never matches
+        let mut runners = [runner(64), runner(32)];
+        let job = FinderJob {
+            size: UVec3::new(40, 1, 1),
+            offset: IVec3::ZERO,
+            rotations: vec![Rotation::new(1, 4).0; 40],
+            y_levels: vec![64],
+        };
+        let source = Arc::new(TileSource::new(None));
+        let status = Mutex::new(FinderStatus::WaitingForJob);
+        let result = ComputeRunner::run_source(&mut runners, &job, source.clone(), &status);
+        assert!(result.is_err());
+        assert!(source.is_cancelled());
+    }
+
+    #[test]
+    fn test_sparse_patterns() {
+        let job = FinderJob {
+            size: UVec3::new(2, 1, 1),
+            offset: IVec3::new(3, 0, 0),
+            rotations: vec![0x41, 0x42, 0, 0, 0x23, 0],
+            y_levels: Vec::new(),
+        };
+        assert_eq!(
+            sparse_patterns(&job).unwrap(),
+            vec![
+                IVec4::new(0, 0, 0, 0x41 | 2 << 16),
+                IVec4::new(1, 0, 0, 0x42 | 2 << 16),
+                // an empty pattern gets a block that matches everything
+                IVec4::new(0, 0, 0, 1 << 8 | 3 << 16),
+                IVec4::new(0, 0, 0, 0x23 | 2 << 8 | 4 << 16),
+            ]
+        );
+    }
+}
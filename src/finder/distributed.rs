@@ -0,0 +1,1083 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    ops::Range,
+    str::{FromStr, SplitWhitespace},
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+#[cfg(unix)]
+use std::{
+    fs,
+    os::unix::net::{UnixListener, UnixStream},
+};
+
+use bevy::{
+    log::{info, warn},
+    math::{IVec3, UVec3},
+};
+
+use crate::constants::MAX_PATTERN_SIZE;
+
+use super::{
+    chunk::{CPUChunkProvider, RotationChunk},
+    compute::{ComputeRunner, TileSource},
+    plugin::{FinderJob, FinderStatus, PatternMatch},
+    region::{ChunkPattern, SparsePattern},
+    tile::TileSettings,
+    util::env_u32,
+};
+
+/// Fits into a single storage buffer binding on most GPUs.
+const DEFAULT_TILE_SIZE: u32 = 1024;
+/// How often workers report the blocks searched in their lease, which also shows they're alive.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the CPU search logs how well tile generation keeps up.
+const METRICS_INTERVAL: Duration = Duration::from_secs(30);
+/// Patterns a job sent to workers may have.
+const MAX_PATTERNS: usize = 8;
+/// Longest message line, job lines have two hex digits per block of each pattern.
+const MAX_LINE: u64 =
+    (MAX_PATTERNS * 2 * MAX_PATTERN_SIZE.0 * MAX_PATTERN_SIZE.1 * MAX_PATTERN_SIZE.2) as u64 + 1024;
+
+/// Splits the spiral of a job into leases of consecutive tiles for the workers connecting to it.
+///
+/// A lease goes back to the pool when its worker disconnects or doesn't report progress for
+/// `FINDER_LEASE_TIMEOUT` seconds, 60 by default. Leases are `FINDER_LEASE_TILES` tiles long,
+/// 16 by default, and the tiles `FINDER_TILE_SIZE` blocks wide, 1024 by default. The search
+/// fails once a lease is lost more than `FINDER_LEASE_RETRIES` times, 3 by default.
+///
+/// Every worker that connects is trusted: the blocks and matches it reports aren't checked, only
+/// the length of its messages is bounded.
+pub struct Coordinator {
+    job: FinderJob,
+    tile: TileSettings,
+    timeout: Duration,
+    leases: Mutex<Leases>,
+    /// signalled whenever a lease is finished or returned
+    changed: Condvar,
+}
+
+impl Coordinator {
+    /// Searches the tiles within `radius` blocks of 0,0, or the whole spiral.
+    pub fn new(job: FinderJob, radius: Option<u64>) -> Result<Self, String> {
+        let size = env_u32("FINDER_TILE_SIZE").unwrap_or(DEFAULT_TILE_SIZE);
+        let tile = TileSettings {
+            size: (size / 16 * 16).max(16),
+            ..Default::default()
+        };
+        if !tile.fits(job.size) {
+            return Err(format!(
+                "pattern of size {} doesn't fit into {tile:?}",
+                job.size
+            ));
+        }
+        if job.pattern_count() > MAX_PATTERNS {
+            return Err(format!(
+                "can't send {} patterns to workers, only {MAX_PATTERNS}",
+                job.pattern_count()
+            ));
+        }
+        if job.layers(tile.height).is_empty() {
+            return Err(format!(
+                "none of the y levels {:?} fit a pattern of size {}",
+                job.y_levels, job.size
+            ));
+        }
+        let max_tiles = radius.map(|radius| tile.tiles_for_radius(radius, job.size));
+        let lease_tiles = env_u32("FINDER_LEASE_TILES").unwrap_or(16).max(1);
+        let retries = env_u32("FINDER_LEASE_RETRIES").unwrap_or(3);
+        Ok(Self {
+            job,
+            tile,
+            timeout: Duration::from_secs(env_u32("FINDER_LEASE_TIMEOUT").unwrap_or(60).into()),
+            leases: Mutex::new(Leases::new(lease_tiles, max_tiles, retries)),
+            changed: Condvar::new(),
+        })
+    }
+
+    /// Hands out leases to the workers connecting to `address` until the first match is known
+    /// or the spiral is searched, and keeps `status` up to date. Workers still searching tiles
+    /// after the match are stopped.
+    ///
+    /// `address` is `host:port`, or `unix:<path>` for a Unix socket.
+    pub fn run(
+        self,
+        address: &str,
+        status: &Mutex<FinderStatus>,
+    ) -> Result<Option<PatternMatch>, String> {
+        let listener = Listener::bind(address)
+            .and_then(|listener| listener.set_nonblocking().map(|_| listener))
+            .map_err(|err| format!("couldn't listen on {address}: {err}"))?;
+        info!("waiting for workers on {address}");
+        let coordinator = Arc::new(self);
+        let start_time = Instant::now();
+        loop {
+            match listener.accept() {
+                Ok(stream) => {
+                    let coordinator = coordinator.clone();
+                    thread::spawn(move || {
+                        if let Err(err) = coordinator.serve(stream) {
+                            warn!("{err}");
+                        }
+                    });
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(100));
+                }
+                Err(err) => warn!("couldn't accept a worker: {err}"),
+            }
+            let leases = coordinator.leases.lock().unwrap();
+            let mut status = status.lock().unwrap();
+            *status = FinderStatus::Running {
+                blocks: leases.blocks(),
+                start_time,
+            };
+            if leases.is_finished() {
+                let found = leases.found.map(|(_, found)| found);
+                if let Some(found) = found {
+                    *status = FinderStatus::Finished {
+                        searched_blocks: leases.blocks(),
+                        pos: found.pos,
+                        pattern: found.pattern,
+                        time: start_time.elapsed(),
+                    };
+                }
+                drop(status);
+                // the workers waiting for a lease are told to stop, the ones searching tiles that
+                // don't matter anymore with their next progress report
+                coordinator.changed.notify_all();
+                let (leases, _) = coordinator
+                    .changed
+                    .wait_timeout_while(leases, PROGRESS_INTERVAL * 3, |leases| {
+                        !leases.active.is_empty()
+                    })
+                    .unwrap();
+                return match &leases.failed {
+                    Some(tiles) => Err(format!(
+                        "giving up on tiles {tiles:?}, they were lost {} times",
+                        leases.retries + 1
+                    )),
+                    None => Ok(found),
+                };
+            }
+        }
+    }
+
+    /// Leases tiles to a worker until there are none left, and returns its lease if it's lost.
+    fn serve(&self, stream: Stream) -> Result<(), String> {
+        let peer = stream.peer();
+        let mut writer = stream
+            .prepare(self.timeout)
+            .and_then(|_| stream.try_clone())
+            .map_err(|err| format!("couldn't set up the connection to {peer}: {err}"))?;
+        let mut reader = BufReader::new(stream);
+        let job = Message::Job {
+            tile_size: self.tile.size,
+            job: self.job.clone(),
+        };
+        let name = match send(&mut writer, &job).and_then(|_| receive(&mut reader))? {
+            Some(Message::Hello { name }) => format!("{name} at {peer}"),
+            _ => return Err(format!("{peer} isn't a worker")),
+        };
+        info!("{name} joined");
+        while let Some((id, tiles)) = self.next_lease() {
+            info!("leasing tiles {tiles:?} to {name}");
+            let result = send(&mut writer, &Message::Lease { id, tiles })
+                .and_then(|_| self.follow(id, &mut reader));
+            match result {
+                Ok(true) => {}
+                Ok(false) => {
+                    info!("stopping {name}, its tiles aren't needed anymore");
+                    break;
+                }
+                Err(err) => {
+                    self.leases.lock().unwrap().release(id);
+                    self.changed.notify_all();
+                    return Err(format!("lost {name}, leasing its tiles again: {err}"));
+                }
+            }
+        }
+        send(&mut writer, &Message::Stop)
+    }
+
+    /// Waits until tiles can be leased, `None` once the search is finished.
+    fn next_lease(&self) -> Option<(u32, Range<u32>)> {
+        let mut leases = self.leases.lock().unwrap();
+        loop {
+            if let Some(lease) = leases.issue() {
+                return Some(lease);
+            }
+            if leases.is_finished() {
+                return None;
+            }
+            // another worker may still be lost and return its tiles
+            leases = self.changed.wait(leases).unwrap();
+        }
+    }
+
+    /// Records the progress of lease `id` until the worker is done with it, `false` if its
+    /// tiles aren't needed anymore and the worker has to be stopped.
+    fn follow(&self, id: u32, reader: &mut impl BufRead) -> Result<bool, String> {
+        loop {
+            match receive(reader)? {
+                Some(Message::Progress { id: lease, blocks }) if lease == id => {
+                    let mut leases = self.leases.lock().unwrap();
+                    leases.progress(id, blocks);
+                    if !leases.is_needed(id) {
+                        leases.finish(id, blocks, None);
+                        self.changed.notify_all();
+                        return Ok(false);
+                    }
+                }
+                Some(Message::Done {
+                    id: lease,
+                    blocks,
+                    found,
+                }) if lease == id => {
+                    self.leases.lock().unwrap().finish(id, blocks, found);
+                    self.changed.notify_all();
+                    return Ok(true);
+                }
+                Some(_) => return Err("unexpected message".to_owned()),
+                None => return Err("connection closed".to_owned()),
+            }
+        }
+    }
+}
+
+/// Which tiles of the spiral are leased, and what has been found in them.
+struct Leases {
+    /// tiles per lease
+    size: u32,
+    /// first tile that hasn't been leased yet
+    next_tile: u32,
+    end: Option<u32>,
+    next_id: u32,
+    /// tiles of lost workers, leased again before the later ones, sorted by the first tile in
+    /// descending order
+    returned: Vec<Range<u32>>,
+    /// tiles of the leases being searched, and the blocks searched in them so far
+    active: HashMap<u32, (Range<u32>, u64)>,
+    /// blocks of the finished leases
+    searched_blocks: u64,
+    /// first tile of the earliest lease with a match, and what was found in it
+    found: Option<(u32, PatternMatch)>,
+    /// times the tiles of a lease were lost, by its first tile
+    losses: HashMap<u32, u32>,
+    /// times tiles are leased again after being lost
+    retries: u32,
+    /// tiles that were lost too often, which ends the search
+    failed: Option<Range<u32>>,
+}
+
+impl Leases {
+    fn new(size: u32, end: Option<u32>, retries: u32) -> Self {
+        Self {
+            size,
+            next_tile: 0,
+            end,
+            next_id: 0,
+            returned: Vec::new(),
+            active: HashMap::new(),
+            searched_blocks: 0,
+            found: None,
+            losses: HashMap::new(),
+            retries,
+            failed: None,
+        }
+    }
+
+    /// Tiles before this one have to be searched, none once the search failed.
+    fn limit(&self) -> u32 {
+        match self.found {
+            _ if self.failed.is_some() => 0,
+            Some((tile, _)) => tile,
+            None => self.end.unwrap_or(u32::MAX),
+        }
+    }
+
+    /// Leases the next tiles, unless they are all leased or past the first match.
+    fn issue(&mut self) -> Option<(u32, Range<u32>)> {
+        let limit = self.limit();
+        let tiles = match self.returned.last() {
+            Some(tiles) if tiles.start < limit => self.returned.pop()?,
+            _ if self.next_tile < limit => {
+                let start = self.next_tile;
+                self.next_tile = start
+                    .saturating_add(self.size)
+                    .min(self.end.unwrap_or(u32::MAX));
+                start..self.next_tile
+            }
+            _ => return None,
+        };
+        let id = self.next_id;
+        self.next_id += 1;
+        self.active.insert(id, (tiles.clone(), 0));
+        Some((id, tiles))
+    }
+
+    fn progress(&mut self, id: u32, blocks: u64) {
+        if let Some((_, searched)) = self.active.get_mut(&id) {
+            *searched = blocks;
+        }
+    }
+
+    /// Keeps the match if no earlier lease has one.
+    fn finish(&mut self, id: u32, blocks: u64, found: Option<PatternMatch>) {
+        let Some((tiles, _)) = self.active.remove(&id) else {
+            return;
+        };
+        self.searched_blocks += blocks;
+        if let Some(found) = found {
+            if !matches!(self.found, Some((tile, _)) if tile < tiles.start) {
+                self.found = Some((tiles.start, found));
+            }
+        }
+    }
+
+    /// Returns the tiles of a lost worker, the blocks it searched don't count. Tiles lost more
+    /// than `retries` times fail the search, unless they come after the first match.
+    fn release(&mut self, id: u32) {
+        let Some((tiles, _)) = self.active.remove(&id) else {
+            return;
+        };
+        if tiles.start >= self.limit() {
+            return;
+        }
+        let losses = self.losses.entry(tiles.start).or_default();
+        *losses += 1;
+        if *losses > self.retries {
+            self.failed.get_or_insert(tiles);
+        } else {
+            self.returned.push(tiles);
+            self.returned.sort_by_key(|tiles| Reverse(tiles.start));
+        }
+    }
+
+    /// Whether the tiles of lease `id` come before the first match.
+    fn is_needed(&self, id: u32) -> bool {
+        let limit = self.limit();
+        self.active
+            .get(&id)
+            .is_some_and(|(tiles, _)| tiles.start < limit)
+    }
+
+    /// Whether every tile before the first match, or of the whole spiral, has been searched.
+    fn is_finished(&self) -> bool {
+        let limit = self.limit();
+        self.next_tile >= limit
+            && self
+                .active
+                .values()
+                .map(|(tiles, _)| tiles)
+                .chain(&self.returned)
+                .all(|tiles| tiles.start >= limit)
+    }
+
+    fn blocks(&self) -> u64 {
+        self.searched_blocks + self.active.values().map(|(_, blocks)| blocks).sum::<u64>()
+    }
+}
+
+/// What a worker searches its leases on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// The adapters picked by `FINDER_ADAPTERS`.
+    Gpu,
+    /// Every core, with the region solver.
+    Cpu,
+}
+
+/// Connects to the coordinator at `address` and searches the tiles it leases until it's done.
+pub fn work(address: &str, backend: Backend) -> Result<(), String> {
+    let stream =
+        Stream::connect(address).map_err(|err| format!("couldn't connect to {address}: {err}"))?;
+    let mut writer = stream.try_clone().map_err(|err| err.to_string())?;
+    let mut reader = BufReader::new(stream);
+    let Some(Message::Job { tile_size, job }) = receive(&mut reader)? else {
+        return Err(format!("{address} didn't send a job"));
+    };
+    let mut searcher = Searcher::new(backend, tile_size)?;
+    send(
+        &mut writer,
+        &Message::Hello {
+            name: searcher.name(),
+        },
+    )?;
+    // read on a thread of their own, so the coordinator can stop a lease being searched
+    let (sender, messages) = mpsc::channel();
+    thread::spawn(move || loop {
+        let message = receive(&mut reader);
+        let last = !matches!(message, Ok(Some(_)));
+        if sender.send(message).is_err() || last {
+            return;
+        }
+    });
+    loop {
+        let (id, tiles) = match messages.recv().unwrap_or(Ok(None))? {
+            Some(Message::Lease { id, tiles }) => (id, tiles),
+            Some(Message::Stop) | None => {
+                info!("the coordinator finished the search");
+                return Ok(());
+            }
+            Some(_) => return Err("unexpected message from the coordinator".to_owned()),
+        };
+        info!("searching tiles {tiles:?}");
+        let source = Arc::new(TileSource::range(tiles));
+        let status = Mutex::new(FinderStatus::WaitingForJob);
+        let (found, stop) = thread::scope(|scope| {
+            let search = scope.spawn(|| searcher.search(&job, source.clone(), &status));
+            let mut stop = None;
+            let mut last_progress = Instant::now();
+            while !search.is_finished() {
+                if stop.is_some() {
+                    thread::sleep(Duration::from_millis(50));
+                    continue;
+                }
+                match messages.recv_timeout(Duration::from_millis(50)) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    // a stop, or a lost coordinator that doesn't need the tiles anymore
+                    message => {
+                        source.cancel();
+                        stop = Some(message.unwrap_or(Ok(None)));
+                    }
+                }
+                if last_progress.elapsed() >= PROGRESS_INTERVAL {
+                    last_progress = Instant::now();
+                    let blocks = status.lock().unwrap().searched_blocks();
+                    // a lost coordinator shows up when the lease is done
+                    let _ = send(&mut writer, &Message::Progress { id, blocks });
+                }
+            }
+            (search.join().unwrap(), stop)
+        });
+        match stop {
+            Some(Ok(Some(Message::Stop) | None)) => {
+                info!("the coordinator stopped the search");
+                return Ok(());
+            }
+            Some(Ok(Some(_))) => return Err("unexpected message from the coordinator".to_owned()),
+            Some(Err(err)) => return Err(err),
+            None => {}
+        }
+        let found = found?;
+        let blocks = status.lock().unwrap().searched_blocks();
+        send(&mut writer, &Message::Done { id, blocks, found })?;
+    }
+}
+
+enum Searcher {
+    Gpu(Vec<ComputeRunner>),
+    Cpu(TileSettings),
+}
+
+impl Searcher {
+    fn new(backend: Backend, tile_size: u32) -> Result<Self, String> {
+        match backend {
+            Backend::Gpu => ComputeRunner::from_env_with_tile_size(tile_size).map(Self::Gpu),
+            Backend::Cpu => Ok(Self::Cpu(TileSettings {
+                size: tile_size,
+                ..Default::default()
+            })),
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Self::Gpu(runners) => runners
+                .iter()
+                .map(ComputeRunner::adapter_name)
+                .collect::<Vec<_>>()
+                .join(", "),
+            Self::Cpu(_) => {
+                let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+                format!("{threads} CPU threads")
+            }
+        }
+    }
+
+    fn search(
+        &mut self,
+        job: &FinderJob,
+        source: Arc<TileSource>,
+        status: &Mutex<FinderStatus>,
+    ) -> Result<Option<PatternMatch>, String> {
+        match self {
+            Self::Gpu(runners) => ComputeRunner::run_source(runners, job, source, status),
+            Self::Cpu(tile) => search_cpu(*tile, job, &source, status),
+        }
+    }
+}
+
+/// Checks the tiles of `source` in order, at the same positions as the GPU kernels, with the
+/// rotations of the tiles generated ahead of the search by a `CPUChunkProvider`. Only the
+/// heights the pattern covers at the y levels of the job are generated, and the search stops
+/// early once `source` is cancelled.
+///
+/// Like on the GPU, the first pattern of the job wins when several of them match in a tile.
+pub fn search_cpu(
+    tile: TileSettings,
+    job: &FinderJob,
+    source: &TileSource,
+    status: &Mutex<FinderStatus>,
+) -> Result<Option<PatternMatch>, String> {
+    if !tile.fits(job.size) {
+        return Err(format!(
+            "pattern of size {} doesn't fit into {tile:?}",
+            job.size
+        ));
+    }
+    let Some(heights) = job.heights(tile.height) else {
+        return Err(format!(
+            "none of the y levels {:?} fit a pattern of size {}",
+            job.y_levels, job.size
+        ));
+    };
+    // every tile of the lease is a chunk of the same size
+    let size = RotationChunk::tile_size(&tile, &heights);
+    let patterns: Vec<ChunkPattern> = SparsePattern::from_job(job)
+        .iter()
+        .map(|pattern| ChunkPattern::new(pattern, size))
+        .collect();
+    let layers = job.layers(tile.height);
+    let positions = tile.positions(job.size);
+    let blocks_per_tile = job.blocks_per_tile(&tile);
+    *status.lock().unwrap() = FinderStatus::Running {
+        blocks: 0,
+        start_time: Instant::now(),
+    };
+    let bottom = heights.start as i32;
+    let mut chunks = CPUChunkProvider::new(tile, source.tiles(), heights, job.size);
+    let mut last_metrics = Instant::now();
+    while let Some((tile_index, chunk)) = chunks.next() {
+        if source.is_cancelled() {
+            break;
+        }
+        if last_metrics.elapsed() >= METRICS_INTERVAL {
+            last_metrics = Instant::now();
+            let metrics = chunks.metrics();
+            info!(
+                "generated {} tiles at {:.2} per second, {} ready and {} being generated",
+                metrics.generated, metrics.rate, metrics.queue_depth, metrics.in_progress
+            );
+        }
+        for (index, pattern) in patterns.iter().enumerate() {
+            // positions relative to the chunk, the blocks of the pattern are offset by the job
+            let found = layers.iter().find_map(|y| {
+                (0..positions.z as i32).find_map(|z| {
+                    (0..positions.x as i32)
+                        .map(|x| IVec3::new(x, *y as i32 - bottom, z) - job.offset)
+                        .find(|pos| pattern.mismatches(&chunk, *pos, 0) == 0)
+                })
+            });
+            if let Some(pos) = found {
+                let origin = tile.origin(tile_index, job.size);
+                return Ok(Some(PatternMatch {
+                    pattern: index,
+                    pos: pos + IVec3::new(origin.x, bottom, origin.y),
+                }));
+            }
+        }
+        if let FinderStatus::Running { blocks, .. } = &mut *status.lock().unwrap() {
+            *blocks += blocks_per_tile;
+        }
+    }
+    Ok(None)
+}
+
+/// What the coordinator and workers send each other, one message per line.
+#[derive(Clone, Debug)]
+enum Message {
+    /// the job and the size of the tiles its spiral is split into
+    Job {
+        tile_size: u32,
+        job: FinderJob,
+    },
+    Hello {
+        name: String,
+    },
+    Lease {
+        id: u32,
+        tiles: Range<u32>,
+    },
+    /// blocks searched in a lease so far
+    Progress {
+        id: u32,
+        blocks: u64,
+    },
+    Done {
+        id: u32,
+        blocks: u64,
+        found: Option<PatternMatch>,
+    },
+    Stop,
+}
+
+impl Message {
+    fn to_line(&self) -> String {
+        match self {
+            Self::Job { tile_size, job } => {
+                let y_levels = match job.y_levels.is_empty() {
+                    true => "-".to_owned(),
+                    false => job
+                        .y_levels
+                        .iter()
+                        .map(i32::to_string)
+                        .collect::<Vec<_>>()
+                        .join(","),
+                };
+                let rotations: String = job
+                    .rotations
+                    .iter()
+                    .map(|rotation| format!("{rotation:02x}"))
+                    .collect();
+                format!(
+                    "job {tile_size} {} {} {} {} {} {} {y_levels} {rotations}",
+                    job.size.x, job.size.y, job.size.z, job.offset.x, job.offset.y, job.offset.z
+                )
+            }
+            Self::Hello { name } => format!("hello {name}"),
+            Self::Lease { id, tiles } => format!("lease {id} {} {}", tiles.start, tiles.end),
+            Self::Progress { id, blocks } => format!("progress {id} {blocks}"),
+            Self::Done { id, blocks, found } => match found {
+                Some(PatternMatch { pattern, pos }) => {
+                    format!("done {id} {blocks} {} {} {} {pattern}", pos.x, pos.y, pos.z)
+                }
+                None => format!("done {id} {blocks}"),
+            },
+            Self::Stop => "stop".to_owned(),
+        }
+    }
+
+    fn parse(line: &str) -> Result<Self, String> {
+        Self::parse_words(line).ok_or_else(|| {
+            // job lines contain the whole pattern
+            let start: String = line.chars().take(40).collect();
+            format!("invalid message {start}")
+        })
+    }
+
+    fn parse_words(line: &str) -> Option<Self> {
+        if let Some(name) = line.strip_prefix("hello ") {
+            return Some(Self::Hello {
+                name: name.to_owned(),
+            });
+        }
+        let mut words = line.split_whitespace();
+        let message = match words.next()? {
+            "job" => {
+                let tile_size = field(&mut words)?;
+                let size = UVec3::new(field(&mut words)?, field(&mut words)?, field(&mut words)?);
+                let offset = IVec3::new(field(&mut words)?, field(&mut words)?, field(&mut words)?);
+                let y_levels = match words.next()? {
+                    "-" => Vec::new(),
+                    y_levels => y_levels
+                        .split(',')
+                        .map(|y| y.parse().ok())
+                        .collect::<Option<_>>()?,
+                };
+                let hex = words.next()?;
+                let rotations: Vec<u8> = (0..hex.len())
+                    .step_by(2)
+                    .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+                    .collect::<Option<_>>()?;
+                // one or more patterns of the same size
+                let volume = size.as_u64vec3().element_product();
+                if volume == 0
+                    || rotations.is_empty()
+                    || !(rotations.len() as u64).is_multiple_of(volume)
+                {
+                    return None;
+                }
+                let job = FinderJob {
+                    size,
+                    offset,
+                    rotations,
+                    y_levels,
+                };
+                Self::Job { tile_size, job }
+            }
+            "lease" => Self::Lease {
+                id: field(&mut words)?,
+                tiles: field(&mut words)?..field(&mut words)?,
+            },
+            "progress" => Self::Progress {
+                id: field(&mut words)?,
+                blocks: field(&mut words)?,
+            },
+            "done" => Self::Done {
+                id: field(&mut words)?,
+                blocks: field(&mut words)?,
+                found: match words.next() {
+                    Some(x) => Some(PatternMatch {
+                        pos: IVec3::new(x.parse().ok()?, field(&mut words)?, field(&mut words)?),
+                        pattern: field(&mut words)?,
+                    }),
+                    None => None,
+                },
+            },
+            "stop" => Self::Stop,
+            _ => return None,
+        };
+        // nothing may follow the fields
+        words.next().is_none().then_some(message)
+    }
+}
+
+fn field<T: FromStr>(words: &mut SplitWhitespace) -> Option<T> {
+    words.next()?.parse().ok()
+}
+
+fn send(writer: &mut impl Write, message: &Message) -> Result<(), String> {
+    let mut line = message.to_line();
+    line.push('\n');
+    writer
+        .write_all(line.as_bytes())
+        .map_err(|err| err.to_string())
+}
+
+/// Reads the next message, `None` once the connection is closed.
+fn receive(reader: &mut impl BufRead) -> Result<Option<Message>, String> {
+    match read_line(reader, MAX_LINE)? {
+        Some(line) => Message::parse(line.trim_end()).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Reads a line, which is rejected once it's longer than `max` bytes.
+fn read_line(reader: &mut impl BufRead, max: u64) -> Result<Option<String>, String> {
+    let mut line = String::new();
+    match reader.by_ref().take(max).read_line(&mut line) {
+        Ok(0) => Ok(None),
+        Ok(len) if len as u64 == max && !line.ends_with('\n') => {
+            Err(format!("message is longer than {max} bytes"))
+        }
+        Ok(_) => Ok(Some(line)),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Connection between the coordinator and a worker.
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn connect(address: &str) -> io::Result<Self> {
+        match address.strip_prefix("unix:") {
+            #[cfg(unix)]
+            Some(path) => UnixStream::connect(path).map(Self::Unix),
+            #[cfg(not(unix))]
+            Some(_) => Err(io::ErrorKind::Unsupported.into()),
+            None => TcpStream::connect(address).map(Self::Tcp),
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Self::Tcp(stream) => stream.try_clone().map(Self::Tcp),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.try_clone().map(Self::Unix),
+        }
+    }
+
+    /// Blocks on reads for at most `timeout`, accepted streams may inherit non-blocking mode.
+    fn prepare(&self, timeout: Duration) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => {
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(timeout))
+            }
+            #[cfg(unix)]
+            Self::Unix(stream) => {
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(timeout))
+            }
+        }
+    }
+
+    fn peer(&self) -> String {
+        match self {
+            Self::Tcp(stream) => stream
+                .peer_addr()
+                .map_or("unknown address".to_owned(), |addr| addr.to_string()),
+            #[cfg(unix)]
+            Self::Unix(_) => "local socket".to_owned(),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    fn bind(address: &str) -> io::Result<Self> {
+        match address.strip_prefix("unix:") {
+            #[cfg(unix)]
+            Some(path) => {
+                // left behind by an earlier coordinator
+                let _ = fs::remove_file(path);
+                UnixListener::bind(path).map(Self::Unix)
+            }
+            #[cfg(not(unix))]
+            Some(_) => Err(io::ErrorKind::Unsupported.into()),
+            None => TcpListener::bind(address).map(Self::Tcp),
+        }
+    }
+
+    fn set_nonblocking(&self) -> io::Result<()> {
+        match self {
+            Self::Tcp(listener) => listener.set_nonblocking(true),
+            #[cfg(unix)]
+            Self::Unix(listener) => listener.set_nonblocking(true),
+        }
+    }
+
+    fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Self::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            #[cfg(unix)]
+            Self::Unix(listener) => listener.accept().map(|(stream, _)| Stream::Unix(stream)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Mutex, thread, time::Duration};
+
+    use bevy::math::{IVec3, UVec3};
+
+    use super::{read_line, search_cpu, work, Backend, Coordinator, Leases, Message};
+    use crate::{
+        constants::WORLD_HEIGHT,
+        finder::{
+            compute::TileSource,
+            plugin::{FinderJob, FinderStatus, PatternMatch},
+            tile::TileSettings,
+        },
+    };
+
+    /// A pattern of `size` blocks taken from the world, found at `pos` and only searched at
+    /// that height.
+    fn world_pattern(pos: IVec3, offset: IVec3, size: UVec3) -> FinderJob {
+        let job = FinderJob::from_world(pos + offset, size);
+        FinderJob { offset, ..job }.with_y_levels(vec![pos.y])
+    }
+
+    #[test]
+    fn test_leases() {
+        let mut leases = Leases::new(4, Some(10), 1);
+        let (first, tiles) = leases.issue().unwrap();
+        assert_eq!(tiles, 0..4);
+        let (second, tiles) = leases.issue().unwrap();
+        assert_eq!(tiles, 4..8);
+        leases.progress(second, 100);
+        // the worker of the first lease is lost, its tiles come before the later ones
+        leases.release(first);
+        let (first, tiles) = leases.issue().unwrap();
+        assert_eq!(tiles, 0..4);
+        assert_eq!(leases.issue().unwrap().1, 8..10);
+        assert!(leases.issue().is_none());
+        let found = PatternMatch {
+            pattern: 1,
+            pos: IVec3::X,
+        };
+        leases.finish(second, 300, Some(found));
+        assert_eq!(leases.blocks(), 300);
+        // the last lease doesn't matter once an earlier one has a match
+        assert!(!leases.is_finished());
+        leases.finish(first, 400, None);
+        assert!(leases.is_finished());
+        assert_eq!(leases.found, Some((4, found)));
+    }
+
+    #[test]
+    fn test_lease_retries() {
+        let mut leases = Leases::new(4, None, 1);
+        let (first, _) = leases.issue().unwrap();
+        let (second, _) = leases.issue().unwrap();
+        leases.release(first);
+        let (first, tiles) = leases.issue().unwrap();
+        assert_eq!(tiles, 0..4);
+        assert!(leases.is_needed(second));
+        // lost a second time, the search can't finish anymore
+        leases.release(first);
+        assert_eq!(leases.failed, Some(0..4));
+        assert!(leases.issue().is_none());
+        assert!(leases.is_finished());
+        assert!(!leases.is_needed(second));
+    }
+
+    #[test]
+    fn test_lease_after_match() {
+        let mut leases = Leases::new(4, None, 0);
+        let (first, _) = leases.issue().unwrap();
+        let (second, _) = leases.issue().unwrap();
+        let found = PatternMatch {
+            pattern: 0,
+            pos: IVec3::ZERO,
+        };
+        leases.finish(first, 100, Some(found));
+        // the tiles after the match aren't needed, losing them doesn't fail the search
+        leases.release(second);
+        assert_eq!(leases.failed, None);
+        assert!(leases.issue().is_none());
+        assert!(leases.is_finished());
+        assert_eq!(leases.found, Some((0, found)));
+    }
+
+    #[test]
+    fn test_search_cpu_y_levels() {
+        let tile = TileSettings {
+            size: 64,
+            height: WORLD_HEIGHT as u32,
+            workgroup_size: 64,
+        };
+        let pos = IVec3::new(20, 150, 30);
+        let job = world_pattern(pos, IVec3::ONE, UVec3::new(4, 2, 4));
+        let search = |job: &FinderJob| {
+            let status = Mutex::new(FinderStatus::WaitingForJob);
+            search_cpu(tile, job, &TileSource::range(0..1), &status)
+        };
+        assert_eq!(
+            search(&job).unwrap(),
+            Some(PatternMatch { pattern: 0, pos })
+        );
+        let job = job.with_y_levels(vec![pos.y - 1, pos.y + 1]);
+        assert_eq!(search(&job).unwrap(), None);
+        assert!(search(&job.with_y_levels(vec![1000])).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_coordinator() {
+        // the default tiles are 1024 blocks wide, a single layer of them is quick to generate
+        let pos = IVec3::new(-700, 90, 1500);
+        let job = world_pattern(pos, IVec3::new(2, 0, 3), UVec3::new(4, 1, 4));
+        let coordinator = Coordinator::new(job, Some(2048)).unwrap();
+        let path = std::env::temp_dir().join(format!("coordinator_test_{}", std::process::id()));
+        let address = format!("unix:{}", path.display());
+        let status = Mutex::new(FinderStatus::WaitingForJob);
+        let found = thread::scope(|scope| {
+            let search = scope.spawn(|| coordinator.run(&address, &status));
+            let workers: Vec<_> = (0..2)
+                .map(|_| {
+                    scope.spawn(|| {
+                        while !path.exists() {
+                            thread::sleep(Duration::from_millis(10));
+                        }
+                        work(&address, Backend::Cpu)
+                    })
+                })
+                .collect();
+            for worker in workers {
+                worker.join().unwrap().unwrap();
+            }
+            search.join().unwrap()
+        });
+        assert_eq!(found.unwrap(), Some(PatternMatch { pattern: 0, pos }));
+        assert!(matches!(
+            *status.lock().unwrap(),
+            FinderStatus::Finished { pos: found, .. } if found == pos
+        ));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_messages() {
+        let job = FinderJob {
+            size: UVec3::new(2, 1, 1),
+            offset: IVec3::new(0, -3, 5),
+            rotations: vec![0x42, 0, 0, 0x23],
+            y_levels: vec![62, -10],
+        };
+        let messages = [
+            Message::Job {
+                tile_size: 1024,
+                job,
+            },
+            Message::Hello {
+                name: "GPU 0, GPU 1".to_owned(),
+            },
+            Message::Lease {
+                id: 3,
+                tiles: 16..32,
+            },
+            Message::Progress {
+                id: 3,
+                blocks: 1 << 40,
+            },
+            Message::Done {
+                id: 3,
+                blocks: 7,
+                found: Some(PatternMatch {
+                    pattern: 1,
+                    pos: IVec3::new(-5, 64, 1),
+                }),
+            },
+            Message::Done {
+                id: 4,
+                blocks: 7,
+                found: None,
+            },
+            Message::Stop,
+        ];
+        for message in messages {
+            let line = message.to_line();
+            assert_eq!(Message::parse(&line).unwrap().to_line(), line);
+        }
+        assert!(Message::parse("lease 1 2").is_err());
+        let mut reader = "stop\nprogress 1 2\n".as_bytes();
+        assert_eq!(
+            read_line(&mut reader, 5).unwrap().as_deref(),
+            Some("stop\n")
+        );
+        assert!(read_line(&mut reader, 5).is_err());
+        assert_eq!(read_line(&mut "".as_bytes(), 5).unwrap(), None);
+        assert!(Message::parse("stop now").is_err());
+        assert!(Message::parse("job 1024 2 1 1 0 0 0 - 42").is_err());
+        assert!(Message::parse("job 1024 2 1 1 0 0 0 - 420000").is_err());
+        assert!(Message::parse("done 3 7 -5 64 1").is_err());
+    }
+}
@@ -1,12 +1,20 @@
+pub mod cache;
 pub mod chunk;
+pub mod cluster;
+pub mod compute;
+pub mod distributed;
 pub mod plugin;
+pub mod prefetch;
+pub mod queue;
+pub mod region;
+pub mod tile;
 
 pub mod util;
 
 // Bits: aaaabbbb
 // a: MaxRotation,
 // b: Rotation
-#[derive(Clone, Copy, Hash, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub struct Rotation(pub u8);
 
 impl Rotation {
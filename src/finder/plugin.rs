@@ -1,14 +1,298 @@
 mod gpu;
 
-use std::time::{Duration, Instant};
+use std::{
+    env,
+    ops::Range,
+    slice::ChunksExact,
+    time::{Duration, Instant},
+};
 
-use crate::constants::GRID_SIZE;
+use crate::constants::{MAX_PATTERN_SIZE, WORLD_HEIGHT};
 
-use bevy::{math::IVec3, prelude::Resource, render::extract_resource::ExtractResource};
+use bevy::{
+    log::warn,
+    math::{IVec3, UVec3},
+    prelude::Resource,
+};
+use bevy_meshem::{
+    prelude::{one_d_cords, three_d_cords},
+    Dimensions,
+};
 pub use gpu::GPUFinderPlugin;
 
-#[derive(Resource, Clone, ExtractResource)]
-pub struct FinderJob(pub Box<[u8; GRID_SIZE.0 * GRID_SIZE.1 * GRID_SIZE.2]>);
+use super::{tile::TileSettings, util::get_block_rotation, Rotation};
+
+/// Patterns the finder searches for.
+///
+/// `rotations` holds the patterns one after another, each laid out like `one_d_cords` with
+/// `size` as dimensions. The found position is reported for the cell at `-offset`.
+#[derive(Clone, Debug)]
+pub struct FinderJob {
+    pub size: UVec3,
+    pub offset: IVec3,
+    pub rotations: Vec<u8>,
+    /// Heights the found position may be at, every height is searched when it's empty.
+    pub y_levels: Vec<i32>,
+}
+
+impl FinderJob {
+    /// Crops the rotations of a `dimensions` sized pattern to the blocks that constrain the
+    /// position.
+    pub fn from_rotations(dimensions: Dimensions, rotations: &[u8]) -> Self {
+        let constrained: Vec<UVec3> = rotations
+            .iter()
+            .enumerate()
+            .filter(|(_, rotation)| Rotation(**rotation).get_max_rotation() > 1)
+            .map(|(index, _)| {
+                let (x, y, z) = three_d_cords(index, dimensions);
+                UVec3::new(x as u32, y as u32, z as u32)
+            })
+            .collect();
+        let Some(min) = constrained.iter().copied().reduce(UVec3::min) else {
+            return Self {
+                size: UVec3::ONE,
+                offset: IVec3::ZERO,
+                rotations: vec![0],
+                y_levels: Vec::new(),
+            };
+        };
+        let max = constrained.iter().copied().fold(min, UVec3::max);
+        let size = max - min + UVec3::ONE;
+        let dims = (size.x as usize, size.y as usize, size.z as usize);
+        let mut cropped = vec![0; dims.0 * dims.1 * dims.2];
+        for pos in constrained {
+            let local = pos - min;
+            cropped[one_d_cords([local.x, local.y, local.z].map(|v| v as usize), dims)] =
+                rotations[one_d_cords([pos.x, pos.y, pos.z].map(|v| v as usize), dimensions)];
+        }
+        Self {
+            size,
+            offset: min.as_ivec3(),
+            rotations: cropped,
+            y_levels: Vec::new(),
+        }
+    }
+
+    /// Searches all patterns of `jobs` at once, so every tile is only generated once.
+    ///
+    /// The patterns are padded to a box covering all of them, keeping their offsets, so the
+    /// found positions are the same as when they are searched on their own. The y levels of the
+    /// first job are used.
+    pub fn combine(jobs: &[FinderJob]) -> Result<Self, String> {
+        let Some(first) = jobs.first() else {
+            return Err("no patterns to search".to_owned());
+        };
+        let min = jobs
+            .iter()
+            .map(|job| job.offset)
+            .reduce(IVec3::min)
+            .unwrap();
+        let max = jobs
+            .iter()
+            .map(|job| job.offset + job.size.as_ivec3())
+            .reduce(IVec3::max)
+            .unwrap();
+        let size = (max - min).as_uvec3();
+        if !Self::fits(size) {
+            return Err(format!(
+                "the patterns need a {size} box to be searched together"
+            ));
+        }
+        let dims = (size.x as usize, size.y as usize, size.z as usize);
+        let volume = dims.0 * dims.1 * dims.2;
+        let mut rotations = vec![0; volume * jobs.iter().map(Self::pattern_count).sum::<usize>()];
+        let mut patterns = rotations.chunks_exact_mut(volume);
+        for job in jobs {
+            let shift = (job.offset - min).as_uvec3();
+            for pattern in job.patterns() {
+                let combined = patterns.next().unwrap();
+                for (index, rotation) in pattern.iter().enumerate() {
+                    let (x, y, z) = three_d_cords(index, job.dimensions());
+                    let pos = UVec3::new(x as u32, y as u32, z as u32) + shift;
+                    combined[one_d_cords([pos.x, pos.y, pos.z].map(|v| v as usize), dims)] =
+                        *rotation;
+                }
+            }
+        }
+        Ok(Self {
+            size,
+            offset: min,
+            rotations,
+            y_levels: first.y_levels.clone(),
+        })
+    }
+
+    /// Job with the rotations of the blocks of the world in a box of `size` at `pos`, which it
+    /// is found at, like a pattern copied from a world.
+    pub fn from_world(pos: IVec3, size: UVec3) -> Self {
+        let rotations = (0..size.y as i32)
+            .flat_map(|y| {
+                (0..size.z as i32)
+                    .flat_map(move |z| (0..size.x as i32).map(move |x| pos + IVec3::new(x, y, z)))
+            })
+            .map(|block| {
+                let rotation = get_block_rotation(block.x as i64, block.y as i64, block.z as i64);
+                Rotation::new(rotation, 4).0
+            })
+            .collect();
+        Self {
+            size,
+            offset: IVec3::ZERO,
+            rotations,
+            y_levels: Vec::new(),
+        }
+    }
+
+    /// The rotations of each pattern, in the order they were combined.
+    pub fn patterns(&self) -> ChunksExact<'_, u8> {
+        let volume = self.size.x as usize * self.size.y as usize * self.size.z as usize;
+        self.rotations.chunks_exact(volume.max(1))
+    }
+
+    pub fn pattern_count(&self) -> usize {
+        self.patterns().len()
+    }
+
+    /// Only searches the given heights, like the surface where players usually see the blocks.
+    pub fn with_y_levels(mut self, y_levels: Vec<i32>) -> Self {
+        self.y_levels = y_levels;
+        self
+    }
+
+    /// Uses the heights in `FINDER_Y_LEVELS`, see `parse_y_levels`.
+    pub fn with_y_levels_from_env(self) -> Self {
+        let Ok(value) = env::var("FINDER_Y_LEVELS") else {
+            return self;
+        };
+        match parse_y_levels(&value) {
+            Ok(y_levels) => self.with_y_levels(y_levels),
+            Err(err) => {
+                warn!("{err}, searching every height");
+                self
+            }
+        }
+    }
+
+    /// Heights inside a tile of `height` blocks at which the first layer of `rotations` is
+    /// checked, in order.
+    pub fn layers(&self, height: u32) -> Vec<u32> {
+        let Some(last) = height.checked_sub(self.size.y) else {
+            return Vec::new();
+        };
+        if self.y_levels.is_empty() {
+            return (0..=last).collect();
+        }
+        let mut layers: Vec<u32> = self
+            .y_levels
+            .iter()
+            .filter_map(|y| u32::try_from(y.checked_add(self.offset.y)?).ok())
+            .filter(|layer| *layer <= last)
+            .collect();
+        layers.sort_unstable();
+        layers.dedup();
+        layers
+    }
+
+    /// Heights of the blocks inside a tile of `height` blocks that the pattern covers at its
+    /// `layers`, none if it fits at none of them.
+    pub fn heights(&self, height: u32) -> Option<Range<u32>> {
+        let layers = self.layers(height);
+        Some(*layers.first()?..layers.last()? + self.size.y)
+    }
+
+    /// Positions checked in a tile, only at the heights of `layers`.
+    pub fn blocks_per_tile(&self, tile: &TileSettings) -> u64 {
+        let positions = tile.positions(self.size);
+        positions.x as u64 * self.layers(tile.height).len() as u64 * positions.z as u64
+    }
+
+    /// Whether a pattern of this size can be searched, the GPU tiles may further limit it.
+    pub fn fits(size: UVec3) -> bool {
+        size.cmple(UVec3::new(
+            MAX_PATTERN_SIZE.0 as u32,
+            MAX_PATTERN_SIZE.1 as u32,
+            MAX_PATTERN_SIZE.2 as u32,
+        ))
+        .all()
+            && size.cmpgt(UVec3::ZERO).all()
+    }
+
+    pub fn dimensions(&self) -> Dimensions {
+        (
+            self.size.x as usize,
+            self.size.y as usize,
+            self.size.z as usize,
+        )
+    }
+}
+
+/// Parses heights like `62,64..=70,100..110` for `FinderJob::with_y_levels`.
+///
+/// Ranges are cut to the heights a pattern can be found at, the position is reported for the
+/// cell at `-offset`, so up to the world height below the ground.
+pub fn parse_y_levels(value: &str) -> Result<Vec<i32>, String> {
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<i32>()
+            .map_err(|_| format!("invalid y level {v}"))
+    };
+    let clamp = |y: i32| y.clamp(-(WORLD_HEIGHT as i32), WORLD_HEIGHT as i32);
+    let mut y_levels = Vec::new();
+    for part in value.split(',') {
+        if let Some((start, end)) = part.split_once("..=") {
+            y_levels.extend(clamp(parse(start)?)..clamp(parse(end)?.saturating_add(1)));
+        } else if let Some((start, end)) = part.split_once("..") {
+            y_levels.extend(clamp(parse(start)?)..clamp(parse(end)?));
+        } else {
+            y_levels.push(parse(part)?);
+        }
+    }
+    Ok(y_levels)
+}
+
+/// How the GPU finder checks the positions of a tile, picked with `FINDER_KERNEL`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FinderKernel {
+    /// Generates the rotations of the whole tile, then matches the pattern against them.
+    #[default]
+    TwoPass,
+    /// Only computes the rotations of the constrained blocks while matching.
+    Fused,
+    /// Alternates between both kernels with every batch of tiles and logs how long the GPU
+    /// takes for their tiles.
+    Benchmark,
+}
+
+impl FinderKernel {
+    pub fn from_env() -> Self {
+        match env::var("FINDER_KERNEL").as_deref() {
+            Err(_) | Ok("two-pass") => Self::TwoPass,
+            Ok("fused") => Self::Fused,
+            Ok("benchmark") => Self::Benchmark,
+            Ok(value) => {
+                warn!("unknown FINDER_KERNEL {value}, expected two-pass, fused or benchmark");
+                Self::default()
+            }
+        }
+    }
+
+    /// Kernel that checks the tiles of the batch with this index.
+    pub fn for_batch(self, index: u32) -> Self {
+        match self {
+            Self::Benchmark if index % 2 == 1 => Self::Fused,
+            Self::Benchmark => Self::TwoPass,
+            kernel => kernel,
+        }
+    }
+}
+
+/// Position found by the finder and the pattern of the job that is there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PatternMatch {
+    /// index of the pattern in `FinderJob::patterns`
+    pub pattern: usize,
+    pub pos: IVec3,
+}
 
 #[derive(Resource, Clone, Copy, Debug)]
 pub enum FinderStatus {
@@ -20,6 +304,90 @@ pub enum FinderStatus {
     Finished {
         searched_blocks: u64,
         pos: IVec3,
+        /// index of the pattern found at `pos`
+        pattern: usize,
         time: Duration,
     },
 }
+
+impl FinderStatus {
+    pub fn searched_blocks(&self) -> u64 {
+        match *self {
+            Self::WaitingForJob => 0,
+            Self::Running { blocks, .. } => blocks,
+            Self::Finished {
+                searched_blocks, ..
+            } => searched_blocks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::math::{IVec3, UVec3};
+
+    use super::{parse_y_levels, FinderJob};
+
+    #[test]
+    fn test_y_levels() {
+        assert_eq!(
+            parse_y_levels("3, -1..1,5..=7").unwrap(),
+            vec![3, -1, 0, 5, 6, 7]
+        );
+        assert!(parse_y_levels("1..x").is_err());
+        // huge ranges stop at the world height
+        assert_eq!(parse_y_levels("318..2147483647").unwrap(), vec![318, 319]);
+        assert_eq!(
+            parse_y_levels("-2147483648..=-319").unwrap(),
+            vec![-320, -319]
+        );
+        assert_eq!(
+            parse_y_levels("1000..=2147483647").unwrap(),
+            Vec::<i32>::new()
+        );
+        let job = FinderJob {
+            size: UVec3::new(1, 4, 1),
+            offset: IVec3::new(0, 2, 0),
+            rotations: vec![0; 4],
+            y_levels: Vec::new(),
+        };
+        assert_eq!(job.layers(8), vec![0, 1, 2, 3, 4]);
+        // the first layer of the rotations is 2 blocks above the reported position
+        let job = job.with_y_levels(vec![3, -2, 1, 2, -5, 1]);
+        assert_eq!(job.layers(8), vec![0, 3, 4]);
+        assert_eq!(job.heights(8), Some(0..8));
+        assert!(job.layers(3).is_empty());
+        assert_eq!(job.heights(3), None);
+        let job = job.with_y_levels(vec![2, i32::MAX]);
+        assert_eq!(job.layers(8), vec![4]);
+        assert_eq!(job.heights(8), Some(4..8));
+    }
+
+    #[test]
+    fn test_combine() {
+        let first = FinderJob {
+            size: UVec3::new(2, 1, 1),
+            offset: IVec3::new(1, 0, 0),
+            rotations: vec![0x41, 0x42],
+            y_levels: vec![64],
+        };
+        let second = FinderJob {
+            size: UVec3::new(1, 2, 1),
+            offset: IVec3::new(0, 1, 0),
+            rotations: vec![0x43, 0x20],
+            y_levels: Vec::new(),
+        };
+        let job = FinderJob::combine(&[first, second]).unwrap();
+        assert_eq!(job.size, UVec3::new(3, 3, 1));
+        assert_eq!(job.offset, IVec3::ZERO);
+        assert_eq!(job.y_levels, vec![64]);
+        assert_eq!(job.pattern_count(), 2);
+        let patterns: Vec<&[u8]> = job.patterns().collect();
+        assert_eq!(patterns[0], [0, 0x41, 0x42, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(patterns[1], [0, 0, 0, 0x43, 0, 0, 0x20, 0, 0]);
+        // combining again keeps the patterns where they are
+        let again = FinderJob::combine(std::slice::from_ref(&job)).unwrap();
+        assert_eq!(again.rotations, job.rotations);
+        assert!(FinderJob::combine(&[]).is_err());
+    }
+}
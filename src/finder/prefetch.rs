@@ -0,0 +1,246 @@
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+/// Produces the items `0..count` on a pool of threads and hands them out in order, with at most
+/// `slots` items generated ahead of the consumer.
+///
+/// A panic while generating an item is raised again when the consumer gets to that item.
+pub struct Prefetcher<T> {
+    workers: Vec<JoinHandle<()>>,
+    shared: Arc<Shared<T>>,
+}
+
+struct Shared<T> {
+    queue: Mutex<Queue<T>>,
+    /// signalled whenever an item is ready or a slot frees up
+    changed: Condvar,
+}
+
+struct Queue<T> {
+    count: u32,
+    slots: u32,
+    /// next item a worker starts on
+    next_claimed: u32,
+    /// next item handed out
+    next_out: u32,
+    /// finished items that are waiting for the ones before them
+    ready: BTreeMap<u32, T>,
+    /// the item whose generator panicked, with the panic
+    panicked: Option<(u32, Box<dyn Any + Send>)>,
+    generated: u32,
+    start_time: Instant,
+}
+
+/// State of a `Prefetcher`, for showing how well generation keeps up.
+#[derive(Clone, Copy, Debug)]
+pub struct PrefetchMetrics {
+    /// items ready to be taken, including those waiting for an earlier one
+    pub queue_depth: usize,
+    /// items being generated right now
+    pub in_progress: u32,
+    pub generated: u32,
+    /// items generated per second since the start
+    pub rate: f64,
+}
+
+impl<T: Send + 'static> Prefetcher<T> {
+    /// Since every thread works on an item in a slot, no more than `slots` threads are started.
+    pub fn new(
+        count: u32,
+        slots: u32,
+        threads: u32,
+        generate: impl Fn(u32) -> T + Send + Sync + 'static,
+    ) -> Self {
+        assert!(slots > 0, "the prefetch queue needs at least one slot");
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                count,
+                slots,
+                next_claimed: 0,
+                next_out: 0,
+                ready: BTreeMap::new(),
+                panicked: None,
+                generated: 0,
+                start_time: Instant::now(),
+            }),
+            changed: Condvar::new(),
+        });
+        let generate = Arc::new(generate);
+        let workers = (0..threads.clamp(1, slots))
+            .map(|_| {
+                let shared = shared.clone();
+                let generate = generate.clone();
+                thread::spawn(move || shared.work(generate.as_ref()))
+            })
+            .collect();
+        Self { workers, shared }
+    }
+
+    /// Takes the next item if it's ready.
+    pub fn try_next(&mut self) -> Option<T> {
+        let mut queue = self.shared.queue.lock().unwrap();
+        let item = queue.take();
+        if item.is_some() {
+            self.shared.changed.notify_all();
+        } else if let Some(payload) = queue.take_panic() {
+            drop(queue);
+            panic::resume_unwind(payload);
+        }
+        item
+    }
+
+    /// Whether every item has been generated.
+    pub fn is_finished(&self) -> bool {
+        self.workers.iter().all(|worker| worker.is_finished())
+    }
+
+    pub fn metrics(&self) -> PrefetchMetrics {
+        let queue = self.shared.queue.lock().unwrap();
+        PrefetchMetrics {
+            queue_depth: queue.ready.len(),
+            in_progress: queue.next_claimed - queue.next_out - queue.ready.len() as u32,
+            generated: queue.generated,
+            rate: queue.generated as f64 / queue.start_time.elapsed().as_secs_f64(),
+        }
+    }
+}
+
+impl<T> Drop for Prefetcher<T> {
+    fn drop(&mut self) {
+        // the workers stop after the items they are generating
+        let mut queue = self.shared.queue.lock().unwrap();
+        queue.count = queue.next_claimed;
+        self.shared.changed.notify_all();
+    }
+}
+
+impl<T> Shared<T> {
+    fn work(&self, generate: &impl Fn(u32) -> T) {
+        loop {
+            let mut queue = self.queue.lock().unwrap();
+            // items already claimed count against the slots too, so memory stays bounded
+            while queue.next_claimed < queue.count
+                && queue.next_claimed - queue.next_out >= queue.slots
+            {
+                queue = self.changed.wait(queue).unwrap();
+            }
+            if queue.next_claimed >= queue.count {
+                return;
+            }
+            let index = queue.next_claimed;
+            queue.next_claimed += 1;
+            drop(queue);
+            let item = panic::catch_unwind(AssertUnwindSafe(|| generate(index)));
+            let mut queue = self.queue.lock().unwrap();
+            match item {
+                Ok(item) => {
+                    queue.ready.insert(index, item);
+                    queue.generated += 1;
+                }
+                Err(payload) => {
+                    // the items after it are never handed out
+                    queue.count = queue.next_claimed;
+                    queue.panicked = Some((index, payload));
+                }
+            }
+            self.changed.notify_all();
+        }
+    }
+}
+
+impl<T> Queue<T> {
+    fn take(&mut self) -> Option<T> {
+        let item = self.ready.remove(&self.next_out)?;
+        self.next_out += 1;
+        Some(item)
+    }
+
+    /// The panic of the next item, once the ones before it have been handed out. Nothing is
+    /// handed out after it.
+    fn take_panic(&mut self) -> Option<Box<dyn Any + Send>> {
+        match self.panicked.take()? {
+            (index, payload) if index == self.next_out => {
+                self.count = self.next_out;
+                self.ready.clear();
+                Some(payload)
+            }
+            panicked => {
+                self.panicked = Some(panicked);
+                None
+            }
+        }
+    }
+}
+
+impl<T: Send + 'static> Iterator for Prefetcher<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut queue = self.shared.queue.lock().unwrap();
+        loop {
+            if let Some(item) = queue.take() {
+                self.shared.changed.notify_all();
+                return Some(item);
+            }
+            if let Some(payload) = queue.take_panic() {
+                drop(queue);
+                panic::resume_unwind(payload);
+            }
+            if queue.next_out >= queue.count {
+                return None;
+            }
+            queue = self.shared.changed.wait(queue).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        thread,
+        time::Duration,
+    };
+
+    use super::Prefetcher;
+
+    #[test]
+    fn test_prefetch_order() {
+        let mut prefetcher = Prefetcher::new(40, 3, 4, |index| {
+            // later items are often faster than earlier ones
+            thread::sleep(Duration::from_millis((index * 7 % 5) as u64));
+            index
+        });
+        thread::sleep(Duration::from_millis(50));
+        let metrics = prefetcher.metrics();
+        assert!(metrics.queue_depth as u32 + metrics.in_progress <= 3);
+        assert!(prefetcher.by_ref().eq(0..40));
+        assert!(prefetcher.try_next().is_none());
+        assert_eq!(prefetcher.metrics().generated, 40);
+    }
+
+    #[test]
+    fn test_prefetch_panic() {
+        let mut prefetcher = Prefetcher::new(40, 3, 3, |index| {
+            assert!(index != 5, "broken item");
+            index
+        });
+        let mut items = Vec::new();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            for item in prefetcher.by_ref() {
+                items.push(item);
+            }
+        }));
+        // the items before the broken one are still handed out
+        assert_eq!(items, (0..5).collect::<Vec<_>>());
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"broken item"));
+        assert!(prefetcher.next().is_none());
+    }
+}
@@ -0,0 +1,412 @@
+use std::{
+    cmp::Reverse,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+use bevy::{log::info, prelude::Resource};
+
+use super::{
+    compute::{ComputeRunner, TileSource},
+    plugin::{FinderJob, FinderStatus, PatternMatch},
+};
+
+/// Searches submitted jobs one after another, so they don't compete for the GPUs.
+///
+/// Jobs with a higher priority are started first, jobs of the same priority in the order they
+/// were submitted. The adapters are opened with the first job, like `ComputeRunner::from_env`.
+#[derive(Resource, Clone)]
+pub struct JobQueue {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    jobs: Mutex<Jobs>,
+    /// signalled whenever a job is submitted
+    submitted: Condvar,
+}
+
+/// id, job, radius and status of a job whose search starts
+type StartedJob = (u32, FinderJob, Option<u64>, Arc<Mutex<FinderStatus>>);
+
+#[derive(Default)]
+struct Jobs {
+    next_id: u32,
+    entries: Vec<QueuedJob>,
+}
+
+struct QueuedJob {
+    id: u32,
+    name: String,
+    job: FinderJob,
+    radius: Option<u64>,
+    priority: i32,
+    state: JobState,
+    status: Arc<Mutex<FinderStatus>>,
+    /// tiles of the running search, for cancelling it
+    source: Option<Arc<TileSource>>,
+    /// blocks within the radius, known once the job starts
+    total_blocks: Option<u64>,
+    /// time the search took, once it's done
+    elapsed: Option<Duration>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JobState {
+    Queued,
+    Running,
+    Found(PatternMatch),
+    NotFound,
+    Cancelled,
+    Failed(String),
+}
+
+impl JobState {
+    pub fn is_done(&self) -> bool {
+        !matches!(self, Self::Queued | Self::Running)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Running => "running",
+            Self::Found(_) => "found",
+            Self::NotFound => "not found",
+            Self::Cancelled => "cancelled",
+            Self::Failed(_) => "failed",
+        }
+    }
+}
+
+/// Snapshot of a job and its progress.
+#[derive(Clone, Debug)]
+pub struct JobInfo {
+    pub id: u32,
+    pub name: String,
+    pub priority: i32,
+    pub state: JobState,
+    pub blocks: u64,
+    pub total_blocks: Option<u64>,
+    pub elapsed: Duration,
+    /// blocks searched per second
+    pub rate: f64,
+    /// time until every block within the radius is searched
+    pub eta: Option<Duration>,
+}
+
+impl JobQueue {
+    pub fn new() -> Self {
+        let queue = Self {
+            shared: Arc::new(Shared {
+                jobs: Mutex::default(),
+                submitted: Condvar::new(),
+            }),
+        };
+        let shared = queue.shared.clone();
+        thread::spawn(move || shared.work());
+        queue
+    }
+
+    /// Queues a search of the tiles within `radius` blocks of 0,0, or of the whole spiral, and
+    /// returns its id.
+    pub fn submit(&self, name: String, job: FinderJob, radius: Option<u64>, priority: i32) -> u32 {
+        let id = self
+            .shared
+            .jobs
+            .lock()
+            .unwrap()
+            .submit(name, job, radius, priority);
+        self.shared.submitted.notify_all();
+        id
+    }
+
+    /// Cancels a queued or running job, returns whether it was still searching.
+    pub fn cancel(&self, id: u32) -> bool {
+        self.shared.jobs.lock().unwrap().cancel(id)
+    }
+
+    /// Changes the priority of a job that hasn't started yet, returns whether it's still queued.
+    pub fn set_priority(&self, id: u32, priority: i32) -> bool {
+        self.shared.jobs.lock().unwrap().set_priority(id, priority)
+    }
+
+    pub fn get(&self, id: u32) -> Option<JobInfo> {
+        self.shared.jobs.lock().unwrap().get(id)
+    }
+
+    /// Progress of a job, like the status `ComputeRunner::run_source` keeps up to date.
+    pub fn status(&self, id: u32) -> Option<FinderStatus> {
+        let mut jobs = self.shared.jobs.lock().unwrap();
+        jobs.entry(id).map(|entry| *entry.status.lock().unwrap())
+    }
+
+    /// Every submitted job, in the order they were submitted.
+    pub fn jobs(&self) -> Vec<JobInfo> {
+        let jobs = self.shared.jobs.lock().unwrap();
+        jobs.entries.iter().map(QueuedJob::info).collect()
+    }
+}
+
+impl Default for JobQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shared {
+    fn work(&self) {
+        let mut runners = None;
+        loop {
+            let (id, job, radius, status) = self.next_job();
+            info!("searching job {id}");
+            let result = match &mut runners {
+                Some(runners) => Ok(runners),
+                None => ComputeRunner::from_env().map(|opened| runners.insert(opened)),
+            }
+            .and_then(|runners| {
+                let tile = runners[0].tile();
+                let max_tiles = radius.map(|radius| tile.tiles_for_radius(radius, job.size));
+                let source = Arc::new(TileSource::new(max_tiles));
+                self.update(id, |entry| {
+                    entry.total_blocks =
+                        max_tiles.map(|tiles| tiles as u64 * job.blocks_per_tile(&tile));
+                    // cancelled while the adapters were opened
+                    if entry.state == JobState::Cancelled {
+                        source.cancel();
+                    }
+                    entry.source = Some(source.clone());
+                });
+                ComputeRunner::run_source(runners, &job, source, &status)
+            });
+            self.jobs.lock().unwrap().finish(id, result);
+        }
+    }
+
+    /// Waits for the next queued job and marks it as running.
+    fn next_job(&self) -> StartedJob {
+        let mut jobs = self.jobs.lock().unwrap();
+        loop {
+            if let Some(next) = jobs.start_next() {
+                return next;
+            }
+            jobs = self.submitted.wait(jobs).unwrap();
+        }
+    }
+
+    fn update(&self, id: u32, update: impl FnOnce(&mut QueuedJob)) {
+        if let Some(entry) = self.jobs.lock().unwrap().entry(id) {
+            update(entry);
+        }
+    }
+}
+
+impl Jobs {
+    fn submit(&mut self, name: String, job: FinderJob, radius: Option<u64>, priority: i32) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.push(QueuedJob {
+            id,
+            name,
+            job,
+            radius,
+            priority,
+            state: JobState::Queued,
+            status: Arc::new(Mutex::new(FinderStatus::WaitingForJob)),
+            source: None,
+            total_blocks: None,
+            elapsed: None,
+        });
+        id
+    }
+
+    fn entry(&mut self, id: u32) -> Option<&mut QueuedJob> {
+        self.entries.iter_mut().find(|entry| entry.id == id)
+    }
+
+    fn cancel(&mut self, id: u32) -> bool {
+        let Some(entry) = self.entry(id) else {
+            return false;
+        };
+        match (&entry.state, &entry.source) {
+            // the search ends once the tiles in flight are read
+            (JobState::Running, Some(source)) => source.cancel(),
+            (JobState::Queued | JobState::Running, _) => entry.state = JobState::Cancelled,
+            _ => return false,
+        }
+        true
+    }
+
+    fn set_priority(&mut self, id: u32, priority: i32) -> bool {
+        match self.entry(id) {
+            Some(entry) if entry.state == JobState::Queued => {
+                entry.priority = priority;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn get(&self, id: u32) -> Option<JobInfo> {
+        self.entries
+            .iter()
+            .find(|entry| entry.id == id)
+            .map(QueuedJob::info)
+    }
+
+    /// Marks the queued job with the highest priority as running, the earliest one of them.
+    fn start_next(&mut self) -> Option<StartedJob> {
+        let entry = self
+            .entries
+            .iter_mut()
+            .filter(|entry| entry.state == JobState::Queued)
+            .max_by_key(|entry| (entry.priority, Reverse(entry.id)))?;
+        entry.state = JobState::Running;
+        Some((
+            entry.id,
+            entry.job.clone(),
+            entry.radius,
+            entry.status.clone(),
+        ))
+    }
+
+    /// Records how the search of job `id` ended, cancelled jobs stay cancelled unless they
+    /// found something or failed before stopping.
+    fn finish(&mut self, id: u32, result: Result<Option<PatternMatch>, String>) {
+        let Some(entry) = self.entry(id) else {
+            return;
+        };
+        let cancelled = entry.state == JobState::Cancelled
+            || entry
+                .source
+                .as_ref()
+                .is_some_and(|source| source.is_cancelled());
+        entry.elapsed = Some(entry.info().elapsed);
+        entry.state = match result {
+            Ok(Some(found)) => JobState::Found(found),
+            Err(err) => JobState::Failed(err),
+            Ok(None) if cancelled => JobState::Cancelled,
+            Ok(None) => JobState::NotFound,
+        };
+        entry.source = None;
+    }
+}
+
+impl QueuedJob {
+    fn info(&self) -> JobInfo {
+        let (blocks, elapsed) = match *self.status.lock().unwrap() {
+            FinderStatus::WaitingForJob => (0, Duration::ZERO),
+            FinderStatus::Running { blocks, start_time } => {
+                (blocks, self.elapsed.unwrap_or(start_time.elapsed()))
+            }
+            FinderStatus::Finished {
+                searched_blocks,
+                time,
+                ..
+            } => (searched_blocks, time),
+        };
+        let rate = match elapsed.is_zero() {
+            true => 0.0,
+            false => blocks as f64 / elapsed.as_secs_f64(),
+        };
+        let eta = match (&self.state, self.total_blocks) {
+            (JobState::Running, Some(total)) if rate > 0.0 => Some(Duration::from_secs_f64(
+                total.saturating_sub(blocks) as f64 / rate,
+            )),
+            _ => None,
+        };
+        JobInfo {
+            id: self.id,
+            name: self.name.clone(),
+            priority: self.priority,
+            state: self.state.clone(),
+            blocks,
+            total_blocks: self.total_blocks,
+            elapsed,
+            rate,
+            eta,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use bevy::math::IVec3;
+
+    use super::{JobState, Jobs};
+    use crate::finder::{
+        compute::TileSource,
+        plugin::{FinderJob, PatternMatch},
+    };
+
+    fn submit(jobs: &mut Jobs, priority: i32) -> u32 {
+        let job = FinderJob::from_rotations((1, 1, 1), &[0x42]);
+        jobs.submit(format!("priority {priority}"), job, Some(1000), priority)
+    }
+
+    fn state(jobs: &Jobs, id: u32) -> JobState {
+        jobs.get(id).unwrap().state
+    }
+
+    #[test]
+    fn test_job_order() {
+        let mut jobs = Jobs::default();
+        let first = submit(&mut jobs, 0);
+        let second = submit(&mut jobs, 0);
+        let urgent = submit(&mut jobs, 5);
+        let cancelled = submit(&mut jobs, 9);
+        assert!(jobs.cancel(cancelled));
+        assert_eq!(state(&jobs, first), JobState::Queued);
+        // higher priorities first, then in the order they were submitted
+        assert_eq!(jobs.start_next().unwrap().0, urgent);
+        assert_eq!(state(&jobs, urgent), JobState::Running);
+        assert!(!jobs.set_priority(urgent, 10));
+        assert!(jobs.set_priority(second, 1));
+        assert_eq!(jobs.start_next().unwrap().0, second);
+        assert_eq!(jobs.start_next().unwrap().0, first);
+        assert!(jobs.start_next().is_none());
+        assert_eq!(state(&jobs, cancelled), JobState::Cancelled);
+        assert!(!jobs.cancel(cancelled));
+        assert!(!jobs.cancel(100));
+    }
+
+    #[test]
+    fn test_job_cancel() {
+        let mut jobs = Jobs::default();
+        let running = submit(&mut jobs, 0);
+        let found = submit(&mut jobs, 0);
+        let failed = submit(&mut jobs, 0);
+        for id in [running, found, failed] {
+            assert_eq!(jobs.start_next().unwrap().0, id);
+        }
+        let source = Arc::new(TileSource::new(None));
+        jobs.entry(running).unwrap().source = Some(source.clone());
+        // a running search is stopped through its tiles and ends up cancelled
+        assert!(jobs.cancel(running));
+        assert!(source.is_cancelled());
+        assert_eq!(state(&jobs, running), JobState::Running);
+        jobs.finish(running, Ok(None));
+        assert_eq!(state(&jobs, running), JobState::Cancelled);
+        assert!(!jobs.cancel(running));
+        // a match found before the search stopped is kept
+        assert!(jobs.cancel(found));
+        let pos = PatternMatch {
+            pattern: 0,
+            pos: IVec3::new(1, 2, 3),
+        };
+        jobs.finish(found, Ok(Some(pos)));
+        assert_eq!(state(&jobs, found), JobState::Found(pos));
+        // failing adapters cancel the source to stop the others
+        let source = Arc::new(TileSource::new(None));
+        source.cancel();
+        jobs.entry(failed).unwrap().source = Some(source);
+        jobs.finish(failed, Err("no adapter".to_owned()));
+        assert_eq!(
+            state(&jobs, failed),
+            JobState::Failed("no adapter".to_owned())
+        );
+        assert!(!jobs.cancel(failed));
+    }
+}
@@ -0,0 +1,303 @@
+use std::thread;
+
+use bevy::math::IVec3;
+use bevy_meshem::{prelude::three_d_cords, Dimensions};
+
+use crate::pattern::Pattern;
+
+use super::{
+    chunk::RotationChunk,
+    plugin::FinderJob,
+    util::{
+        check_rotation, get_block_rotation, get_block_rotation_from_terms, seed_row_term,
+        seed_x_term,
+    },
+    Rotation,
+};
+
+/// Position whose rendered rotations differ from the pattern in `mismatches` blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegionMatch {
+    pub pos: IVec3,
+    pub mismatches: usize,
+}
+
+/// Offsets of the blocks that constrain the position, skipping air and single variant blocks.
+#[derive(Clone, Debug, Default)]
+pub struct SparsePattern(pub Vec<(IVec3, Rotation)>);
+
+impl SparsePattern {
+    /// One for every pattern of the job, in order.
+    pub fn from_job(job: &FinderJob) -> Vec<Self> {
+        job.patterns()
+            .map(|rotations| {
+                Self(
+                    rotations
+                        .iter()
+                        .enumerate()
+                        .map(|(index, rotation)| {
+                            (three_d_cords(index, job.dimensions()), Rotation(*rotation))
+                        })
+                        .filter(|(_, rotation)| rotation.get_max_rotation() > 1)
+                        .map(|((x, y, z), rotation)| {
+                            (
+                                IVec3::new(x as i32, y as i32, z as i32) + job.offset,
+                                rotation,
+                            )
+                        })
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    pub fn from_pattern(pattern: &Pattern) -> Self {
+        Self(
+            pattern
+                .blocks
+                .iter()
+                .filter(|block| block.variants > 1)
+                .filter_map(|block| {
+                    Some((
+                        IVec3::new(
+                            block.pos[0] as i32,
+                            block.pos[1] as i32,
+                            block.pos[2] as i32,
+                        ),
+                        Rotation::new(block.rotation?, block.variants),
+                    ))
+                })
+                .collect(),
+        )
+    }
+
+    /// Number of blocks that don't match at `pos`, stopping early once `limit` is exceeded.
+    #[inline]
+    pub fn mismatches(&self, pos: IVec3, limit: usize) -> usize {
+        let mut mismatches = 0;
+        for (offset, rotation) in self.0.iter() {
+            let block = pos + *offset;
+            if !check_rotation(
+                *rotation,
+                get_block_rotation(block.x as i64, block.y as i64, block.z as i64),
+            ) {
+                mismatches += 1;
+                if mismatches > limit {
+                    break;
+                }
+            }
+        }
+        mismatches
+    }
+
+    /// Like `mismatches`, with the rotations taken from `table`, which has to contain every
+    /// block of the pattern at `pos`.
+    #[inline]
+    pub fn mismatches_in(&self, table: &SeedTable, pos: IVec3, limit: usize) -> usize {
+        let mut mismatches = 0;
+        for (offset, rotation) in self.0.iter() {
+            if !check_rotation(*rotation, table.get_block_rotation(pos + *offset)) {
+                mismatches += 1;
+                if mismatches > limit {
+                    break;
+                }
+            }
+        }
+        mismatches
+    }
+
+    /// Smallest and largest offset of the blocks.
+    pub fn bounds(&self) -> (IVec3, IVec3) {
+        self.0
+            .iter()
+            .fold((IVec3::MAX, IVec3::MIN), |(min, max), (offset, _)| {
+                (min.min(*offset), max.max(*offset))
+            })
+    }
+}
+
+/// A `SparsePattern` with the offsets of its blocks turned into offsets of their indices in
+/// chunks of one size, so checking a position doesn't convert them again.
+pub struct ChunkPattern {
+    size: Dimensions,
+    blocks: Vec<(isize, Rotation)>,
+}
+
+impl ChunkPattern {
+    pub fn new(pattern: &SparsePattern, size: Dimensions) -> Self {
+        Self {
+            size,
+            blocks: pattern
+                .0
+                .iter()
+                .map(|(offset, rotation)| (index_offset(*offset, size), *rotation))
+                .collect(),
+        }
+    }
+
+    /// Like `SparsePattern::mismatches`, with the rotations taken from `chunk`, which has to
+    /// contain every block of the pattern at `pos`.
+    #[inline]
+    pub fn mismatches(&self, chunk: &RotationChunk, pos: IVec3, limit: usize) -> usize {
+        debug_assert_eq!(chunk.size(), self.size);
+        let index = index_offset(pos, self.size);
+        let mut mismatches = 0;
+        for (offset, rotation) in self.blocks.iter() {
+            if !check_rotation(*rotation, chunk.get((index + offset) as usize)) {
+                mismatches += 1;
+                if mismatches > limit {
+                    break;
+                }
+            }
+        }
+        mismatches
+    }
+}
+
+/// `one_d_cords` for offsets, which may be negative.
+fn index_offset(offset: IVec3, size: Dimensions) -> isize {
+    let (width, depth) = (size.0 as isize, size.2 as isize);
+    (offset.y as isize * depth + offset.z as isize) * width + offset.x as isize
+}
+
+/// The terms of `get_rendering_seed` that only depend on x or on the row along x, tabulated for
+/// a box so the rotation of a block inside it costs two multiplications less.
+pub struct SeedTable {
+    min: IVec3,
+    size: IVec3,
+    x_terms: Vec<i64>,
+    /// indexed by y, then z
+    row_terms: Vec<i64>,
+}
+
+impl SeedTable {
+    /// Covers the blocks between `min` and `max` (inclusive).
+    pub fn new(min: IVec3, max: IVec3) -> Self {
+        let (min, max) = (min.min(max), min.max(max));
+        let size = max - min + IVec3::ONE;
+        Self {
+            min,
+            size,
+            x_terms: (min.x..=max.x).map(|x| seed_x_term(x as i64)).collect(),
+            row_terms: (min.y..=max.y)
+                .flat_map(|y| (min.z..=max.z).map(move |z| seed_row_term(y as i64, z as i64)))
+                .collect(),
+        }
+    }
+
+    #[inline]
+    pub fn get_block_rotation(&self, pos: IVec3) -> u8 {
+        let pos = pos - self.min;
+        get_block_rotation_from_terms(
+            self.x_terms[pos.x as usize],
+            self.row_terms[(pos.y * self.size.z + pos.z) as usize],
+        )
+    }
+}
+
+/// Checks every origin between `min` and `max` (inclusive) and returns the ones with at most
+/// `max_mismatches` differing blocks, best matches first.
+///
+/// Unlike the chunked finders this works on arbitrary boxes and reports near misses, which makes
+/// it the tool of choice when the area is already roughly known.
+pub fn solve_region(
+    pattern: &SparsePattern,
+    min: IVec3,
+    max: IVec3,
+    max_mismatches: usize,
+) -> Vec<RegionMatch> {
+    let (min, max) = (min.min(max), min.max(max));
+    let (offset_min, offset_max) = pattern.bounds();
+    let table = &SeedTable::new(
+        min + offset_min.min(IVec3::ZERO),
+        max + offset_max.max(IVec3::ZERO),
+    );
+    let threads = thread::available_parallelism()
+        .map(|v| v.get())
+        .unwrap_or(1)
+        .min((max.x - min.x + 1) as usize);
+    let mut matches: Vec<RegionMatch> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads as i32)
+            .map(|thread| {
+                scope.spawn(move || {
+                    let mut matches = Vec::new();
+                    // interleave the columns so every thread gets the same amount of work
+                    for x in (min.x + thread..=max.x).step_by(threads) {
+                        for z in min.z..=max.z {
+                            for y in min.y..=max.y {
+                                let pos = IVec3::new(x, y, z);
+                                let mismatches = pattern.mismatches_in(table, pos, max_mismatches);
+                                if mismatches <= max_mismatches {
+                                    matches.push(RegionMatch { pos, mismatches });
+                                }
+                            }
+                        }
+                    }
+                    matches
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+    matches.sort_by_key(|v| (v.mismatches, v.pos.x, v.pos.y, v.pos.z));
+    matches
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::math::{IVec3, UVec3};
+
+    use super::{solve_region, SeedTable, SparsePattern};
+    use crate::finder::{
+        plugin::FinderJob,
+        util::{get_block_rotation, RowRotations},
+    };
+
+    #[test]
+    fn test_solve_region() {
+        let origin = IVec3::new(-1203, 70, 455);
+        let job = FinderJob::from_world(origin, UVec3::new(6, 1, 4));
+        let pattern = SparsePattern::from_job(&job).remove(0);
+        let matches = solve_region(
+            &pattern,
+            origin - IVec3::new(20, 3, 20),
+            origin + IVec3::new(20, 3, 20),
+            2,
+        );
+        assert_eq!(matches[0].pos, origin);
+        assert_eq!(matches[0].mismatches, 0);
+        assert!(matches[1..].iter().all(|v| v.mismatches > 0));
+    }
+
+    #[test]
+    fn test_incremental_rotations() {
+        let data: &[(i64, i64, i64, i64, u8)] = &include!("../testdata.txt");
+        for (x, y, z, _, rotation) in data {
+            assert_eq!(RowRotations::new(*x, *y, *z).next(), Some(*rotation));
+        }
+        let start = IVec3::new(-29_999_990, -64, 29_999_000);
+        for row in 0..8 {
+            let (y, z) = (start.y + row, start.z + row * 3);
+            let rotations = RowRotations::new(start.x as i64, y as i64, z as i64).take(1000);
+            assert!(rotations
+                .eq((0..1000)
+                    .map(|i| { get_block_rotation((start.x + i) as i64, y as i64, z as i64) })));
+        }
+        let (min, max) = (IVec3::new(-40, 60, 1990), IVec3::new(25, 75, 2010));
+        let table = SeedTable::new(min, max);
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    assert_eq!(
+                        table.get_block_rotation(IVec3::new(x, y, z)),
+                        get_block_rotation(x as i64, y as i64, z as i64),
+                        "wrong rotation at {x},{y},{z}"
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use bevy::{
+    log::info,
+    math::{IVec2, UVec3},
+    render::settings::WgpuLimits,
+};
+
+use crate::constants::{CHUNK_SIZE, WORLD_HEIGHT};
+
+use super::util::{env_u32, spiral};
+
+/// Dimensions of the tiles the world is searched in.
+///
+/// Neighbouring tiles overlap by the pattern size minus one, so every position is checked
+/// exactly once no matter how large the pattern is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TileSettings {
+    /// width and depth of a tile, a multiple of 16
+    pub size: u32,
+    pub height: u32,
+    /// threads per workgroup of the compute shaders, spread along x
+    pub workgroup_size: u32,
+}
+
+impl Default for TileSettings {
+    fn default() -> Self {
+        Self {
+            size: CHUNK_SIZE as u32,
+            height: WORLD_HEIGHT as u32,
+            workgroup_size: 64,
+        }
+    }
+}
+
+impl TileSettings {
+    /// Largest tile whose packed rotations fit into a single storage buffer binding.
+    ///
+    /// `FINDER_TILE_SIZE` can be used to pick a smaller size and `FINDER_WORKGROUP_SIZE` to tune
+    /// the workgroups for the hardware.
+    pub fn from_limits(limits: &WgpuLimits) -> Self {
+        let max_bytes = (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size);
+        let height = WORLD_HEIGHT as u32;
+        // 2 bits per block
+        let max_size = ((max_bytes * 4 / height as u64) as f64).sqrt() as u32;
+        let mut size = max_size
+            .min(CHUNK_SIZE as u32)
+            .min(limits.max_compute_workgroups_per_dimension);
+        if let Some(value) = env_u32("FINDER_TILE_SIZE") {
+            size = size.min(value);
+        }
+        let workgroup_size = env_u32("FINDER_WORKGROUP_SIZE")
+            .unwrap_or(Self::default().workgroup_size)
+            .min(limits.max_compute_invocations_per_workgroup)
+            .min(limits.max_compute_workgroup_size_x)
+            .max(1);
+        let settings = Self {
+            size: (size / 16 * 16).max(16),
+            height,
+            workgroup_size,
+        };
+        info!(
+            "using {0}x{1}x{0} tiles, {2} threads per workgroup",
+            settings.size, settings.height, settings.workgroup_size
+        );
+        settings
+    }
+
+    /// Size in bytes of the packed rotations of a tile.
+    pub fn buffer_size(&self) -> u64 {
+        self.size as u64 * self.size as u64 * self.height as u64 / 4
+    }
+
+    /// Number of pattern origins checked per tile along each axis.
+    pub fn positions(&self, pattern_size: UVec3) -> UVec3 {
+        UVec3::new(self.size, self.height, self.size)
+            .saturating_sub(pattern_size.saturating_sub(UVec3::ONE))
+    }
+
+    pub fn blocks_per_tile(&self, pattern_size: UVec3) -> u64 {
+        let positions = self.positions(pattern_size);
+        positions.x as u64 * positions.y as u64 * positions.z as u64
+    }
+
+    /// World position of the corner of the tile with this index in the spiral.
+    pub fn origin(&self, index: u32, pattern_size: UVec3) -> IVec2 {
+        let (x, z) = spiral(index as i32);
+        let positions = self.positions(pattern_size);
+        IVec2::new(x * positions.x as i32, z * positions.z as i32)
+    }
+
+    /// Workgroups to dispatch for one thread per cell of `extent`, the shaders skip the excess.
+    pub fn workgroups(&self, extent: UVec3) -> UVec3 {
+        UVec3::new(extent.x.div_ceil(self.workgroup_size), extent.y, extent.z)
+    }
+
+    /// Tiles of the spiral needed to check every position within `radius` blocks of 0,0.
+    pub fn tiles_for_radius(&self, radius: u64, pattern_size: UVec3) -> u32 {
+        let step = self.positions(pattern_size).x.max(1) as u64;
+        let rings = radius.div_ceil(step);
+        (2 * rings + 1).pow(2).min(u32::MAX as u64) as u32
+    }
+
+    pub fn fits(&self, pattern_size: UVec3) -> bool {
+        pattern_size.x <= self.size && pattern_size.y <= self.height && pattern_size.z <= self.size
+    }
+}
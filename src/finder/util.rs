@@ -1,5 +1,6 @@
-use std::num::Wrapping;
+use std::{env, num::Wrapping};
 
+use bevy::log::warn;
 use bevy_meshem::util::{one_d_cords, three_d_cords};
 
 use crate::constants::GRID_SIZE;
@@ -7,7 +8,7 @@ use crate::constants::GRID_SIZE;
 use super::Rotation;
 
 pub fn get_block_rotation(x: i64, y: i64, z: i64) -> u8 {
-    (get_rotation_from_seed(get_rendering_seed(x, y, z) >> 16).abs() & 3) as u8
+    rotation_from_terms(seed_x_term(x) ^ seed_row_term(y, z)) as u8
 }
 pub fn get_rendering_seed(x: i64, y: i64, z: i64) -> i64 {
     let (x, y, z) = (Wrapping(x), Wrapping(y), Wrapping(z));
@@ -16,6 +17,7 @@ pub fn get_rendering_seed(x: i64, y: i64, z: i64) -> i64 {
     l.0
 }
 
+#[inline(always)]
 fn get_rotation_from_seed(seed: i64) -> i32 {
     let seed = (seed ^ 0x5DEECE66D) & ((1 << 48) - 1);
     let value = (((seed.wrapping_mul(0xBB20B4600A69).wrapping_add(0x40942DE6BA) as u64) >> 16)
@@ -23,6 +25,176 @@ fn get_rotation_from_seed(seed: i64) -> i32 {
     value as i32
 }
 
+/// The rotation of a block from the xor `l` of its seed terms, `l * l * 42317861 + l * 11`
+/// factored to save a multiplication. Every way of computing rotations goes through here.
+#[inline(always)]
+fn rotation_from_terms(l: i64) -> u32 {
+    let seed = l.wrapping_mul(l.wrapping_mul(42317861).wrapping_add(11));
+    get_rotation_from_seed(seed >> 16).unsigned_abs() & 3
+}
+
+/// `x * 3129871`, the part of `get_rendering_seed` that only depends on x.
+#[inline(always)]
+pub fn seed_x_term(x: i64) -> i64 {
+    x.wrapping_mul(3129871)
+}
+
+/// `z * 116129781 ^ y`, the part of `get_rendering_seed` that only depends on the row along x.
+#[inline(always)]
+pub fn seed_row_term(y: i64, z: i64) -> i64 {
+    z.wrapping_mul(116129781) ^ y
+}
+
+/// Same as `get_block_rotation` with the terms of the seed computed beforehand.
+#[inline]
+pub fn get_block_rotation_from_terms(x_term: i64, row_term: i64) -> u8 {
+    rotation_from_terms(x_term ^ row_term) as u8
+}
+
+/// Rotations of the blocks from `x` along x, the x term of the seed grows by addition.
+#[derive(Clone, Copy, Debug)]
+pub struct RowRotations {
+    x_term: i64,
+    row_term: i64,
+}
+
+impl RowRotations {
+    pub fn new(x: i64, y: i64, z: i64) -> Self {
+        Self {
+            x_term: seed_x_term(x),
+            row_term: seed_row_term(y, z),
+        }
+    }
+}
+
+impl Iterator for RowRotations {
+    type Item = u8;
+
+    #[inline]
+    fn next(&mut self) -> Option<u8> {
+        let rotation = get_block_rotation_from_terms(self.x_term, self.row_term);
+        self.x_term = self.x_term.wrapping_add(3129871);
+        Some(rotation)
+    }
+}
+
+/// Computes the rotations of the 16 blocks starting at `x` along x, packed like `pack16xU2` in
+/// `chunk.wgsl` with the rotation of `x` in the lowest bits.
+pub type BlockRotationsFn = fn(i64, i64, i64) -> u32;
+
+/// The fastest [`BlockRotationsFn`] this CPU supports, loops should look it up once.
+pub fn block_rotations_fn() -> BlockRotationsFn {
+    // the intrinsics are only inlined in optimized builds, calling each of them is far slower
+    if cfg!(debug_assertions) {
+        return get_block_rotations_scalar;
+    }
+    block_rotations_fns().last().unwrap().1
+}
+
+/// Every [`BlockRotationsFn`] this CPU supports by name, the fastest last.
+pub fn block_rotations_fns() -> Vec<(&'static str, BlockRotationsFn)> {
+    let mut fns: Vec<(&'static str, BlockRotationsFn)> = vec![("cpu", get_block_rotations_scalar)];
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        fns.push(("avx2", get_block_rotations_avx2));
+    }
+    fns
+}
+
+fn get_block_rotations_scalar(x: i64, y: i64, z: i64) -> u32 {
+    let row_term = seed_row_term(y, z);
+    (0..16).fold(0, |packed, i| {
+        packed | rotation_from_terms(seed_x_term(x.wrapping_add(i)) ^ row_term) << (i * 2)
+    })
+}
+
+/// Only handed out by [`block_rotations_fns`] once avx2 is detected.
+#[cfg(target_arch = "x86_64")]
+fn get_block_rotations_avx2(x: i64, y: i64, z: i64) -> u32 {
+    // SAFETY: avx2 is available
+    unsafe { avx2::get_block_rotations(x, y, z) }
+}
+
+/// [`rotation_from_terms`] for 4 blocks at once in 64 bit lanes.
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    use super::{seed_row_term, seed_x_term};
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn get_block_rotations(x: i64, y: i64, z: i64) -> u32 {
+        let row_term = _mm256_set1_epi64x(seed_row_term(y, z));
+        // the x term grows by 3129871 per block, and by 4 times that per group of lanes
+        let mut x_terms = _mm256_add_epi64(
+            _mm256_set1_epi64x(seed_x_term(x)),
+            _mm256_setr_epi64x(0, seed_x_term(1), seed_x_term(2), seed_x_term(3)),
+        );
+        let step = _mm256_set1_epi64x(seed_x_term(4));
+        // lane i of group g ends up at bit 2 * (4 * g + i), every lane at its own bits
+        let mut shifts = _mm256_setr_epi64x(0, 2, 4, 6);
+        let mut packed = _mm256_setzero_si256();
+        for _ in 0..4 {
+            let rotations = rotations(_mm256_xor_si256(x_terms, row_term));
+            packed = _mm256_or_si256(packed, _mm256_sllv_epi64(rotations, shifts));
+            x_terms = _mm256_add_epi64(x_terms, step);
+            shifts = _mm256_add_epi64(shifts, _mm256_set1_epi64x(8));
+        }
+        let mut lanes = [0u64; 4];
+        _mm256_storeu_si256(lanes.as_mut_ptr().cast(), packed);
+        (lanes[0] | lanes[1] | lanes[2] | lanes[3]) as u32
+    }
+
+    #[target_feature(enable = "avx2")]
+    #[inline]
+    unsafe fn rotations(l: __m256i) -> __m256i {
+        let seed = mul(
+            l,
+            _mm256_add_epi64(mul_u32(l, _mm256_set1_epi64x(42317861)), _mm256_set1_epi64x(11)),
+        );
+        // only the low 48 bits of the shifted seed are used, they're the same for a logical shift
+        let seed = _mm256_and_si256(
+            _mm256_xor_si256(_mm256_srli_epi64(seed, 16), _mm256_set1_epi64x(0x5DEECE66D)),
+            _mm256_set1_epi64x((1 << 48) - 1),
+        );
+        let value = _mm256_srli_epi64(
+            _mm256_add_epi64(
+                mul(seed, _mm256_set1_epi64x(0xBB20B4600A69)),
+                _mm256_set1_epi64x(0x40942DE6BA),
+            ),
+            16,
+        );
+        // the absolute value of the low 32 bits, of which only the lowest 2 are kept
+        let negative = _mm256_sub_epi64(
+            _mm256_setzero_si256(),
+            _mm256_and_si256(_mm256_srli_epi64(value, 31), _mm256_set1_epi64x(1)),
+        );
+        _mm256_and_si256(
+            _mm256_sub_epi64(_mm256_xor_si256(value, negative), negative),
+            _mm256_set1_epi64x(3),
+        )
+    }
+
+    /// Like [`mul`] for `b` below 2^32.
+    #[target_feature(enable = "avx2")]
+    #[inline]
+    unsafe fn mul_u32(a: __m256i, b: __m256i) -> __m256i {
+        let high = _mm256_mul_epu32(_mm256_srli_epi64(a, 32), b);
+        _mm256_add_epi64(_mm256_mul_epu32(a, b), _mm256_slli_epi64(high, 32))
+    }
+
+    /// Low 64 bits of the products of the lanes, avx2 only multiplies their low 32 bits.
+    #[target_feature(enable = "avx2")]
+    #[inline]
+    unsafe fn mul(a: __m256i, b: __m256i) -> __m256i {
+        let cross = _mm256_add_epi64(
+            _mm256_mul_epu32(_mm256_srli_epi64(a, 32), b),
+            _mm256_mul_epu32(a, _mm256_srli_epi64(b, 32)),
+        );
+        _mm256_add_epi64(_mm256_mul_epu32(a, b), _mm256_slli_epi64(cross, 32))
+    }
+}
+
 #[inline]
 pub fn check_rotation(desired_rotation: Rotation, rotation: u8) -> bool {
     desired_rotation.get_max_rotation() <= 1
@@ -88,3 +260,13 @@ pub fn check_rotation2(desired_rotation: Rotation, rotation: u8) -> bool {
     (desired_rotation.0 >> 4) & 0x0F <= 1
         || rotation % ((desired_rotation.0 >> 4) & 0x0F) == desired_rotation.0 & 0x0F
 }
+
+/// Reads a number from the environment, warning if it's set to something else.
+pub fn env_u32(name: &str) -> Option<u32> {
+    let value = env::var(name).ok()?;
+    let parsed = value.parse().ok();
+    if parsed.is_none() {
+        warn!("invalid {name} {value}");
+    }
+    parsed
+}
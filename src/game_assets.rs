@@ -42,13 +42,12 @@ impl Debug for BlockId {
 impl VoxelRegistry for MinecraftBlockProvider {
     type Voxel = BlockId;
     fn get_mesh(&self, voxel: &Self::Voxel) -> VoxelMesh<&Mesh> {
-        self.meshes
-            .get(voxel)
+        self.get_variant_mesh(voxel)
             .map(VoxelMesh::NormalCube)
             .unwrap_or(VoxelMesh::Null)
     }
     fn is_covering(&self, voxel: &Self::Voxel, _side: bevy_meshem::prelude::Face) -> bool {
-        self.meshes.get(voxel).is_some() && *voxel != AIR
+        self.get_variant_mesh(voxel).is_some() && *voxel != AIR
     }
 
     fn get_center(&self) -> [f32; 3] {
@@ -65,6 +64,27 @@ impl VoxelRegistry for MinecraftBlockProvider {
 }
 
 impl MinecraftBlockProvider {
+    /// Provider with the given block names and variant counts, but without meshes or textures.
+    #[cfg(test)]
+    pub(crate) fn from_blocks(blocks: &[(&str, u8)]) -> Self {
+        Self {
+            block_material: Handle::default(),
+            blocks: blocks
+                .iter()
+                .enumerate()
+                .map(|(id, (_, variants))| BlockMeta {
+                    id,
+                    variants: *variants,
+                })
+                .collect(),
+            block_map: blocks
+                .iter()
+                .enumerate()
+                .map(|(i, (name, _))| (name.to_string(), i))
+                .collect(),
+            meshes: HashMap::default(),
+        }
+    }
     pub fn get_random_block(&self) -> &BlockMeta {
         self.blocks.choose(&mut rand::thread_rng()).unwrap()
     }
@@ -77,9 +97,20 @@ impl MinecraftBlockProvider {
     pub fn get_meta<'a>(&'a self, id: &str) -> &'a BlockMeta {
         self.blocks.get(*self.block_map.get(id).unwrap()).unwrap()
     }
+    pub fn try_get_meta<'a>(&'a self, id: &str) -> Option<&'a BlockMeta> {
+        self.blocks.get(*self.block_map.get(id)?)
+    }
     pub fn get_meta_from_index(&self, index: usize) -> Option<&BlockMeta> {
         self.blocks.get(index)
     }
+    fn get_variant_mesh(&self, voxel: &BlockId) -> Option<&Mesh> {
+        if voxel.1 == UNKNOWN_ROTATION {
+            // there is no mesh for an unknown rotation, show the default variant instead
+            self.meshes.get(&BlockId(voxel.0, 0))
+        } else {
+            self.meshes.get(voxel)
+        }
+    }
 }
 
 impl MinecraftAssets {
@@ -2,16 +2,40 @@ use bevy::prelude::*;
 use bevy_meshem::prelude::*;
 use bevy_mod_raycast::prelude::*;
 
-use crate::finder::Rotation;
+use crate::finder::{plugin::FinderJob, Rotation};
 use crate::game_assets::{BlockId, MinecraftBlockProvider};
 use crate::{constants::*, AppState};
 
 #[derive(Resource)]
 pub struct Grid {
-    pub grid: Box<[BlockId; GRID_SIZE.0 * GRID_SIZE.1 * GRID_SIZE.2]>,
+    /// indexed with `one_d_cords` over `size`
+    pub grid: Box<[BlockId]>,
+    pub size: Dimensions,
     metadata: MeshMD<BlockId>,
 }
 
+/// Blocks to load into the grid, see `Grid::load`.
+#[derive(Clone, Debug)]
+pub struct GridBlocks {
+    pub size: Dimensions,
+    pub blocks: Box<[BlockId]>,
+}
+
+impl GridBlocks {
+    /// Air with room for a pattern of `size`, and at least `GRID_SIZE` to build in.
+    pub fn for_pattern(size: [usize; 3]) -> Self {
+        let size = (
+            size[0].max(GRID_SIZE.0),
+            size[1].max(GRID_SIZE.1),
+            size[2].max(GRID_SIZE.2),
+        );
+        Self {
+            size,
+            blocks: vec![AIR; size.0 * size.1 * size.2].into_boxed_slice(),
+        }
+    }
+}
+
 #[derive(Component)]
 pub struct GridMesh;
 
@@ -21,9 +45,9 @@ impl Grid {
         voxel_registry: &MinecraftBlockProvider,
         material: &Handle<StandardMaterial>,
     ) -> (Self, PbrBundle) {
-        let grid = Box::new([AIR; GRID_SIZE.0 * GRID_SIZE.1 * GRID_SIZE.2]);
+        let GridBlocks { size, blocks: grid } = GridBlocks::for_pattern([0; 3]);
         let (mesh, metadata) = mesh_grid(
-            GRID_SIZE,
+            size,
             &[],
             grid.as_ref(),
             voxel_registry,
@@ -32,7 +56,11 @@ impl Grid {
         )
         .unwrap();
         (
-            Grid { grid, metadata },
+            Grid {
+                grid,
+                size,
+                metadata,
+            },
             PbrBundle {
                 mesh: meshes.add(mesh),
                 material: material.clone(),
@@ -50,7 +78,7 @@ impl Grid {
         let neighbors = {
             let mut neighboring_voxels = [None; 6];
             for (i, item) in neighboring_voxels.iter_mut().enumerate() {
-                *item = if let Some(a) = get_neighbor(voxel_index, Face::from(i), GRID_SIZE) {
+                *item = if let Some(a) = get_neighbor(voxel_index, Face::from(i), self.size) {
                     Some(self.grid[a])
                 } else {
                     continue;
@@ -76,7 +104,11 @@ impl Grid {
         let mut block = self.grid[voxel_index];
         let block_info = voxel_registry.get_meta_from_index(block.0);
         info!("{block_info:?}, {block:?}");
-        block.1 = (block.1 + 1) % block_info.map(|v| v.variants).unwrap_or_default();
+        block.1 = if block.1 == UNKNOWN_ROTATION {
+            0
+        } else {
+            (block.1 + 1) % block_info.map(|v| v.variants).unwrap_or_default()
+        };
         self.remove_block(voxel_index, mesh, voxel_registry);
         self.add_block(voxel_index, block, mesh, voxel_registry);
     }
@@ -92,7 +124,7 @@ impl Grid {
         let neighbors = {
             let mut neighboring_voxels = [None; 6];
             for (i, item) in neighboring_voxels.iter_mut().enumerate() {
-                *item = if let Some(a) = get_neighbor(voxel_index, Face::from(i), GRID_SIZE) {
+                *item = if let Some(a) = get_neighbor(voxel_index, Face::from(i), self.size) {
                     Some(self.grid[a])
                 } else {
                     continue;
@@ -110,9 +142,18 @@ impl Grid {
         update_mesh(mesh, &mut self.metadata, voxel_registry);
     }
     pub fn reset(&mut self, mesh: &mut Mesh, voxel_registry: &MinecraftBlockProvider) {
-        self.grid = Box::new([AIR; GRID_SIZE.0 * GRID_SIZE.1 * GRID_SIZE.2]);
+        self.load(GridBlocks::for_pattern([0; 3]), mesh, voxel_registry);
+    }
+    pub fn load(
+        &mut self,
+        blocks: GridBlocks,
+        mesh: &mut Mesh,
+        voxel_registry: &MinecraftBlockProvider,
+    ) {
+        self.grid = blocks.blocks;
+        self.size = blocks.size;
         (*mesh, self.metadata) = mesh_grid(
-            GRID_SIZE,
+            self.size,
             &[],
             self.grid.as_ref(),
             voxel_registry,
@@ -121,41 +162,36 @@ impl Grid {
         )
         .unwrap();
     }
-    pub fn as_rotations(
-        &self,
-        block_provider: &MinecraftBlockProvider,
-    ) -> Box<[Rotation; GRID_SIZE.0 * GRID_SIZE.1 * GRID_SIZE.2]> {
-        let mut array: Box<[Rotation; GRID_SIZE.0 * GRID_SIZE.1 * GRID_SIZE.2]> =
-            Box::new([Rotation(0); GRID_SIZE.0 * GRID_SIZE.1 * GRID_SIZE.2]);
-        for (i, element) in self.grid.iter().enumerate() {
-            array[i] = Rotation::new(
-                element.1,
-                block_provider
-                    .get_meta_from_index(element.0)
-                    .map(|v| v.variants)
-                    .unwrap_or_default(),
-            );
-        }
-        array
+    pub fn as_rotations(&self, block_provider: &MinecraftBlockProvider) -> Vec<Rotation> {
+        self.grid
+            .iter()
+            .map(|block| block_rotation(*block, block_provider))
+            .collect()
     }
-    pub fn as_u8(
-        &self,
-        block_provider: &MinecraftBlockProvider,
-    ) -> Box<[u8; GRID_SIZE.0 * GRID_SIZE.1 * GRID_SIZE.2]> {
-        let mut array: Box<[u8; GRID_SIZE.0 * GRID_SIZE.1 * GRID_SIZE.2]> =
-            Box::new([0; GRID_SIZE.0 * GRID_SIZE.1 * GRID_SIZE.2]);
-        for (i, element) in self.grid.iter().enumerate() {
-            array[i] = Rotation::new(
-                element.1,
-                block_provider
-                    .get_meta_from_index(element.0)
-                    .map(|v| v.variants)
-                    .unwrap_or_default(),
-            )
-            .0;
-        }
-        array
+    pub fn as_u8(&self, block_provider: &MinecraftBlockProvider) -> Vec<u8> {
+        self.grid
+            .iter()
+            .map(|block| block_rotation(*block, block_provider).0)
+            .collect()
+    }
+    /// The blocks of the grid as a finder job, cropped to the blocks that constrain it.
+    pub fn to_job(&self, block_provider: &MinecraftBlockProvider) -> FinderJob {
+        FinderJob::from_rotations(self.size, &self.as_u8(block_provider))
+    }
+}
+
+fn block_rotation(block: BlockId, block_provider: &MinecraftBlockProvider) -> Rotation {
+    if block.1 == UNKNOWN_ROTATION {
+        // a max rotation of 0 matches every rotation
+        return Rotation::new(0, 0);
     }
+    Rotation::new(
+        block.1,
+        block_provider
+            .get_meta_from_index(block.0)
+            .map(|v| v.variants)
+            .unwrap_or_default(),
+    )
 }
 
 pub struct GridPlugin;
@@ -0,0 +1,740 @@
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use bevy::{prelude::Resource, utils::HashMap};
+use bevy_meshem::prelude::one_d_cords;
+
+use crate::{
+    constants::*,
+    game_assets::{BlockId, MinecraftBlockProvider},
+    grid::GridBlocks,
+    nbt::{self, NbtError, Tag},
+    pattern::Pattern,
+};
+
+/// Path of a pattern or structure file that should be loaded into the grid instead of starting empty.
+#[derive(Resource, Clone, Debug)]
+pub struct PatternImport(pub PathBuf);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StructureFormat {
+    /// vanilla structure block files (`.nbt`)
+    Structure,
+    /// Sponge schematics as written by WorldEdit (`.schem`)
+    Sponge,
+    /// Litematica schematics (`.litematic`)
+    Litematic,
+}
+
+impl StructureFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "nbt" => Some(Self::Structure),
+            "schem" => Some(Self::Sponge),
+            "litematic" => Some(Self::Litematic),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BlockState {
+    pub name: String,
+    pub properties: Vec<(String, String)>,
+}
+
+impl BlockState {
+    /// Parses states in the `minecraft:grass_block[snowy=false]` notation used by Sponge palettes.
+    pub fn parse(state: &str) -> Self {
+        let (name, properties) = match state.split_once('[') {
+            Some((name, properties)) => (
+                name,
+                properties
+                    .trim_end_matches(']')
+                    .split(',')
+                    .filter_map(|property| property.split_once('='))
+                    .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                    .collect(),
+            ),
+            None => (state, Vec::new()),
+        };
+        Self {
+            name: name.to_owned(),
+            properties,
+        }
+    }
+    pub(crate) fn from_nbt(tag: &Tag) -> Result<Self, ImportError> {
+        let name = tag
+            .get("Name")
+            .and_then(Tag::as_str)
+            .ok_or(ImportError::Malformed("palette entry without a name"))?;
+        let properties = tag
+            .get("Properties")
+            .and_then(Tag::as_compound)
+            .map(|properties| {
+                properties
+                    .iter()
+                    .filter_map(|(k, v)| Some((k.clone(), v.as_str()?.to_owned())))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(Self {
+            name: name.to_owned(),
+            properties,
+        })
+    }
+    /// Name without the `minecraft:` namespace, as used by the block list.
+    pub fn short_name(&self) -> &str {
+        self.name.trim_start_matches("minecraft:")
+    }
+    pub fn is_air(&self) -> bool {
+        matches!(
+            self.short_name(),
+            "air" | "cave_air" | "void_air" | "structure_void"
+        )
+    }
+}
+
+impl Display for BlockState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.name)?;
+        if !self.properties.is_empty() {
+            f.write_str("[")?;
+            for (i, (k, v)) in self.properties.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(",")?;
+                }
+                write!(f, "{k}={v}")?;
+            }
+            f.write_str("]")?;
+        }
+        Ok(())
+    }
+}
+
+/// Blocks of an imported structure, relative to its minimum corner.
+#[derive(Clone, Debug, Default)]
+pub struct Structure {
+    pub size: [usize; 3],
+    pub blocks: Vec<([usize; 3], BlockState)>,
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io(std::io::Error),
+    Nbt(NbtError),
+    UnknownFormat(PathBuf),
+    Malformed(&'static str),
+    TooLarge([usize; 3]),
+    OutOfBounds([usize; 3]),
+    MissingChunk(i32, i32),
+    InvalidPattern(usize),
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::Io(err) => write!(f, "couldn't read structure file: {err}"),
+            ImportError::Nbt(err) => err.fmt(f),
+            ImportError::UnknownFormat(path) => {
+                write!(f, "unknown structure format: {}", path.display())
+            }
+            ImportError::Malformed(reason) => write!(f, "malformed structure file: {reason}"),
+            ImportError::TooLarge(size) => write!(
+                f,
+                "structure of size {size:?} is larger than the largest pattern {MAX_PATTERN_SIZE:?}"
+            ),
+            ImportError::OutOfBounds(pos) => {
+                write!(f, "block at {pos:?} is outside of the structure")
+            }
+            ImportError::MissingChunk(x, z) => write!(f, "chunk {x}, {z} hasn't been generated"),
+            ImportError::InvalidPattern(line) => write!(f, "invalid pattern file in line {line}"),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<std::io::Error> for ImportError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<NbtError> for ImportError {
+    fn from(value: NbtError) -> Self {
+        Self::Nbt(value)
+    }
+}
+
+/// Everything that couldn't be represented exactly while mapping a structure onto the grid.
+#[derive(Clone, Debug, Default)]
+pub struct ImportReport {
+    /// block states without an entry in the block list, with the positions they occurred at
+    pub unmapped: HashMap<String, Vec<[usize; 3]>>,
+    /// number of random-rotation blocks, whose rotation isn't stored in structure files
+    pub unknown_rotation: usize,
+    /// number of blocks that were mapped
+    pub mapped: usize,
+}
+
+impl ImportReport {
+    pub fn is_complete(&self) -> bool {
+        self.unmapped.is_empty()
+    }
+}
+
+impl Display for ImportReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "imported {} blocks, {} with unknown rotation",
+            self.mapped, self.unknown_rotation
+        )?;
+        let mut unmapped: Vec<_> = self.unmapped.iter().collect();
+        unmapped.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then(a.0.cmp(b.0)));
+        for (state, positions) in unmapped {
+            write!(
+                f,
+                "\n  couldn't map {state} ({} blocks, first at {:?})",
+                positions.len(),
+                positions[0]
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Structure {
+    pub fn load(path: &Path) -> Result<Self, ImportError> {
+        let format = StructureFormat::from_path(path)
+            .ok_or_else(|| ImportError::UnknownFormat(path.to_owned()))?;
+        Self::read(&fs::read(path)?, format)
+    }
+
+    pub fn read(data: &[u8], format: StructureFormat) -> Result<Self, ImportError> {
+        let (_, root) = nbt::read_compressed(data)?;
+        let structure = match format {
+            StructureFormat::Structure => Self::from_structure_nbt(&root),
+            StructureFormat::Sponge => Self::from_sponge(&root),
+            StructureFormat::Litematic => Self::from_litematic(&root),
+        }?;
+        check_bounds(structure.size, structure.blocks.iter().map(|(pos, _)| pos))?;
+        Ok(structure)
+    }
+
+    fn from_structure_nbt(root: &Tag) -> Result<Self, ImportError> {
+        let size = read_size_list(root.get("size"))?;
+        let palette = root
+            .get("palette")
+            .or_else(|| root.get("palettes").and_then(|v| v.as_list()?.first()))
+            .and_then(Tag::as_list)
+            .ok_or(ImportError::Malformed("missing palette"))?
+            .iter()
+            .map(BlockState::from_nbt)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut blocks = Vec::new();
+        for block in root
+            .get("blocks")
+            .and_then(Tag::as_list)
+            .ok_or(ImportError::Malformed("missing block list"))?
+        {
+            let pos = read_size_list(block.get("pos"))?;
+            let state = block
+                .get("state")
+                .and_then(Tag::as_i64)
+                .and_then(|state| palette.get(state as usize))
+                .ok_or(ImportError::Malformed("invalid palette index"))?;
+            blocks.push((pos, state.clone()));
+        }
+        Ok(Self { size, blocks })
+    }
+
+    fn from_sponge(root: &Tag) -> Result<Self, ImportError> {
+        // version 3 nests everything in a "Schematic" compound
+        let root = root.get("Schematic").unwrap_or(root);
+        let dimension = |name| {
+            root.get(name)
+                .and_then(Tag::as_i64)
+                .map(|v| v as u16 as usize)
+                .ok_or(ImportError::Malformed("missing schematic dimensions"))
+        };
+        let size = [
+            dimension("Width")?,
+            dimension("Height")?,
+            dimension("Length")?,
+        ];
+        let (palette, data) = match root.get("Blocks") {
+            Some(blocks) => (blocks.get("Palette"), blocks.get("Data")),
+            None => (root.get("Palette"), root.get("BlockData")),
+        };
+        let palette = palette
+            .and_then(Tag::as_compound)
+            .ok_or(ImportError::Malformed("missing palette"))?;
+        let mut states = vec![None; palette.len()];
+        for (state, index) in palette {
+            let index = index
+                .as_i64()
+                .and_then(|index| usize::try_from(index).ok())
+                .filter(|index| *index < states.len())
+                .ok_or(ImportError::Malformed("invalid palette index"))?;
+            states[index] = Some(BlockState::parse(state));
+        }
+        let data = data
+            .and_then(Tag::as_byte_array)
+            .ok_or(ImportError::Malformed("missing block data"))?;
+        let indices = read_varints(data)?;
+        if indices.len() != size[0] * size[1] * size[2] {
+            return Err(ImportError::Malformed(
+                "block data doesn't match dimensions",
+            ));
+        }
+        let mut blocks = Vec::new();
+        for (i, index) in indices.into_iter().enumerate() {
+            let pos = [
+                i % size[0],
+                i / (size[0] * size[2]),
+                (i / size[0]) % size[2],
+            ];
+            let state = states
+                .get(index as usize)
+                .and_then(Option::as_ref)
+                .ok_or(ImportError::Malformed("invalid palette index"))?;
+            blocks.push((pos, state.clone()));
+        }
+        Ok(Self { size, blocks })
+    }
+
+    fn from_litematic(root: &Tag) -> Result<Self, ImportError> {
+        let regions = root
+            .get("Regions")
+            .and_then(Tag::as_compound)
+            .ok_or(ImportError::Malformed("missing regions"))?;
+        let mut placed = Vec::new();
+        for region in regions.values() {
+            let position = read_vec(region.get("Position"))?;
+            let size = read_vec(region.get("Size"))?;
+            // negative sizes extend the region from its position towards negative coordinates
+            let min = [0, 1, 2].map(|i| position[i] + (size[i] + 1).min(0));
+            let size = size.map(|v| v.unsigned_abs() as usize);
+            let palette = region
+                .get("BlockStatePalette")
+                .and_then(Tag::as_list)
+                .ok_or(ImportError::Malformed("missing palette"))?
+                .iter()
+                .map(BlockState::from_nbt)
+                .collect::<Result<Vec<_>, _>>()?;
+            let states = region
+                .get("BlockStates")
+                .and_then(Tag::as_long_array)
+                .ok_or(ImportError::Malformed("missing block states"))?;
+            let bits = (usize::BITS - palette.len().saturating_sub(1).leading_zeros()).max(2);
+            for i in 0..size[0] * size[1] * size[2] {
+                let index = read_packed(states, bits, i)
+                    .ok_or(ImportError::Malformed("block states are too short"))?;
+                let state = palette
+                    .get(index as usize)
+                    .ok_or(ImportError::Malformed("invalid palette index"))?;
+                let pos = [
+                    (i % size[0]) as i64,
+                    (i / (size[0] * size[2])) as i64,
+                    ((i / size[0]) % size[2]) as i64,
+                ];
+                placed.push(([0, 1, 2].map(|j| pos[j] + min[j]), state.clone()));
+            }
+        }
+        let min = [0, 1, 2].map(|i| placed.iter().map(|(pos, _)| pos[i]).min().unwrap_or(0));
+        let max = [0, 1, 2].map(|i| placed.iter().map(|(pos, _)| pos[i]).max().unwrap_or(-1));
+        Ok(Self {
+            size: [0, 1, 2].map(|i| (max[i] - min[i] + 1) as usize),
+            blocks: placed
+                .into_iter()
+                .map(|(pos, state)| ([0, 1, 2].map(|i| (pos[i] - min[i]) as usize), state))
+                .collect(),
+        })
+    }
+
+    /// Maps the structure onto grid blocks, starting at the grid origin. The grid grows to fit
+    /// structures larger than `GRID_SIZE`.
+    ///
+    /// Blocks that have several random variants get [`UNKNOWN_ROTATION`], since the rendered
+    /// rotation isn't part of the block state.
+    pub fn to_grid(
+        &self,
+        block_provider: &MinecraftBlockProvider,
+    ) -> Result<(GridBlocks, ImportReport), ImportError> {
+        check_size(self.size)?;
+        check_bounds(self.size, self.blocks.iter().map(|(pos, _)| pos))?;
+        let mut grid = GridBlocks::for_pattern(self.size);
+        let mut report = ImportReport::default();
+        for (pos, state) in self.blocks.iter() {
+            if state.is_air() {
+                continue;
+            }
+            match block_provider.try_get_meta(state.short_name()) {
+                Some(meta) => {
+                    let variant = if meta.variants > 1 {
+                        report.unknown_rotation += 1;
+                        UNKNOWN_ROTATION
+                    } else {
+                        0
+                    };
+                    grid.blocks[one_d_cords(*pos, grid.size)] = BlockId(meta.id, variant);
+                    report.mapped += 1;
+                }
+                None => report
+                    .unmapped
+                    .entry(state.to_string())
+                    .or_default()
+                    .push(*pos),
+            }
+        }
+        Ok((grid, report))
+    }
+}
+
+/// Loads a pattern or structure file into grid blocks, depending on the file extension.
+pub fn load_grid(
+    path: &Path,
+    block_provider: &MinecraftBlockProvider,
+) -> Result<(GridBlocks, ImportReport), ImportError> {
+    if path.extension().and_then(|v| v.to_str()) == Some("pattern") {
+        Pattern::load(path)?.to_grid(block_provider)
+    } else {
+        Structure::load(path)?.to_grid(block_provider)
+    }
+}
+
+/// Makes sure a structure of `size` can be searched for, see `FinderJob::fits`.
+pub(crate) fn check_size(size: [usize; 3]) -> Result<(), ImportError> {
+    if size[0] > MAX_PATTERN_SIZE.0 || size[1] > MAX_PATTERN_SIZE.1 || size[2] > MAX_PATTERN_SIZE.2
+    {
+        return Err(ImportError::TooLarge(size));
+    }
+    Ok(())
+}
+
+/// Makes sure every block position is inside a structure of `size`, so it can be indexed with
+/// `one_d_cords`.
+pub(crate) fn check_bounds<'a>(
+    size: [usize; 3],
+    positions: impl IntoIterator<Item = &'a [usize; 3]>,
+) -> Result<(), ImportError> {
+    match positions
+        .into_iter()
+        .find(|pos| (0..3).any(|i| pos[i] >= size[i]))
+    {
+        Some(pos) => Err(ImportError::OutOfBounds(*pos)),
+        None => Ok(()),
+    }
+}
+
+fn read_size_list(tag: Option<&Tag>) -> Result<[usize; 3], ImportError> {
+    match tag.and_then(Tag::as_list) {
+        Some([x, y, z]) => match (x.as_i64(), y.as_i64(), z.as_i64()) {
+            (Some(x), Some(y), Some(z)) if x >= 0 && y >= 0 && z >= 0 => {
+                Ok([x as usize, y as usize, z as usize])
+            }
+            _ => Err(ImportError::Malformed("invalid position")),
+        },
+        _ => Err(ImportError::Malformed("invalid position")),
+    }
+}
+
+fn read_vec(tag: Option<&Tag>) -> Result<[i64; 3], ImportError> {
+    let tag = tag.ok_or(ImportError::Malformed("missing region position"))?;
+    let get = |name| {
+        tag.get(name)
+            .and_then(Tag::as_i64)
+            .ok_or(ImportError::Malformed("invalid region position"))
+    };
+    Ok([get("x")?, get("y")?, get("z")?])
+}
+
+/// Decodes the varint encoded palette indices of Sponge schematics.
+fn read_varints(data: &[i8]) -> Result<Vec<u32>, ImportError> {
+    let mut values = Vec::with_capacity(data.len());
+    let mut value = 0u32;
+    let mut shift = 0;
+    for byte in data.iter().map(|v| *v as u8) {
+        if shift >= 32 {
+            return Err(ImportError::Malformed("varint is too long"));
+        }
+        value |= ((byte & 0x7F) as u32) << shift;
+        if byte & 0x80 == 0 {
+            values.push(value);
+            value = 0;
+            shift = 0;
+        } else {
+            shift += 7;
+        }
+    }
+    if shift != 0 {
+        return Err(ImportError::Malformed("truncated varint"));
+    }
+    Ok(values)
+}
+
+/// Reads entry `index` of Litematica's bit array, where entries may span two longs.
+fn read_packed(data: &[i64], bits: u32, index: usize) -> Option<u64> {
+    let mask = (1u64 << bits) - 1;
+    let start_bit = index * bits as usize;
+    let start = start_bit / 64;
+    let end = (start_bit + bits as usize - 1) / 64;
+    let offset = start_bit % 64;
+    let low = (*data.get(start)? as u64) >> offset;
+    if start == end {
+        Some(low & mask)
+    } else {
+        Some((low | ((*data.get(end)? as u64) << (64 - offset))) & mask)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::utils::HashMap;
+    use bevy_meshem::prelude::one_d_cords;
+
+    use super::{
+        check_bounds, read_packed, read_varints, BlockState, ImportError, Structure,
+        StructureFormat,
+    };
+    use crate::{
+        constants::UNKNOWN_ROTATION,
+        game_assets::{BlockId, MinecraftBlockProvider},
+        nbt::{self, compound, Tag},
+    };
+
+    fn ints(values: [i32; 3]) -> Tag {
+        Tag::List(values.map(Tag::Int).to_vec())
+    }
+
+    fn xyz(values: [i32; 3]) -> Tag {
+        compound([
+            ("x", Tag::Int(values[0])),
+            ("y", Tag::Int(values[1])),
+            ("z", Tag::Int(values[2])),
+        ])
+    }
+
+    fn state(name: &str) -> Tag {
+        compound([("Name", Tag::String(name.to_owned()))])
+    }
+
+    fn sponge(palette: [(&str, i32); 3], data: Vec<i8>) -> Vec<u8> {
+        let palette = palette.map(|(name, index)| (name, Tag::Int(index)));
+        nbt::write(
+            "Schematic",
+            &compound([
+                ("Width", Tag::Short(2)),
+                ("Height", Tag::Short(1)),
+                ("Length", Tag::Short(2)),
+                ("Palette", compound(palette)),
+                ("BlockData", Tag::ByteArray(data)),
+            ]),
+        )
+    }
+
+    #[test]
+    fn test_decoding() {
+        assert_eq!(
+            read_varints(&[1, -128, 1, 0, -1, 127]).unwrap(),
+            vec![1, 128, 0, 16383]
+        );
+        // 5 bit entries 0..=20 packed back to back, entry 12 spans both longs
+        let mut data = [0u64; 2];
+        for i in 0..20u64 {
+            data[(i * 5 / 64) as usize] |= i << (i * 5 % 64);
+            if i * 5 % 64 > 59 {
+                data[1] |= i >> (64 - i * 5 % 64);
+            }
+        }
+        let data = data.map(|v| v as i64);
+        for i in 0..20 {
+            assert_eq!(read_packed(&data, 5, i), Some(i as u64));
+        }
+        let state = BlockState::parse("minecraft:grass_block[snowy=false]");
+        assert_eq!(state.short_name(), "grass_block");
+        assert_eq!(state.to_string(), "minecraft:grass_block[snowy=false]");
+    }
+
+    #[test]
+    fn test_check_bounds() {
+        assert!(check_bounds([2, 1, 2], &[[0, 0, 0], [1, 0, 1]]).is_ok());
+        assert!(matches!(
+            check_bounds([2, 1, 2], &[[0, 0, 0], [1, 1, 0]]),
+            Err(ImportError::OutOfBounds([1, 1, 0]))
+        ));
+    }
+
+    #[test]
+    fn test_structure_formats() {
+        let provider =
+            MinecraftBlockProvider::from_blocks(&[("stone", 1), ("dirt", 1), ("oak_log", 2)]);
+
+        let data = nbt::write(
+            "",
+            &compound([
+                ("size", ints([2, 1, 1])),
+                (
+                    "palette",
+                    Tag::List(vec![
+                        state("minecraft:stone"),
+                        compound([
+                            ("Name", Tag::String("minecraft:oak_log".to_owned())),
+                            (
+                                "Properties",
+                                compound([("axis", Tag::String("y".to_owned()))]),
+                            ),
+                        ]),
+                    ]),
+                ),
+                (
+                    "blocks",
+                    Tag::List(vec![
+                        compound([("pos", ints([0, 0, 0])), ("state", Tag::Int(0))]),
+                        compound([("pos", ints([1, 0, 0])), ("state", Tag::Int(1))]),
+                    ]),
+                ),
+            ]),
+        );
+        let structure = Structure::read(&data, StructureFormat::Structure).unwrap();
+        assert_eq!(structure.size, [2, 1, 1]);
+        assert_eq!(
+            structure.blocks[1],
+            ([1, 0, 0], BlockState::parse("minecraft:oak_log[axis=y]"))
+        );
+        let (grid, report) = structure.to_grid(&provider).unwrap();
+        assert_eq!(
+            grid.blocks[one_d_cords([0, 0, 0], grid.size)],
+            BlockId(0, 0)
+        );
+        assert_eq!(
+            grid.blocks[one_d_cords([1, 0, 0], grid.size)],
+            BlockId(2, UNKNOWN_ROTATION)
+        );
+        assert_eq!((report.mapped, report.unknown_rotation), (2, 1));
+        assert!(report.is_complete());
+
+        // 2x1x2, stored x first, then z
+        let data = sponge(
+            [
+                ("minecraft:stone", 0),
+                ("minecraft:air", 1),
+                ("minecraft:diamond_ore", 2),
+            ],
+            vec![0, 1, 2, 0],
+        );
+        let structure = Structure::read(&data, StructureFormat::Sponge).unwrap();
+        assert_eq!(structure.size, [2, 1, 2]);
+        let (grid, report) = structure.to_grid(&provider).unwrap();
+        assert_eq!(
+            grid.blocks[one_d_cords([1, 0, 1], grid.size)],
+            BlockId(0, 0)
+        );
+        assert_eq!(report.mapped, 2);
+        assert_eq!(
+            report.unmapped,
+            HashMap::from_iter([("minecraft:diamond_ore".to_owned(), vec![[0, 0, 1]])])
+        );
+        assert!(report
+            .to_string()
+            .contains("couldn't map minecraft:diamond_ore (1 blocks, first at [0, 0, 1])"));
+
+        // a region extending from x 5 towards negative x, with 2 bit entries stone and dirt
+        let data = nbt::write(
+            "",
+            &compound([(
+                "Regions",
+                compound([(
+                    "main",
+                    compound([
+                        ("Position", xyz([5, 0, 0])),
+                        ("Size", xyz([-2, 1, 1])),
+                        (
+                            "BlockStatePalette",
+                            Tag::List(vec![
+                                state("minecraft:air"),
+                                state("minecraft:stone"),
+                                state("minecraft:dirt"),
+                            ]),
+                        ),
+                        ("BlockStates", Tag::LongArray(vec![1 | 2 << 2])),
+                    ]),
+                )]),
+            )]),
+        );
+        let structure = Structure::read(&data, StructureFormat::Litematic).unwrap();
+        assert_eq!(structure.size, [2, 1, 1]);
+        let (grid, report) = structure.to_grid(&provider).unwrap();
+        assert_eq!(
+            grid.blocks[one_d_cords([0, 0, 0], grid.size)],
+            BlockId(0, 0)
+        );
+        assert_eq!(
+            grid.blocks[one_d_cords([1, 0, 0], grid.size)],
+            BlockId(1, 0)
+        );
+        assert_eq!(report.mapped, 2);
+    }
+
+    #[test]
+    fn test_malformed_structures() {
+        let palette = |last| {
+            [
+                ("minecraft:stone", 0),
+                ("minecraft:air", 1),
+                ("minecraft:dirt", last),
+            ]
+        };
+        for last in [3, -1, i32::MAX] {
+            assert!(matches!(
+                Structure::read(&sponge(palette(last), vec![0; 4]), StructureFormat::Sponge),
+                Err(ImportError::Malformed("invalid palette index"))
+            ));
+        }
+        // indices without a palette entry, and data that doesn't fill the dimensions
+        assert!(matches!(
+            Structure::read(
+                &sponge(palette(2), vec![0, 1, 5, 0]),
+                StructureFormat::Sponge
+            ),
+            Err(ImportError::Malformed("invalid palette index"))
+        ));
+        assert!(matches!(
+            Structure::read(&sponge(palette(2), vec![0, 1]), StructureFormat::Sponge),
+            Err(ImportError::Malformed(
+                "block data doesn't match dimensions"
+            ))
+        ));
+        let data = nbt::write(
+            "",
+            &compound([
+                ("size", ints([1, 1, 1])),
+                ("palette", Tag::List(vec![state("minecraft:stone")])),
+                (
+                    "blocks",
+                    Tag::List(vec![compound([
+                        ("pos", ints([0, 1, 0])),
+                        ("state", Tag::Int(0)),
+                    ])]),
+                ),
+            ]),
+        );
+        assert!(matches!(
+            Structure::read(&data, StructureFormat::Structure),
+            Err(ImportError::OutOfBounds([0, 1, 0]))
+        ));
+        assert!(matches!(
+            Structure::read(&data[..data.len() - 3], StructureFormat::Structure),
+            Err(ImportError::Nbt(_))
+        ));
+        assert!(matches!(
+            Structure::read(&data, StructureFormat::Litematic),
+            Err(ImportError::Malformed("missing regions"))
+        ));
+    }
+}
@@ -0,0 +1,358 @@
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+
+use crate::{
+    analysis::{format_duration, SearchRadius},
+    finder::{
+        plugin::FinderStatus,
+        queue::{JobInfo, JobQueue, JobState},
+    },
+    game_assets::MinecraftBlockProvider,
+    grid::Grid,
+    AppState,
+};
+
+/// Lists the jobs of the [`JobQueue`] next to the builder, so more patterns can be searched
+/// without restarting the app.
+///
+/// Enter searches the current pattern within the search radius before the queued ones and
+/// follows it, Q queues it, `[` and `]` change the priority of the next one, Tab selects a queued
+/// job, Page Up and Page Down change its priority and Delete cancels the followed job, or the
+/// running one.
+pub struct JobPanelPlugin;
+
+#[derive(Component)]
+struct JobPanel;
+
+#[derive(Component)]
+struct JobPanelLabel;
+
+/// Priority given to the next queued pattern.
+#[derive(Resource, Default)]
+struct NextPriority(i32);
+
+/// Queued job whose priority Page Up and Page Down change.
+#[derive(Resource, Default)]
+struct SelectedJob(Option<u32>);
+
+/// Job started with Enter, whose progress is shown while searching.
+#[derive(Resource)]
+struct SearchedJob(u32);
+
+impl Plugin for JobPanelPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<JobQueue>()
+            .init_resource::<NextPriority>()
+            .init_resource::<SelectedJob>()
+            .add_systems(OnEnter(AppState::Building), setup_job_panel)
+            .add_systems(
+                Update,
+                (handle_job_inputs, update_job_panel).run_if(in_state(AppState::Building)),
+            )
+            .add_systems(
+                Update,
+                cancel_job
+                    .run_if(in_state(AppState::Building).or_else(in_state(AppState::Searching))),
+            )
+            .add_systems(
+                Update,
+                follow_searched_job
+                    .run_if(in_state(AppState::Searching).and_then(resource_exists::<SearchedJob>)),
+            )
+            .add_systems(OnExit(AppState::Building), remove_job_panel);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn handle_job_inputs(
+    mut commands: Commands,
+    inputs: Res<ButtonInput<KeyCode>>,
+    voxel_registry: Res<MinecraftBlockProvider>,
+    grid: Res<Grid>,
+    radius: Res<SearchRadius>,
+    queue: Res<JobQueue>,
+    mut priority: ResMut<NextPriority>,
+    mut selected: ResMut<SelectedJob>,
+    mut finder_status: ResMut<FinderStatus>,
+    mut state: ResMut<NextState<AppState>>,
+) {
+    if inputs.just_pressed(KeyCode::Enter) {
+        let job = grid
+            .to_job(voxel_registry.as_ref())
+            .with_y_levels_from_env();
+        let jobs = queue.jobs();
+        let name = format!("pattern {}", jobs.len() + 1);
+        let id = queue.submit(name, job, Some(radius.0), search_priority(&jobs));
+        info!("searching job {id}");
+        commands.insert_resource(SearchedJob(id));
+        *finder_status = FinderStatus::WaitingForJob;
+        *state.as_mut() = NextState::Pending(AppState::Searching);
+    }
+    if inputs.just_pressed(KeyCode::BracketRight) {
+        priority.0 += 1;
+    }
+    if inputs.just_pressed(KeyCode::BracketLeft) {
+        priority.0 -= 1;
+    }
+    if inputs.just_pressed(KeyCode::KeyQ) {
+        let job = grid
+            .to_job(voxel_registry.as_ref())
+            .with_y_levels_from_env();
+        let name = format!("pattern {}", queue.jobs().len() + 1);
+        let id = queue.submit(name, job, Some(radius.0), priority.0);
+        info!("queued job {id} with priority {}", priority.0);
+    }
+    if inputs.just_pressed(KeyCode::Tab) {
+        selected.0 = next_queued(&queue.jobs(), selected.0);
+    }
+    for (key, change) in [(KeyCode::PageUp, 1), (KeyCode::PageDown, -1)] {
+        if !inputs.just_pressed(key) {
+            continue;
+        }
+        let Some((id, new_priority)) = changed_priority(&queue.jobs(), selected.0, change) else {
+            continue;
+        };
+        // the job may have started since the list was read
+        if queue.set_priority(id, new_priority) {
+            info!("job {id} now has priority {new_priority}");
+        } else {
+            selected.0 = None;
+        }
+    }
+}
+
+fn cancel_job(
+    inputs: Res<ButtonInput<KeyCode>>,
+    queue: Res<JobQueue>,
+    searched: Option<Res<SearchedJob>>,
+) {
+    if !inputs.just_pressed(KeyCode::Delete) {
+        return;
+    }
+    let running = || {
+        queue
+            .jobs()
+            .into_iter()
+            .find(|info| info.state == JobState::Running)
+            .map(|info| info.id)
+    };
+    if let Some(id) = searched.map(|searched| searched.0).or_else(running) {
+        if queue.cancel(id) {
+            info!("cancelled job {id}");
+        }
+    }
+}
+
+/// A priority above every queued job, so the job starts once the running one is done.
+fn search_priority(jobs: &[JobInfo]) -> i32 {
+    jobs.iter()
+        .filter(|info| info.state == JobState::Queued)
+        .map(|info| info.priority.saturating_add(1))
+        .max()
+        .unwrap_or(0)
+}
+
+/// The queued job listed after `selected`, or the first one.
+fn next_queued(jobs: &[JobInfo], selected: Option<u32>) -> Option<u32> {
+    let mut queued = jobs
+        .iter()
+        .filter(|info| info.state == JobState::Queued)
+        .map(|info| info.id);
+    let first = queued.clone().next();
+    queued
+        .find(|id| selected.is_some_and(|selected| *id > selected))
+        .or(first)
+}
+
+/// The selected job and its priority changed by `change`, if it's still queued.
+fn changed_priority(jobs: &[JobInfo], selected: Option<u32>, change: i32) -> Option<(u32, i32)> {
+    let info = jobs
+        .iter()
+        .find(|info| Some(info.id) == selected && info.state == JobState::Queued)?;
+    Some((info.id, info.priority.saturating_add(change)))
+}
+
+/// Shows the progress of the searched job until it ends.
+fn follow_searched_job(
+    mut commands: Commands,
+    searched: Res<SearchedJob>,
+    queue: Res<JobQueue>,
+    mut finder_status: ResMut<FinderStatus>,
+) {
+    let (Some(info), Some(status)) = (queue.get(searched.0), queue.status(searched.0)) else {
+        return;
+    };
+    *finder_status = status;
+    if !info.state.is_done() {
+        return;
+    }
+    match info.state {
+        JobState::Found(_) => info!("{}", job_line(&info)),
+        _ => error!("{}", job_line(&info)),
+    }
+    commands.remove_resource::<SearchedJob>();
+}
+
+fn setup_job_panel(mut commands: Commands) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    right: Val::Px(0.),
+                    width: Val::Percent(25.0),
+                    flex_direction: FlexDirection::Column,
+                    ..default()
+                },
+                background_color: Color::srgb(0.15, 0.15, 0.15).into(),
+                ..default()
+            },
+            JobPanel,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section("", TextStyle::default()).with_style(Style {
+                    margin: UiRect::all(Val::Px(5.)),
+                    ..default()
+                }),
+                JobPanelLabel,
+            ));
+        });
+}
+
+fn update_job_panel(
+    queue: Res<JobQueue>,
+    priority: Res<NextPriority>,
+    selected: Res<SelectedJob>,
+    mut label: Query<&mut Text, With<JobPanelLabel>>,
+    mut last_update: Local<Option<Instant>>,
+) {
+    // the queue is polled, so only a few times a second
+    if last_update.is_some_and(|last| last.elapsed() < Duration::from_millis(250))
+        && !priority.is_changed()
+        && !selected.is_changed()
+    {
+        return;
+    }
+    *last_update = Some(Instant::now());
+    let Ok(mut label) = label.get_single_mut() else {
+        return;
+    };
+    let mut text = format!(
+        "jobs (Enter: search first, Q: queue with priority {}, [ ]: priority,\n\
+         Tab: select, Page Up/Down: change its priority, Delete: cancel)",
+        priority.0
+    );
+    for info in queue.jobs() {
+        text.push_str(match selected.0 == Some(info.id) {
+            true => "\n> ",
+            false => "\n",
+        });
+        text.push_str(&job_line(&info));
+    }
+    *label.as_mut() = Text::from_section(
+        text,
+        TextStyle {
+            font_size: 20.0,
+            ..default()
+        },
+    );
+}
+
+fn job_line(info: &JobInfo) -> String {
+    let mut formatter = human_format::Formatter::new();
+    formatter.with_decimals(1);
+    let progress = match &info.state {
+        JobState::Queued => String::new(),
+        JobState::Running => format!(
+            ", {} blocks/s, eta {}",
+            formatter.format(info.rate),
+            info.eta
+                .map(format_duration)
+                .unwrap_or_else(|| "unknown".to_owned())
+        ),
+        JobState::Found(found) => format!(" at {} {} {}", found.pos.x, found.pos.y, found.pos.z),
+        JobState::Failed(err) => format!(": {err}"),
+        JobState::NotFound | JobState::Cancelled => format!(
+            " after {} blocks in {}",
+            formatter.format(info.blocks as f64),
+            format_duration(info.elapsed)
+        ),
+    };
+    format!(
+        "#{} {} [{}] {}{progress}",
+        info.id,
+        info.name,
+        info.priority,
+        info.state.name()
+    )
+}
+
+fn remove_job_panel(mut commands: Commands, panel: Query<Entity, With<JobPanel>>) {
+    for entity in panel.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{changed_priority, next_queued, search_priority};
+    use crate::finder::queue::{JobInfo, JobState};
+
+    fn job(id: u32, priority: i32, state: JobState) -> JobInfo {
+        JobInfo {
+            id,
+            name: format!("pattern {id}"),
+            priority,
+            state,
+            blocks: 0,
+            total_blocks: None,
+            elapsed: Duration::ZERO,
+            rate: 0.0,
+            eta: None,
+        }
+    }
+
+    #[test]
+    fn test_search_priority() {
+        assert_eq!(search_priority(&[]), 0);
+        let jobs = [
+            job(0, 7, JobState::Running),
+            job(1, 2, JobState::Queued),
+            job(2, 9, JobState::Cancelled),
+            job(3, -4, JobState::Queued),
+        ];
+        // only the queued jobs have to start after the searched one
+        assert_eq!(search_priority(&jobs), 3);
+        assert_eq!(search_priority(&jobs[3..]), -3);
+        assert_eq!(
+            search_priority(&[job(0, i32::MAX, JobState::Queued)]),
+            i32::MAX
+        );
+    }
+
+    #[test]
+    fn test_job_selection() {
+        let jobs = [
+            job(0, 0, JobState::Running),
+            job(1, 0, JobState::Queued),
+            job(2, 0, JobState::NotFound),
+            job(3, 5, JobState::Queued),
+        ];
+        assert_eq!(next_queued(&jobs, None), Some(1));
+        assert_eq!(next_queued(&jobs, Some(1)), Some(3));
+        assert_eq!(next_queued(&jobs, Some(3)), Some(1));
+        // a selected job that started moves on to the next queued one
+        assert_eq!(next_queued(&jobs, Some(0)), Some(1));
+        assert_eq!(next_queued(&jobs[..3], Some(1)), Some(1));
+        assert_eq!(next_queued(&jobs[..1], Some(0)), None);
+
+        assert_eq!(changed_priority(&jobs, Some(3), 1), Some((3, 6)));
+        assert_eq!(changed_priority(&jobs, Some(1), -1), Some((1, -1)));
+        assert_eq!(changed_priority(&jobs, Some(0), 1), None);
+        assert_eq!(changed_priority(&jobs, None, 1), None);
+    }
+}
@@ -7,28 +7,33 @@ use std::{
 
 #[allow(unused)]
 use bevy::prelude::*;
-use bevy::{
-    asset::io::AssetSourceBuilder,
-    render::{
-        settings::{RenderCreation, WgpuFeatures, WgpuSettings},
-        RenderPlugin,
-    },
+use bevy::render::{
+    settings::{RenderCreation, WgpuSettings},
+    RenderPlugin,
 };
 use bevy_flycam::prelude::*;
 use bevy_mod_raycast::prelude::*;
 use finder::plugin::GPUFinderPlugin;
+use import::PatternImport;
 use zip::ZipArchive;
-#[cfg(not(debug_assertions))]
-use crate::shader_assets::embedded_shader_source;
 
+pub mod analysis;
+pub mod bench;
 pub mod block_list;
 pub mod builder;
+pub mod cli;
 pub mod constants;
 pub mod finder;
 pub mod game_assets;
 pub mod grid;
-#[cfg(not(debug_assertions))]
-pub mod shader_assets;
+pub mod import;
+pub mod jobs;
+pub mod nbt;
+pub mod paths;
+pub mod pattern;
+pub mod server;
+pub mod verify;
+pub mod world;
 
 #[derive(Clone, PartialEq, Eq, Hash, Debug, Default, States)]
 enum AppState {
@@ -37,43 +42,51 @@ enum AppState {
     BuildingInit,
     Building,
     Searching,
+    Verifying,
     Finished,
 }
 
 fn main() {
-    if !Path::new("assets").exists() {
-        copy_minecraft_assets();
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Some(code) = cli::run(&args) {
+        std::process::exit(code);
     }
+    ensure_minecraft_assets();
     let mut render_plugin = RenderPlugin::default();
-    let mut wgpu_settings = WgpuSettings::default();
-    wgpu_settings.features = wgpu_settings.features.union(WgpuFeatures::SHADER_INT64);
-    println!("{:?}", wgpu_settings.features);
+    // every feature of the adapter is requested, SHADER_INT64 is only used when it's there
+    let wgpu_settings = WgpuSettings::default();
     render_plugin.render_creation = RenderCreation::Automatic(wgpu_settings);
-    App::new()
-        .register_asset_source(
-            "shader",
-            #[cfg(debug_assertions)]
-            AssetSourceBuilder::platform_default("shaders", None),
-            #[cfg(not(debug_assertions))]
-            embedded_shader_source(),
-        )
-        .add_plugins(
-            DefaultPlugins
-                .set(ImagePlugin::default_nearest())
-                .set(render_plugin),
-        )
-        .add_plugins(PlayerPlugin)
-        .init_state::<AppState>()
-        .add_plugins(game_assets::GameAssetsPlugin)
-        .add_plugins(grid::GridPlugin)
-        .add_plugins(builder::BuilderPlugin)
-        .add_plugins(DeferredRaycastingPlugin::<()>::default())
-        .add_plugins(GPUFinderPlugin)
-        .insert_resource(AmbientLight {
-            brightness: 1250.0,
-            color: Color::WHITE,
-        })
-        .run();
+    let mut app = App::new();
+    if let Some(path) = args.first() {
+        app.insert_resource(PatternImport(path.into()));
+    }
+    app.add_plugins(
+        DefaultPlugins
+            .set(ImagePlugin::default_nearest())
+            .set(render_plugin),
+    )
+    .add_plugins(PlayerPlugin)
+    .init_state::<AppState>()
+    .add_plugins(game_assets::GameAssetsPlugin)
+    .add_plugins(grid::GridPlugin)
+    .add_plugins(builder::BuilderPlugin)
+    .add_plugins(jobs::JobPanelPlugin)
+    .add_plugins(DeferredRaycastingPlugin::<()>::default())
+    .add_plugins(GPUFinderPlugin)
+    .add_plugins(verify::VerifyPlugin)
+    .insert_resource(AmbientLight {
+        brightness: 1250.0,
+        color: Color::WHITE,
+    })
+    .run();
+}
+
+/// Copies the assets out of the installed game unless they are there already, only the GUI and
+/// the commands that look up block models need them.
+fn ensure_minecraft_assets() {
+    if !Path::new("assets").exists() {
+        copy_minecraft_assets();
+    }
 }
 
 fn copy_minecraft_assets() {
@@ -142,7 +155,7 @@ fn get_minecraft_version_path() -> PathBuf {
 
 #[cfg(test)]
 mod test {
-    use crate::finder::util::{get_block_rotation, get_rendering_seed};
+    use crate::finder::util::{block_rotations_fns, get_block_rotation, get_rendering_seed};
 
     #[test]
     fn test_generator() {
@@ -156,4 +169,106 @@ mod test {
             );
         }
     }
+
+    #[test]
+    fn test_packed_generator() {
+        let data: &[(i64, i64, i64, i64, u8)] = &include!("testdata.txt");
+        let positions = data.iter().map(|(x, y, z, _, _)| (*x, *y, *z)).chain([
+            (-30_000_000, 319, 29_999_984),
+            (-8, -64, -1),
+            (i32::MAX as i64, 0, 0),
+        ]);
+        for (x, y, z) in positions {
+            for (name, get_block_rotations) in block_rotations_fns() {
+                let packed = get_block_rotations(x, y, z);
+                for i in 0..16 {
+                    assert_eq!(
+                        (packed >> (i * 2)) as u8 & 3,
+                        get_block_rotation(x + i, y, z),
+                        "wrong {name} rotation at {},{y},{z}",
+                        x + i
+                    );
+                }
+            }
+        }
+    }
+
+    // mirrors the emulated 64 bit arithmetic of the shaders, (low, high) like their vec2<u32>
+    type U64 = (u32, u32);
+
+    fn from_i32(value: i32) -> U64 {
+        (value as u32, if value < 0 { u32::MAX } else { 0 })
+    }
+
+    fn add64(a: U64, b: U64) -> U64 {
+        let low = a.0.wrapping_add(b.0);
+        (low, a.1.wrapping_add(b.1).wrapping_add((low < a.0) as u32))
+    }
+
+    fn mul32(a: u32, b: u32) -> U64 {
+        let (a0, a1, b0, b1) = (a & 0xFFFF, a >> 16, b & 0xFFFF, b >> 16);
+        let (p00, p01, p10) = (a0 * b0, a0 * b1, a1 * b0);
+        let mid = (p00 >> 16) + (p01 & 0xFFFF) + (p10 & 0xFFFF);
+        (
+            (p00 & 0xFFFF) | (mid << 16),
+            (a1 * b1) + (p01 >> 16) + (p10 >> 16) + (mid >> 16),
+        )
+    }
+
+    fn mul64(a: U64, b: U64) -> U64 {
+        let low = mul32(a.0, b.0);
+        (
+            low.0,
+            low.1
+                .wrapping_add(a.0.wrapping_mul(b.1))
+                .wrapping_add(a.1.wrapping_mul(b.0)),
+        )
+    }
+
+    fn xor64(a: U64, b: U64) -> U64 {
+        (a.0 ^ b.0, a.1 ^ b.1)
+    }
+
+    fn shr16(value: U64) -> U64 {
+        (
+            (value.0 >> 16) | (value.1 << 16),
+            ((value.1 as i32) >> 16) as u32,
+        )
+    }
+
+    fn emulated_rendering_seed(x: i32, y: i32, z: i32) -> U64 {
+        let l = xor64(
+            xor64(
+                mul64(from_i32(x), (3129871, 0)),
+                mul64(from_i32(z), (116129781, 0)),
+            ),
+            from_i32(y),
+        );
+        add64(mul64(mul64(l, l), (42317861, 0)), mul64(l, (11, 0)))
+    }
+
+    fn emulated_rotation_from_seed(seed: U64) -> i32 {
+        let seed = (seed.0 ^ 0xDEECE66D, (seed.1 ^ 0x5) & 0xFFFF);
+        let value = add64(mul64(seed, (0xB4600A69, 0xBB20)), (0x942DE6BA, 0x40));
+        ((value.0 >> 16) | (value.1 << 16)) as i32
+    }
+
+    #[test]
+    fn test_emulated_generator() {
+        let data: &[(i64, i64, i64, i64, u8)] = &include!("testdata.txt");
+        for (x, y, z, seed, rotation) in data {
+            let (x, y, z) = (*x as i32, *y as i32, *z as i32);
+            let emulated_seed = emulated_rendering_seed(x, y, z);
+            assert_eq!(
+                emulated_seed,
+                (*seed as u64 as u32, (*seed as u64 >> 32) as u32)
+            );
+            let emulated_rotation =
+                emulated_rotation_from_seed(shr16(emulated_seed)).unsigned_abs() & 3;
+            assert_eq!(
+                emulated_rotation as u8, *rotation,
+                "wrong rotation at {x},{y},{z}"
+            );
+        }
+    }
 }
@@ -0,0 +1,378 @@
+use std::{
+    fmt::Display,
+    io::{self, Read},
+};
+
+use bevy::utils::HashMap;
+use flate2::read::{GzDecoder, ZlibDecoder};
+
+/// Lists and compounds nested deeper than this are rejected, like Minecraft does.
+const MAX_DEPTH: usize = 512;
+/// Structures and chunks are far smaller, anything larger is rejected while it's decompressed.
+const MAX_DECOMPRESSED: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tag {
+    End,
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    List(Vec<Tag>),
+    Compound(HashMap<String, Tag>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+#[derive(Debug)]
+pub enum NbtError {
+    Io(io::Error),
+    InvalidTag(u8),
+    InvalidString,
+    NegativeLength(i32),
+    TooDeep,
+    TooLarge,
+}
+
+impl Display for NbtError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NbtError::Io(err) => write!(f, "couldn't read nbt data: {err}"),
+            NbtError::InvalidTag(id) => write!(f, "invalid nbt tag id {id}"),
+            NbtError::InvalidString => f.write_str("nbt string is not valid utf-8"),
+            NbtError::NegativeLength(len) => write!(f, "negative nbt array length {len}"),
+            NbtError::TooDeep => write!(f, "nbt tags are nested deeper than {MAX_DEPTH}"),
+            NbtError::TooLarge => write!(f, "nbt data is larger than {MAX_DECOMPRESSED} bytes"),
+        }
+    }
+}
+
+impl std::error::Error for NbtError {}
+
+impl From<io::Error> for NbtError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl Tag {
+    pub fn get(&self, key: &str) -> Option<&Tag> {
+        match self {
+            Tag::Compound(map) => map.get(key),
+            _ => None,
+        }
+    }
+    pub fn as_compound(&self) -> Option<&HashMap<String, Tag>> {
+        match self {
+            Tag::Compound(map) => Some(map),
+            _ => None,
+        }
+    }
+    pub fn as_list(&self) -> Option<&[Tag]> {
+        match self {
+            Tag::List(list) => Some(list),
+            _ => None,
+        }
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Tag::String(value) => Some(value),
+            _ => None,
+        }
+    }
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Tag::Byte(value) => Some(*value as i64),
+            Tag::Short(value) => Some(*value as i64),
+            Tag::Int(value) => Some(*value as i64),
+            Tag::Long(value) => Some(*value),
+            _ => None,
+        }
+    }
+    pub fn as_byte_array(&self) -> Option<&[i8]> {
+        match self {
+            Tag::ByteArray(value) => Some(value),
+            _ => None,
+        }
+    }
+    pub fn as_long_array(&self) -> Option<&[i64]> {
+        match self {
+            Tag::LongArray(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// Reads a gzip compressed nbt file, as written by structure blocks, WorldEdit and Litematica.
+/// Uncompressed data is accepted as well.
+pub fn read_compressed(data: &[u8]) -> Result<(String, Tag), NbtError> {
+    match data {
+        [0x1f, 0x8b, ..] => read(&mut decompress(GzDecoder::new(data))?.as_slice()),
+        [0x78, ..] => read(&mut decompress(ZlibDecoder::new(data))?.as_slice()),
+        _ => read(&mut &data[..]),
+    }
+}
+
+fn decompress(decoder: impl Read) -> Result<Vec<u8>, NbtError> {
+    let mut decompressed = Vec::new();
+    decoder
+        .take(MAX_DECOMPRESSED + 1)
+        .read_to_end(&mut decompressed)?;
+    if decompressed.len() as u64 > MAX_DECOMPRESSED {
+        return Err(NbtError::TooLarge);
+    }
+    Ok(decompressed)
+}
+
+/// Reads the named root tag of an uncompressed nbt stream.
+pub fn read(reader: &mut impl Read) -> Result<(String, Tag), NbtError> {
+    let id = read_u8(reader)?;
+    if id == 0 {
+        return Ok((String::new(), Tag::End));
+    }
+    let name = read_string(reader)?;
+    let tag = read_payload(reader, id, 0)?;
+    Ok((name, tag))
+}
+
+/// Reads a tag inside `depth` lists and compounds.
+fn read_payload(reader: &mut impl Read, id: u8, depth: usize) -> Result<Tag, NbtError> {
+    if depth > MAX_DEPTH {
+        return Err(NbtError::TooDeep);
+    }
+    Ok(match id {
+        0 => Tag::End,
+        1 => Tag::Byte(read_u8(reader)? as i8),
+        2 => Tag::Short(i16::from_be_bytes(read_array(reader)?)),
+        3 => Tag::Int(i32::from_be_bytes(read_array(reader)?)),
+        4 => Tag::Long(i64::from_be_bytes(read_array(reader)?)),
+        5 => Tag::Float(f32::from_be_bytes(read_array(reader)?)),
+        6 => Tag::Double(f64::from_be_bytes(read_array(reader)?)),
+        7 => {
+            let len = read_len(reader)?;
+            // the length isn't trusted, the data grows as it's read
+            let mut data = Vec::with_capacity(len.min(4096));
+            reader.by_ref().take(len as u64).read_to_end(&mut data)?;
+            if data.len() < len {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            Tag::ByteArray(data.into_iter().map(|v| v as i8).collect())
+        }
+        8 => Tag::String(read_string(reader)?),
+        9 => {
+            let element_id = read_u8(reader)?;
+            let len = read_len(reader)?;
+            // end tags take no space, only empty lists may have them
+            if element_id == 0 && len > 0 {
+                return Err(NbtError::InvalidTag(0));
+            }
+            let mut list = Vec::with_capacity(len.min(4096));
+            for _ in 0..len {
+                list.push(read_payload(reader, element_id, depth + 1)?);
+            }
+            Tag::List(list)
+        }
+        10 => {
+            let mut map = HashMap::default();
+            loop {
+                let id = read_u8(reader)?;
+                if id == 0 {
+                    break;
+                }
+                let name = read_string(reader)?;
+                map.insert(name, read_payload(reader, id, depth + 1)?);
+            }
+            Tag::Compound(map)
+        }
+        11 => {
+            let len = read_len(reader)?;
+            let mut data = Vec::with_capacity(len.min(4096));
+            for _ in 0..len {
+                data.push(i32::from_be_bytes(read_array(reader)?));
+            }
+            Tag::IntArray(data)
+        }
+        12 => {
+            let len = read_len(reader)?;
+            let mut data = Vec::with_capacity(len.min(4096));
+            for _ in 0..len {
+                data.push(i64::from_be_bytes(read_array(reader)?));
+            }
+            Tag::LongArray(data)
+        }
+        id => return Err(NbtError::InvalidTag(id)),
+    })
+}
+
+fn read_u8(reader: &mut impl Read) -> Result<u8, NbtError> {
+    Ok(read_array::<1>(reader)?[0])
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N], NbtError> {
+    let mut data = [0; N];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+fn read_len(reader: &mut impl Read) -> Result<usize, NbtError> {
+    let len = i32::from_be_bytes(read_array(reader)?);
+    usize::try_from(len).map_err(|_| NbtError::NegativeLength(len))
+}
+
+fn read_string(reader: &mut impl Read) -> Result<String, NbtError> {
+    let len = u16::from_be_bytes(read_array(reader)?) as usize;
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data)?;
+    // java uses modified utf-8, which only differs for \0 and surrogate pairs
+    String::from_utf8(data).map_err(|_| NbtError::InvalidString)
+}
+
+/// Writes an uncompressed named root tag, the inverse of [`read`].
+#[cfg(test)]
+pub(crate) fn write(name: &str, tag: &Tag) -> Vec<u8> {
+    let mut data = vec![tag_id(tag)];
+    write_string(&mut data, name);
+    write_payload(&mut data, tag);
+    data
+}
+
+/// Compound of the given entries, to build test data with.
+#[cfg(test)]
+pub(crate) fn compound<const N: usize>(entries: [(&str, Tag); N]) -> Tag {
+    Tag::Compound(HashMap::from_iter(
+        entries.map(|(name, tag)| (name.to_owned(), tag)),
+    ))
+}
+
+#[cfg(test)]
+fn tag_id(tag: &Tag) -> u8 {
+    match tag {
+        Tag::End => 0,
+        Tag::Byte(_) => 1,
+        Tag::Short(_) => 2,
+        Tag::Int(_) => 3,
+        Tag::Long(_) => 4,
+        Tag::Float(_) => 5,
+        Tag::Double(_) => 6,
+        Tag::ByteArray(_) => 7,
+        Tag::String(_) => 8,
+        Tag::List(_) => 9,
+        Tag::Compound(_) => 10,
+        Tag::IntArray(_) => 11,
+        Tag::LongArray(_) => 12,
+    }
+}
+
+#[cfg(test)]
+fn write_string(data: &mut Vec<u8>, value: &str) {
+    data.extend((value.len() as u16).to_be_bytes());
+    data.extend(value.as_bytes());
+}
+
+#[cfg(test)]
+fn write_payload(data: &mut Vec<u8>, tag: &Tag) {
+    match tag {
+        Tag::End => {}
+        Tag::Byte(v) => data.push(*v as u8),
+        Tag::Short(v) => data.extend(v.to_be_bytes()),
+        Tag::Int(v) => data.extend(v.to_be_bytes()),
+        Tag::Long(v) => data.extend(v.to_be_bytes()),
+        Tag::Float(v) => data.extend(v.to_be_bytes()),
+        Tag::Double(v) => data.extend(v.to_be_bytes()),
+        Tag::ByteArray(v) => {
+            data.extend((v.len() as i32).to_be_bytes());
+            data.extend(v.iter().map(|v| *v as u8));
+        }
+        Tag::String(v) => write_string(data, v),
+        Tag::List(list) => {
+            data.push(list.first().map_or(0, tag_id));
+            data.extend((list.len() as i32).to_be_bytes());
+            for tag in list {
+                write_payload(data, tag);
+            }
+        }
+        Tag::Compound(map) => {
+            for (name, tag) in map {
+                data.push(tag_id(tag));
+                write_string(data, name);
+                write_payload(data, tag);
+            }
+            data.push(0);
+        }
+        Tag::IntArray(v) => {
+            data.extend((v.len() as i32).to_be_bytes());
+            data.extend(v.iter().flat_map(|v| v.to_be_bytes()));
+        }
+        Tag::LongArray(v) => {
+            data.extend((v.len() as i32).to_be_bytes());
+            data.extend(v.iter().flat_map(|v| v.to_be_bytes()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use bevy::utils::HashMap;
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::{read, read_compressed, write, NbtError, Tag, MAX_DEPTH};
+
+    #[test]
+    fn test_roundtrip() {
+        let tag = Tag::Compound(HashMap::from_iter([
+            ("byte".to_owned(), Tag::Byte(-3)),
+            ("name".to_owned(), Tag::String("stone".to_owned())),
+            ("bytes".to_owned(), Tag::ByteArray(vec![1, -1, 0])),
+            ("longs".to_owned(), Tag::LongArray(vec![i64::MIN, 7])),
+            (
+                "list".to_owned(),
+                Tag::List(vec![Tag::Int(1), Tag::Int(-2)]),
+            ),
+            ("empty".to_owned(), Tag::List(Vec::new())),
+        ]));
+        let data = write("root", &tag);
+        assert_eq!(
+            read(&mut data.as_slice()).unwrap(),
+            ("root".to_owned(), tag.clone())
+        );
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(read_compressed(&compressed).unwrap().1, tag);
+    }
+
+    #[test]
+    fn test_malformed() {
+        // a byte array claiming 2 GiB, followed by only 3 bytes
+        let mut data = write("", &Tag::ByteArray(vec![1, 2, 3]));
+        data[3..7].copy_from_slice(&i32::MAX.to_be_bytes());
+        assert!(matches!(read(&mut data.as_slice()), Err(NbtError::Io(_))));
+        data[3..7].copy_from_slice(&(-1i32).to_be_bytes());
+        assert!(matches!(
+            read(&mut data.as_slice()),
+            Err(NbtError::NegativeLength(-1))
+        ));
+        // a list of end tags that claims to have elements
+        let data = [9, 0, 0, 0, 0, 0, 0, 0x10];
+        assert!(matches!(
+            read(&mut data.as_slice()),
+            Err(NbtError::InvalidTag(0))
+        ));
+        // lists nested inside each other, without ever ending
+        let mut data = vec![9, 0, 0];
+        for _ in 0..=MAX_DEPTH {
+            data.extend([9, 0, 0, 0, 1]);
+        }
+        assert!(matches!(read(&mut data.as_slice()), Err(NbtError::TooDeep)));
+        assert!(matches!(
+            read(&mut [10, 0, 0, 42, 0, 0].as_slice()),
+            Err(NbtError::InvalidTag(42))
+        ));
+    }
+}
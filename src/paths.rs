@@ -0,0 +1,29 @@
+use std::{env, path::PathBuf};
+
+const APP_NAME: &str = "minecraft_blockfinder";
+
+/// Directory for what is kept between runs, like the measured finder throughput.
+///
+/// Follows the XDG base directories, uses `%APPDATA%` on Windows and falls back to the working
+/// directory if neither is set.
+pub fn data_dir() -> PathBuf {
+    base_dir("XDG_DATA_HOME", "APPDATA", ".local/share")
+}
+
+/// Directory for files that can be regenerated, like cached chunks.
+pub fn cache_dir() -> PathBuf {
+    base_dir("XDG_CACHE_HOME", "LOCALAPPDATA", ".cache")
+}
+
+fn base_dir(xdg_var: &str, windows_var: &str, home_dir: &str) -> PathBuf {
+    let base = [xdg_var, windows_var]
+        .into_iter()
+        .filter_map(env::var_os)
+        .find(|value| !value.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(home_dir)));
+    match base {
+        Some(base) => base.join(APP_NAME),
+        None => PathBuf::from("."),
+    }
+}
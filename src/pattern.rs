@@ -0,0 +1,273 @@
+use std::{fmt::Write as _, fs, path::Path};
+
+use bevy::math::{IVec3, UVec3};
+use bevy_meshem::prelude::one_d_cords;
+
+use crate::{
+    constants::*,
+    finder::{plugin::FinderJob, util::get_block_rotation, Rotation},
+    game_assets::{BlockId, MinecraftBlockProvider},
+    grid::GridBlocks,
+    import::{check_bounds, check_size, ImportError, ImportReport, Structure},
+    world::World,
+};
+
+const HEADER: &str = "# minecraft_blockfinder pattern";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PatternBlock {
+    pub pos: [usize; 3],
+    pub name: String,
+    /// `None` if the rendered rotation isn't known
+    pub rotation: Option<u8>,
+    pub variants: u8,
+}
+
+/// Blocks with their rendered rotation, relative to the pattern origin.
+///
+/// The text format has one block per line (`x y z block rotation variants`, with `?` for
+/// unknown rotations), preceded by the size and, for patterns with a known answer, the world
+/// position of the origin.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pattern {
+    pub origin: Option<IVec3>,
+    pub size: [usize; 3],
+    pub blocks: Vec<PatternBlock>,
+}
+
+impl Pattern {
+    /// Computes the rendered rotation of every random-rotation block in a box of a world.
+    pub fn extract(
+        world: &mut World,
+        min: IVec3,
+        max: IVec3,
+        variants: impl Fn(&str) -> Option<u8>,
+    ) -> Result<(Self, ImportReport), ImportError> {
+        let structure = world.read_box(min, max)?;
+        Ok(Self::from_structure(&structure, min.min(max), variants))
+    }
+
+    /// Places a structure at `origin` and computes the rotations it would be rendered with there.
+    pub fn from_structure(
+        structure: &Structure,
+        origin: IVec3,
+        variants: impl Fn(&str) -> Option<u8>,
+    ) -> (Self, ImportReport) {
+        let mut report = ImportReport::default();
+        let mut blocks = Vec::new();
+        for (pos, state) in structure.blocks.iter() {
+            if state.is_air() {
+                continue;
+            }
+            let Some(variants) = variants(state.short_name()) else {
+                report
+                    .unmapped
+                    .entry(state.to_string())
+                    .or_default()
+                    .push(*pos);
+                continue;
+            };
+            let world_pos = origin + IVec3::new(pos[0] as i32, pos[1] as i32, pos[2] as i32);
+            let rotation = if variants > 1 {
+                get_block_rotation(world_pos.x as i64, world_pos.y as i64, world_pos.z as i64)
+                    % variants
+            } else {
+                0
+            };
+            report.mapped += 1;
+            blocks.push(PatternBlock {
+                pos: *pos,
+                name: state.short_name().to_owned(),
+                rotation: Some(rotation),
+                variants,
+            });
+        }
+        (
+            Self {
+                origin: Some(origin),
+                size: structure.size,
+                blocks,
+            },
+            report,
+        )
+    }
+
+    pub fn to_job(&self) -> Result<FinderJob, ImportError> {
+        let size = UVec3::new(
+            self.size[0] as u32,
+            self.size[1] as u32,
+            self.size[2] as u32,
+        );
+        if !FinderJob::fits(size) {
+            return Err(ImportError::TooLarge(self.size));
+        }
+        check_bounds(self.size, self.blocks.iter().map(|block| &block.pos))?;
+        let mut rotations = vec![0; self.size[0] * self.size[1] * self.size[2]];
+        for block in self.blocks.iter() {
+            if let Some(rotation) = block.rotation {
+                rotations[one_d_cords(block.pos, (self.size[0], self.size[1], self.size[2]))] =
+                    Rotation::new(rotation, block.variants).0;
+            }
+        }
+        let size = (self.size[0], self.size[1], self.size[2]);
+        Ok(FinderJob::from_rotations(size, &rotations))
+    }
+
+    pub fn to_grid(
+        &self,
+        block_provider: &MinecraftBlockProvider,
+    ) -> Result<(GridBlocks, ImportReport), ImportError> {
+        check_size(self.size)?;
+        check_bounds(self.size, self.blocks.iter().map(|block| &block.pos))?;
+        let mut grid = GridBlocks::for_pattern(self.size);
+        let mut report = ImportReport::default();
+        for block in self.blocks.iter() {
+            let Some(meta) = block_provider.try_get_meta(&block.name) else {
+                report
+                    .unmapped
+                    .entry(block.name.clone())
+                    .or_default()
+                    .push(block.pos);
+                continue;
+            };
+            let variant = match block.rotation {
+                Some(rotation) => rotation % meta.variants.max(1),
+                None => {
+                    report.unknown_rotation += 1;
+                    UNKNOWN_ROTATION
+                }
+            };
+            grid.blocks[one_d_cords(block.pos, grid.size)] = BlockId(meta.id, variant);
+            report.mapped += 1;
+        }
+        Ok((grid, report))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ImportError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ImportError> {
+        Ok(fs::write(path, self.to_string())?)
+    }
+
+    pub fn parse(data: &str) -> Result<Self, ImportError> {
+        let mut pattern = Self::default();
+        for (line_number, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || ImportError::InvalidPattern(line_number + 1);
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["origin", x, y, z] => {
+                    pattern.origin = Some(IVec3::new(
+                        x.parse().map_err(|_| invalid())?,
+                        y.parse().map_err(|_| invalid())?,
+                        z.parse().map_err(|_| invalid())?,
+                    ))
+                }
+                ["size", x, y, z] => {
+                    pattern.size = [
+                        x.parse().map_err(|_| invalid())?,
+                        y.parse().map_err(|_| invalid())?,
+                        z.parse().map_err(|_| invalid())?,
+                    ]
+                }
+                [x, y, z, name, rotation, variants] => {
+                    let block = PatternBlock {
+                        pos: [
+                            x.parse().map_err(|_| invalid())?,
+                            y.parse().map_err(|_| invalid())?,
+                            z.parse().map_err(|_| invalid())?,
+                        ],
+                        name: name.to_string(),
+                        rotation: match *rotation {
+                            "?" => None,
+                            rotation => Some(rotation.parse().map_err(|_| invalid())?),
+                        },
+                        variants: variants.parse().map_err(|_| invalid())?,
+                    };
+                    for i in 0..3 {
+                        pattern.size[i] = pattern.size[i].max(block.pos[i].saturating_add(1));
+                    }
+                    pattern.blocks.push(block);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        // a size line after the blocks may leave some of them outside
+        check_bounds(pattern.size, pattern.blocks.iter().map(|block| &block.pos))?;
+        Ok(pattern)
+    }
+}
+
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{HEADER}")?;
+        if let Some(origin) = self.origin {
+            writeln!(f, "origin {} {} {}", origin.x, origin.y, origin.z)?;
+        }
+        writeln!(f, "size {} {} {}", self.size[0], self.size[1], self.size[2])?;
+        for block in self.blocks.iter() {
+            let mut rotation = String::new();
+            match block.rotation {
+                Some(value) => write!(rotation, "{value}")?,
+                None => rotation.push('?'),
+            }
+            writeln!(
+                f,
+                "{} {} {} {} {rotation} {}",
+                block.pos[0], block.pos[1], block.pos[2], block.name, block.variants
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::math::IVec3;
+
+    use super::Pattern;
+    use crate::{
+        finder::util::get_block_rotation,
+        import::{BlockState, ImportError, Structure},
+    };
+
+    #[test]
+    fn test_pattern_roundtrip() {
+        let structure = Structure {
+            size: [2, 1, 2],
+            blocks: vec![
+                (
+                    [0, 0, 0],
+                    BlockState::parse("minecraft:grass_block[snowy=false]"),
+                ),
+                ([1, 0, 1], BlockState::parse("minecraft:grass_block")),
+                ([1, 0, 0], BlockState::parse("minecraft:stone")),
+            ],
+        };
+        let origin = IVec3::new(9315, 175, 6321);
+        let (pattern, report) = Pattern::from_structure(&structure, origin, |name| {
+            (name == "grass_block").then_some(4)
+        });
+        assert_eq!(report.mapped, 2);
+        assert_eq!(report.unmapped["minecraft:stone"], vec![[1, 0, 0]]);
+        assert_eq!(
+            pattern.blocks[1].rotation,
+            Some(get_block_rotation(9316, 175, 6322) % 4)
+        );
+        assert_eq!(Pattern::parse(&pattern.to_string()).unwrap(), pattern);
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        let data = "0 0 0 grass_block 1 4\n5 0 0 grass_block 2 4\nsize 2 1 2\n";
+        assert!(matches!(
+            Pattern::parse(data),
+            Err(ImportError::OutOfBounds([5, 0, 0]))
+        ));
+    }
+}
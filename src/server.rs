@@ -0,0 +1,533 @@
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use bevy::{log::warn, math::IVec3};
+
+use crate::{
+    finder::{
+        plugin::{parse_y_levels, PatternMatch},
+        queue::{JobInfo, JobQueue, JobState},
+    },
+    pattern::Pattern,
+};
+
+/// Patterns are small, anything larger is rejected before it's read.
+const MAX_BODY: usize = 16 * 1024 * 1024;
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+/// Clients sending their request slower than this are dropped.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serves the JSON API for the jobs of `queue` on `port` of the loopback interface:
+///
+/// - `POST /jobs?radius=..&y_levels=..&name=..&priority=..` with a pattern file as the body
+///   queues a search
+/// - `GET /jobs` lists the jobs, `GET /jobs/<id>` shows one of them
+/// - `GET /jobs/<id>/progress` streams the progress as server-sent events until the job is done
+/// - `GET /jobs/<id>/result` returns the found position of a finished job
+/// - `DELETE /jobs/<id>` cancels a job
+///
+/// There is no authentication, so only requests for localhost are answered, and only web pages
+/// of the `origins`, like `http://localhost:3000` for a dashboard, can submit or cancel jobs and
+/// read the responses.
+pub fn serve(port: u16, origins: Vec<String>, queue: JobQueue) -> Result<(), String> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .map_err(|err| format!("couldn't listen on port {port}: {err}"))?;
+    let origins: Arc<[String]> = origins.into();
+    for stream in listener.incoming() {
+        let stream = match stream.and_then(|stream| {
+            stream.set_read_timeout(Some(READ_TIMEOUT))?;
+            Ok(stream)
+        }) {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("couldn't accept a connection: {err}");
+                continue;
+            }
+        };
+        let queue = queue.clone();
+        let origins = origins.clone();
+        thread::spawn(move || {
+            if let Err(err) = handle(stream, &queue, &origins) {
+                warn!("couldn't answer a request: {err}");
+            }
+        });
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Refuses requests for other hosts, which a web page could send after pointing its own
+    /// domain at this machine, and jobs being changed from pages of origins that aren't in
+    /// `origins`. Returns the origin the response is shared with, if it's one of them.
+    fn check_origin(&self, origins: &[String]) -> Result<Option<&str>, &'static str> {
+        let host = self.header("host").unwrap_or_default();
+        let name = match host.rsplit_once(':') {
+            Some((name, port)) if !port.contains(']') => name,
+            _ => host,
+        };
+        if !matches!(name, "localhost" | "127.0.0.1" | "[::1]") {
+            return Err("only requests for localhost are answered");
+        }
+        // preflight requests are only answered for the allowed origins
+        let changes_jobs = matches!(self.method.as_str(), "POST" | "DELETE" | "OPTIONS");
+        match self.header("origin") {
+            Some(origin) if origins.iter().any(|allowed| allowed == origin) => Ok(Some(origin)),
+            Some(origin) if changes_jobs && origin != format!("http://{host}") => {
+                Err("jobs can't be changed from other origins")
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+fn handle(stream: TcpStream, queue: &JobQueue, origins: &[String]) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut stream = stream;
+    let request = match read_request(&mut reader) {
+        Ok(request) => request,
+        Err(err) => return respond(&mut stream, "", 400, &error_json(&err)),
+    };
+    let cors = match request.check_origin(origins) {
+        Ok(origin) => origin.map(cors_headers).unwrap_or_default(),
+        Err(err) => return respond(&mut stream, "", 403, &error_json(err)),
+    };
+    let cors = cors.as_str();
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    let job = |id: &str| id.parse().ok().and_then(|id| queue.get(id));
+    match (request.method.as_str(), segments.as_slice()) {
+        ("OPTIONS", _) if !cors.is_empty() => respond(&mut stream, cors, 204, ""),
+        ("POST", ["jobs"]) => match submit(&request, queue) {
+            Ok(info) => respond(&mut stream, cors, 201, &job_json(&info)),
+            Err(err) => respond(&mut stream, cors, 400, &error_json(&err)),
+        },
+        ("GET", ["jobs"]) => {
+            let jobs: Vec<String> = queue.jobs().iter().map(job_json).collect();
+            respond(&mut stream, cors, 200, &format!("[{}]", jobs.join(",")))
+        }
+        ("GET", ["jobs", id]) => match job(id) {
+            Some(info) => respond(&mut stream, cors, 200, &job_json(&info)),
+            None => respond(&mut stream, cors, 404, &error_json("unknown job")),
+        },
+        ("GET", ["jobs", id, "progress"]) => match job(id) {
+            Some(info) => stream_progress(&mut stream, cors, queue, info),
+            None => respond(&mut stream, cors, 404, &error_json("unknown job")),
+        },
+        ("GET", ["jobs", id, "result"]) => match job(id) {
+            Some(info) if info.state.is_done() => {
+                respond(&mut stream, cors, 200, &result_json(&info))
+            }
+            Some(_) => respond(
+                &mut stream,
+                cors,
+                409,
+                &error_json("the job is still searching"),
+            ),
+            None => respond(&mut stream, cors, 404, &error_json("unknown job")),
+        },
+        ("DELETE", ["jobs", id]) => match job(id) {
+            Some(info) if queue.cancel(info.id) => respond(
+                &mut stream,
+                cors,
+                200,
+                &job_json(&queue.get(info.id).unwrap()),
+            ),
+            Some(_) => respond(
+                &mut stream,
+                cors,
+                409,
+                &error_json("the job is already done"),
+            ),
+            None => respond(&mut stream, cors, 404, &error_json("unknown job")),
+        },
+        _ => respond(&mut stream, cors, 404, &error_json("unknown endpoint")),
+    }
+}
+
+fn submit(request: &Request, queue: &JobQueue) -> Result<JobInfo, String> {
+    let pattern = std::str::from_utf8(&request.body)
+        .map_err(|_| "the pattern isn't valid UTF-8".to_owned())
+        .and_then(|data| Pattern::parse(data).map_err(|err| err.to_string()))?;
+    let radius = request
+        .query("radius")
+        .map(|value| {
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("invalid radius {value}"))
+        })
+        .transpose()?;
+    let priority = request
+        .query("priority")
+        .map(|value| {
+            value
+                .parse::<i32>()
+                .map_err(|_| format!("invalid priority {value}"))
+        })
+        .transpose()?
+        .unwrap_or(0);
+    let y_levels = match request.query("y_levels") {
+        Some(value) => parse_y_levels(value)?,
+        None => Vec::new(),
+    };
+    let job = pattern
+        .to_job()
+        .map_err(|err| err.to_string())?
+        .with_y_levels(y_levels);
+    let name = match request.query("name") {
+        Some(name) => name.to_owned(),
+        None => format!("pattern of {} blocks", pattern.blocks.len()),
+    };
+    let id = queue.submit(name, job, radius, priority);
+    Ok(queue.get(id).unwrap())
+}
+
+/// Sends the progress of the job every second until it's done.
+fn stream_progress(
+    stream: &mut TcpStream,
+    cors: &str,
+    queue: &JobQueue,
+    mut info: JobInfo,
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+         {cors}Connection: close\r\n\r\n"
+    )?;
+    loop {
+        write!(stream, "data: {}\n\n", job_json(&info))?;
+        if info.state.is_done() {
+            return Ok(());
+        }
+        thread::sleep(PROGRESS_INTERVAL);
+        info = queue.get(info.id).unwrap();
+    }
+}
+
+fn read_request(reader: &mut impl BufRead) -> Result<Request, String> {
+    let mut line = String::new();
+    read_line(reader, &mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err("invalid request line".to_owned());
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut request = Request {
+        method: method.to_owned(),
+        path: decode(path),
+        query: query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (decode(key), decode(value))
+            })
+            .collect(),
+        headers: Vec::new(),
+        body: Vec::new(),
+    };
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        read_line(reader, &mut line)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("invalid content length {value}"))?;
+            }
+            request
+                .headers
+                .push((name.trim().to_owned(), value.trim().to_owned()));
+        }
+    }
+    if content_length > MAX_BODY {
+        return Err("the request is too large".to_owned());
+    }
+    request.body = vec![0; content_length];
+    reader
+        .read_exact(&mut request.body)
+        .map_err(|err| err.to_string())?;
+    Ok(request)
+}
+
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> Result<(), String> {
+    match reader.read_line(line) {
+        Ok(0) => Err("connection closed".to_owned()),
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Decodes `%XX` escapes and `+` of URLs.
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = value
+            .get(index + 1..index + 3)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[index], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 2;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Lets the browser share the responses with pages of `origin`, and send them requests with a
+/// pattern as the body.
+fn cors_headers(origin: &str) -> String {
+    format!(
+        "Access-Control-Allow-Origin: {origin}\r\nAccess-Control-Allow-Methods: GET, POST, \
+         DELETE\r\nAccess-Control-Allow-Headers: Content-Type\r\nAccess-Control-Max-Age: \
+         600\r\nVary: Origin\r\n"
+    )
+}
+
+/// Sends `body` as JSON, with the `headers` ending in a line break each.
+fn respond(stream: &mut TcpStream, headers: &str, status: u16, body: &str) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        409 => "Conflict",
+        _ => "",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status} {reason}\r\nContent-Type: application/json\r\n{headers}\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+fn job_json(info: &JobInfo) -> String {
+    let optional = |value: Option<String>| value.unwrap_or("null".to_owned());
+    let error = match &info.state {
+        JobState::Failed(err) => Some(json_string(err)),
+        _ => None,
+    };
+    format!(
+        "{{\"id\":{},\"name\":{},\"priority\":{},\"state\":{},\"blocks\":{},\"total_blocks\":{},\
+         \"elapsed_seconds\":{:.1},\"rate\":{:.0},\"eta_seconds\":{},\"pos\":{},\"pattern\":{},\
+         \"error\":{}}}",
+        info.id,
+        json_string(&info.name),
+        info.priority,
+        json_string(info.state.name()),
+        info.blocks,
+        optional(info.total_blocks.map(|blocks| blocks.to_string())),
+        info.elapsed.as_secs_f64(),
+        info.rate,
+        optional(info.eta.map(|eta| format!("{:.0}", eta.as_secs_f64()))),
+        optional(found(info).map(|found| pos_json(found.pos))),
+        optional(found(info).map(|found| found.pattern.to_string())),
+        optional(error),
+    )
+}
+
+fn result_json(info: &JobInfo) -> String {
+    let (pos, pattern) = match found(info) {
+        Some(found) => (pos_json(found.pos), found.pattern.to_string()),
+        None => ("null".to_owned(), "null".to_owned()),
+    };
+    format!(
+        "{{\"id\":{},\"found\":{},\"pos\":{pos},\"pattern\":{pattern}}}",
+        info.id,
+        found(info).is_some()
+    )
+}
+
+fn found(info: &JobInfo) -> Option<PatternMatch> {
+    match info.state {
+        JobState::Found(found) => Some(found),
+        _ => None,
+    }
+}
+
+fn pos_json(pos: IVec3) -> String {
+    format!("{{\"x\":{},\"y\":{},\"z\":{}}}", pos.x, pos.y, pos.z)
+}
+
+fn error_json(err: &str) -> String {
+    format!("{{\"error\":{}}}", json_string(err))
+}
+
+pub(crate) fn json_string(value: &str) -> String {
+    let mut escaped = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if c < ' ' => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use bevy::math::IVec3;
+
+    use super::{job_json, read_request, Request};
+    use crate::finder::{
+        plugin::PatternMatch,
+        queue::{JobInfo, JobState},
+    };
+
+    #[test]
+    fn test_read_request() {
+        let data = b"POST /jobs?radius=5000&y_levels=62%2C64..%3D70&name=my+base HTTP/1.1\r\n\
+                     Host: localhost\r\nContent-Length: 7\r\n\r\nsize 1 1 1";
+        let request = read_request(&mut &data[..]).unwrap();
+        assert_eq!(
+            request,
+            Request {
+                method: "POST".to_owned(),
+                path: "/jobs".to_owned(),
+                query: vec![
+                    ("radius".to_owned(), "5000".to_owned()),
+                    ("y_levels".to_owned(), "62,64..=70".to_owned()),
+                    ("name".to_owned(), "my base".to_owned()),
+                ],
+                headers: vec![
+                    ("Host".to_owned(), "localhost".to_owned()),
+                    ("Content-Length".to_owned(), "7".to_owned()),
+                ],
+                body: b"size 1 ".to_vec(),
+            }
+        );
+        assert!(read_request(&mut &b"GET\r\n\r\n"[..]).is_err());
+    }
+
+    #[test]
+    fn test_check_origin() {
+        let request = |method: &str, headers: &[(&str, &str)]| Request {
+            method: method.to_owned(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            ..Default::default()
+        };
+        let none: &[String] = &[];
+        let host = ("Host", "localhost:8080");
+        assert_eq!(request("GET", &[host]).check_origin(none), Ok(None));
+        assert_eq!(request("POST", &[host]).check_origin(none), Ok(None));
+        assert_eq!(
+            request("DELETE", &[host, ("Origin", "http://localhost:8080")]).check_origin(none),
+            Ok(None)
+        );
+        assert!(request("GET", &[("host", "[::1]:80")])
+            .check_origin(none)
+            .is_ok());
+        // pages of other sites may read nothing and change nothing
+        let foreign = ("Origin", "http://example.com");
+        assert_eq!(
+            request("GET", &[host, foreign]).check_origin(none),
+            Ok(None)
+        );
+        assert!(request("POST", &[host, foreign])
+            .check_origin(none)
+            .is_err());
+        assert!(request("OPTIONS", &[host, foreign])
+            .check_origin(none)
+            .is_err());
+        assert!(request("DELETE", &[host, ("Origin", "null")])
+            .check_origin(none)
+            .is_err());
+        assert!(request("GET", &[("Host", "example.com:8080")])
+            .check_origin(none)
+            .is_err());
+        assert!(request("GET", &[("Host", "localhost.example.com")])
+            .check_origin(none)
+            .is_err());
+        assert!(request("GET", &[]).check_origin(none).is_err());
+        // the dashboard may do everything, and its responses are shared with it
+        let origins = ["http://localhost:3000".to_owned()];
+        let dashboard = ("Origin", "http://localhost:3000");
+        for method in ["GET", "POST", "DELETE", "OPTIONS"] {
+            assert_eq!(
+                request(method, &[host, dashboard]).check_origin(&origins),
+                Ok(Some("http://localhost:3000"))
+            );
+        }
+        assert!(request("POST", &[host, foreign])
+            .check_origin(&origins)
+            .is_err());
+        assert!(request("POST", &[("Host", "example.com"), dashboard])
+            .check_origin(&origins)
+            .is_err());
+    }
+
+    #[test]
+    fn test_job_json() {
+        let info = JobInfo {
+            id: 3,
+            name: "the \"old\" base".to_owned(),
+            priority: -1,
+            state: JobState::Found(PatternMatch {
+                pattern: 0,
+                pos: IVec3::new(-5, 64, 12),
+            }),
+            blocks: 1000,
+            total_blocks: None,
+            elapsed: Duration::from_millis(2500),
+            rate: 400.0,
+            eta: None,
+        };
+        assert_eq!(
+            job_json(&info),
+            "{\"id\":3,\"name\":\"the \\\"old\\\" base\",\"priority\":-1,\"state\":\"found\",\"blocks\":1000,\
+             \"total_blocks\":null,\"elapsed_seconds\":2.5,\"rate\":400,\"eta_seconds\":null,\
+             \"pos\":{\"x\":-5,\"y\":64,\"z\":12},\"pattern\":0,\"error\":null}"
+        );
+    }
+}
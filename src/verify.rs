@@ -0,0 +1,198 @@
+use bevy::prelude::*;
+use bevy_meshem::prelude::*;
+
+use crate::{
+    constants::*,
+    finder::{plugin::FinderStatus, util::get_block_rotation},
+    game_assets::{BlockId, MinecraftBlockProvider},
+    grid::Grid,
+    AppState,
+};
+
+/// Distance between the pattern and the rendered world blocks.
+fn verify_offset(grid: &Grid) -> Vec3 {
+    Vec3::new(grid.size.0 as f32 + 4.0, 0.0, 0.0)
+}
+
+/// World position of the grid origin that should be verified.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct VerifyCandidate(pub IVec3);
+
+#[derive(Resource, Default)]
+struct Mismatches {
+    positions: Vec<[usize; 3]>,
+    checked: usize,
+}
+
+#[derive(Component)]
+struct VerifyMesh;
+
+#[derive(Component)]
+struct VerifyGui;
+
+pub struct VerifyPlugin;
+
+impl Plugin for VerifyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Mismatches>()
+            .add_systems(
+                Update,
+                start_verifying.run_if(in_state(AppState::Searching)),
+            )
+            .add_systems(OnEnter(AppState::Verifying), setup_verify_gui)
+            .add_systems(
+                Update,
+                (
+                    move_candidate,
+                    update_world_grid.run_if(resource_changed::<VerifyCandidate>),
+                    highlight_mismatches,
+                )
+                    .chain()
+                    .run_if(in_state(AppState::Verifying)),
+            );
+    }
+}
+
+/// Blocks of the grid with the rotations they would be rendered with at `candidate`, and the
+/// cells where those differ from the grid.
+pub fn world_grid(
+    grid: &Grid,
+    block_provider: &MinecraftBlockProvider,
+    candidate: IVec3,
+) -> (Box<[BlockId]>, Vec<[usize; 3]>) {
+    let mut world = vec![AIR; grid.grid.len()].into_boxed_slice();
+    let mut mismatches = Vec::new();
+    for (index, block) in grid.grid.iter().enumerate() {
+        if *block == AIR {
+            continue;
+        }
+        let variants = block_provider
+            .get_meta_from_index(block.0)
+            .map(|v| v.variants)
+            .unwrap_or_default();
+        if variants <= 1 {
+            world[index] = *block;
+            continue;
+        }
+        let (x, y, z) = three_d_cords(index, grid.size);
+        let pos = candidate + IVec3::new(x as i32, y as i32, z as i32);
+        let rotation = get_block_rotation(pos.x as i64, pos.y as i64, pos.z as i64) % variants;
+        if block.1 != UNKNOWN_ROTATION && block.1 != rotation {
+            mismatches.push([x, y, z]);
+        }
+        world[index] = BlockId(block.0, rotation);
+    }
+    (world, mismatches)
+}
+
+fn start_verifying(
+    mut commands: Commands,
+    inputs: Res<ButtonInput<KeyCode>>,
+    finder_status: Res<FinderStatus>,
+    mut state: ResMut<NextState<AppState>>,
+) {
+    if let FinderStatus::Finished { pos, .. } = finder_status.as_ref() {
+        if inputs.just_pressed(KeyCode::KeyV) {
+            commands.insert_resource(VerifyCandidate(*pos));
+            *state.as_mut() = NextState::Pending(AppState::Verifying);
+        }
+    }
+}
+
+fn setup_verify_gui(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_section("", TextStyle::default()).with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(5.0),
+            left: Val::Px(5.0),
+            ..default()
+        }),
+        VerifyGui,
+    ));
+}
+
+fn move_candidate(inputs: Res<ButtonInput<KeyCode>>, mut candidate: ResMut<VerifyCandidate>) {
+    let offset = [
+        (KeyCode::ArrowLeft, IVec3::NEG_X),
+        (KeyCode::ArrowRight, IVec3::X),
+        (KeyCode::ArrowUp, IVec3::NEG_Z),
+        (KeyCode::ArrowDown, IVec3::Z),
+        (KeyCode::PageUp, IVec3::Y),
+        (KeyCode::PageDown, IVec3::NEG_Y),
+    ]
+    .into_iter()
+    .filter(|(key, _)| inputs.just_pressed(*key))
+    .map(|(_, offset)| offset)
+    .sum::<IVec3>();
+    if offset != IVec3::ZERO {
+        candidate.0 += offset;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_world_grid(
+    mut commands: Commands,
+    candidate: Res<VerifyCandidate>,
+    grid: Res<Grid>,
+    block_provider: Res<MinecraftBlockProvider>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut mismatches: ResMut<Mismatches>,
+    verify_mesh: Query<Entity, With<VerifyMesh>>,
+    mut label: Query<&mut Text, With<VerifyGui>>,
+) {
+    for entity in verify_mesh.iter() {
+        commands.entity(entity).despawn();
+    }
+    let (world, positions) = world_grid(grid.as_ref(), block_provider.as_ref(), candidate.0);
+    let (mesh, _) = mesh_grid(
+        grid.size,
+        &[],
+        world.as_ref(),
+        block_provider.as_ref(),
+        MeshingAlgorithm::Culling,
+        None,
+    )
+    .unwrap();
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(mesh),
+            material: block_provider.get_block_material(),
+            transform: Transform::from_translation(verify_offset(grid.as_ref())),
+            ..default()
+        },
+        VerifyMesh,
+    ));
+    mismatches.checked = grid
+        .grid
+        .iter()
+        .filter(|block| {
+            **block != AIR
+                && block.1 != UNKNOWN_ROTATION
+                && block_provider
+                    .get_meta_from_index(block.0)
+                    .is_some_and(|v| v.variants > 1)
+        })
+        .count();
+    mismatches.positions = positions;
+    *label.single_mut() = Text::from_section(
+        format!(
+            "candidate: {}\n{} of {} blocks differ\narrow keys / page up / page down move the candidate",
+            candidate.0,
+            mismatches.positions.len(),
+            mismatches.checked
+        ),
+        TextStyle::default(),
+    );
+}
+
+fn highlight_mismatches(mismatches: Res<Mismatches>, grid: Res<Grid>, mut gizmos: Gizmos) {
+    for pos in mismatches.positions.iter() {
+        let pos = Vec3::from_array(pos.map(|v| v as f32));
+        for offset in [Vec3::ZERO, verify_offset(grid.as_ref())] {
+            gizmos.cuboid(
+                Transform::from_translation(pos + offset).with_scale(Vec3::splat(1.02)),
+                bevy::color::palettes::css::RED,
+            );
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use bevy::{math::IVec3, utils::HashMap};
+
+use crate::{
+    import::{BlockState, ImportError, Structure},
+    nbt::{self, Tag},
+};
+
+/// First data version that stopped packed block states from spanning two longs (20w17a).
+const DATA_VERSION_NO_SPANNING: i64 = 2529;
+
+/// Read-only access to the Anvil region files of a saved world.
+pub struct World {
+    region_dir: PathBuf,
+    regions: HashMap<(i32, i32), Option<Vec<u8>>>,
+}
+
+/// Decoded block states of a 16x16x16 chunk section.
+struct Section {
+    palette: Vec<BlockState>,
+    indices: Vec<u16>,
+}
+
+impl World {
+    /// Opens a world folder, or a `region` folder of any dimension directly.
+    pub fn open(path: &Path) -> Self {
+        let region_dir = path.join("region");
+        Self {
+            region_dir: if region_dir.is_dir() {
+                region_dir
+            } else {
+                path.to_owned()
+            },
+            regions: HashMap::default(),
+        }
+    }
+
+    /// Reads all blocks between `min` and `max` (inclusive), relative to `min`.
+    pub fn read_box(&mut self, min: IVec3, max: IVec3) -> Result<Structure, ImportError> {
+        let (min, max) = (min.min(max), min.max(max));
+        let size = (max - min + IVec3::ONE).as_uvec3();
+        let mut blocks = Vec::new();
+        for chunk_x in (min.x >> 4)..=(max.x >> 4) {
+            for chunk_z in (min.z >> 4)..=(max.z >> 4) {
+                let sections = self.read_chunk(chunk_x, chunk_z)?;
+                for (section_y, section) in sections {
+                    let base = IVec3::new(chunk_x * 16, section_y * 16, chunk_z * 16);
+                    if base.y + 15 < min.y || base.y > max.y {
+                        continue;
+                    }
+                    for (i, &index) in section.indices.iter().enumerate() {
+                        let pos =
+                            base + IVec3::new(i as i32 % 16, i as i32 / 256, (i as i32 / 16) % 16);
+                        if pos.cmplt(min).any() || pos.cmpgt(max).any() {
+                            continue;
+                        }
+                        let state = section
+                            .palette
+                            .get(index as usize)
+                            .ok_or(ImportError::Malformed("invalid palette index"))?;
+                        if !state.is_air() {
+                            let pos = (pos - min).as_uvec3();
+                            blocks.push((
+                                [pos.x as usize, pos.y as usize, pos.z as usize],
+                                state.clone(),
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(Structure {
+            size: [size.x as usize, size.y as usize, size.z as usize],
+            blocks,
+        })
+    }
+
+    fn read_chunk(
+        &mut self,
+        chunk_x: i32,
+        chunk_z: i32,
+    ) -> Result<Vec<(i32, Section)>, ImportError> {
+        let missing = ImportError::MissingChunk(chunk_x, chunk_z);
+        let Some(region) = self.region(chunk_x >> 5, chunk_z >> 5)? else {
+            return Err(missing);
+        };
+        let header = 4 * ((chunk_x & 31) + (chunk_z & 31) * 32) as usize;
+        let offset = u32::from_be_bytes([0, region[header], region[header + 1], region[header + 2]])
+            as usize
+            * 4096;
+        if offset == 0 || region.len() < offset + 5 {
+            return Err(missing);
+        }
+        let length = u32::from_be_bytes(region[offset..offset + 4].try_into().unwrap()) as usize;
+        let data = region
+            .get(offset + 5..offset + 4 + length)
+            .ok_or(ImportError::Malformed("truncated chunk"))?;
+        match region[offset + 4] {
+            1..=3 => {}
+            _ => return Err(ImportError::Malformed("unsupported chunk compression")),
+        }
+        let (_, root) = nbt::read_compressed(data)?;
+        let data_version = root.get("DataVersion").and_then(Tag::as_i64).unwrap_or(0);
+        // 1.18 moved sections out of the "Level" compound and renamed the block state fields
+        let (sections, palette_key, data_key) = match root.get("sections") {
+            Some(sections) => (Some(sections), None, "data"),
+            None => (
+                root.get("Level").and_then(|level| level.get("Sections")),
+                Some("Palette"),
+                "BlockStates",
+            ),
+        };
+        let mut result = Vec::new();
+        for section in sections.and_then(Tag::as_list).unwrap_or_default() {
+            let y = section
+                .get("Y")
+                .and_then(Tag::as_i64)
+                .ok_or(ImportError::Malformed("section without height"))?;
+            let states = match palette_key {
+                Some(_) => Some(section),
+                None => section.get("block_states"),
+            };
+            let Some(states) = states else {
+                continue;
+            };
+            let Some(palette) = states
+                .get(palette_key.unwrap_or("palette"))
+                .and_then(Tag::as_list)
+            else {
+                continue;
+            };
+            let palette = palette
+                .iter()
+                .map(BlockState::from_nbt)
+                .collect::<Result<Vec<_>, _>>()?;
+            let indices = match states.get(data_key).and_then(Tag::as_long_array) {
+                Some(data) => {
+                    let bits = (u32::BITS
+                        - (palette.len() as u32).saturating_sub(1).leading_zeros())
+                    .max(4);
+                    unpack_indices(data, bits, data_version >= DATA_VERSION_NO_SPANNING)
+                        .ok_or(ImportError::Malformed("block states are too short"))?
+                }
+                // sections with a single state don't store any data
+                None => vec![0; 4096],
+            };
+            result.push((y as i32, Section { palette, indices }));
+        }
+        Ok(result)
+    }
+
+    fn region(&mut self, region_x: i32, region_z: i32) -> Result<Option<&[u8]>, ImportError> {
+        if !self.regions.contains_key(&(region_x, region_z)) {
+            let path = self.region_dir.join(format!("r.{region_x}.{region_z}.mca"));
+            let data = match fs::read(path) {
+                Ok(data) if data.len() >= 8192 => Some(data),
+                Ok(_) => None,
+                Err(err) if err.kind() == ErrorKind::NotFound => None,
+                Err(err) => return Err(err.into()),
+            };
+            self.regions.insert((region_x, region_z), data);
+        }
+        Ok(self.regions[&(region_x, region_z)].as_deref())
+    }
+}
+
+/// Unpacks the 4096 palette indices of a section.
+///
+/// Since 1.16 entries never span two longs, before that they were packed back to back.
+fn unpack_indices(data: &[i64], bits: u32, aligned: bool) -> Option<Vec<u16>> {
+    let mask = (1u64 << bits) - 1;
+    let per_long = (64 / bits) as usize;
+    (0..4096)
+        .map(|i| {
+            if aligned {
+                let value = *data.get(i / per_long)? as u64;
+                Some(((value >> ((i % per_long) * bits as usize)) & mask) as u16)
+            } else {
+                let start_bit = i * bits as usize;
+                let (start, offset) = (start_bit / 64, start_bit % 64);
+                let mut value = (*data.get(start)? as u64) >> offset;
+                if offset + bits as usize > 64 {
+                    value |= (*data.get(start + 1)? as u64) << (64 - offset);
+                }
+                Some((value & mask) as u16)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use bevy::{math::IVec3, utils::HashMap};
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::{unpack_indices, World};
+    use crate::{
+        import::{BlockState, ImportError},
+        nbt::{self, compound, Tag},
+    };
+
+    fn state(name: &str) -> Tag {
+        compound([("Name", Tag::String(name.to_owned()))])
+    }
+
+    /// A 1.18 chunk with stone at 1,0,0 in the lowest section, and air everywhere else.
+    fn chunk() -> Vec<u8> {
+        let mut data = vec![0; 256];
+        data[0] = 1 << 4;
+        let tag = compound([
+            ("DataVersion", Tag::Int(3465)),
+            (
+                "sections",
+                Tag::List(vec![
+                    compound([
+                        ("Y", Tag::Byte(0)),
+                        (
+                            "block_states",
+                            compound([
+                                (
+                                    "palette",
+                                    Tag::List(vec![
+                                        state("minecraft:air"),
+                                        state("minecraft:stone"),
+                                    ]),
+                                ),
+                                ("data", Tag::LongArray(data)),
+                            ]),
+                        ),
+                    ]),
+                    // a section with a single state doesn't store data
+                    compound([
+                        ("Y", Tag::Byte(1)),
+                        (
+                            "block_states",
+                            compound([("palette", Tag::List(vec![state("minecraft:air")]))]),
+                        ),
+                    ]),
+                ]),
+            ),
+        ]);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(&nbt::write("", &tag)).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Points the header entry of chunk `x`, `z` at `sector`.
+    fn set_header(region: &mut [u8], x: usize, z: usize, sector: u32) {
+        let header = 4 * (x + z * 32);
+        region[header..header + 3].copy_from_slice(&sector.to_be_bytes()[1..]);
+        region[header + 3] = 1;
+    }
+
+    #[test]
+    fn test_unpack_indices() {
+        // since 1.16, 12 entries of 5 bits fill a long and the last 4 bits are unused
+        let mut data = vec![0; 342];
+        data[0] = (1 | 2 << 5 | 31u64 << 55) as i64;
+        data[1] = 7;
+        let indices = unpack_indices(&data, 5, true).unwrap();
+        assert_eq!(indices.len(), 4096);
+        assert_eq!(indices[..3], [1, 2, 0]);
+        assert_eq!(indices[11..14], [31, 7, 0]);
+        assert_eq!(unpack_indices(&data[..341], 5, true), None);
+
+        // before, entry 12 starts at bit 60 and continues in the next long
+        let mut data = vec![0; 320];
+        data[0] = (0b0110u64 << 60 | 3) as i64;
+        data[1] = 0b1;
+        let indices = unpack_indices(&data, 5, false).unwrap();
+        assert_eq!(indices[..2], [3, 0]);
+        assert_eq!(indices[11..14], [0, 0b10110, 0]);
+        assert_eq!(unpack_indices(&data[..319], 5, false), None);
+    }
+
+    #[test]
+    fn test_read_chunk() {
+        let chunk = chunk();
+        let mut region = vec![0; 4 * 4096];
+        // length and compression type come before the data
+        let sector = 2 * 4096;
+        region[sector..sector + 4].copy_from_slice(&(chunk.len() as u32 + 1).to_be_bytes());
+        region[sector + 4] = 2;
+        region[sector + 5..sector + 5 + chunk.len()].copy_from_slice(&chunk);
+        set_header(&mut region, 0, 0, 2);
+        // past the end of the region file
+        set_header(&mut region, 0, 1, 9);
+        // a chunk whose length reaches past the end
+        region[3 * 4096..3 * 4096 + 4].copy_from_slice(&8192u32.to_be_bytes());
+        region[3 * 4096 + 4] = 2;
+        set_header(&mut region, 1, 1, 3);
+        let mut world = World {
+            region_dir: std::env::temp_dir().join("missing_world_region"),
+            regions: HashMap::from_iter([((0, 0), Some(region.clone()))]),
+        };
+
+        let sections = world.read_chunk(0, 0).unwrap();
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].1.indices[..3], [0, 1, 0]);
+        assert_eq!(sections[1].1.indices, vec![0; 4096]);
+        let structure = world
+            .read_box(IVec3::new(0, 0, 0), IVec3::new(2, 20, 0))
+            .unwrap();
+        assert_eq!(structure.size, [3, 21, 1]);
+        assert_eq!(
+            structure.blocks,
+            vec![([1, 0, 0], BlockState::parse("minecraft:stone"))]
+        );
+
+        assert!(matches!(
+            world.read_chunk(1, 0),
+            Err(ImportError::MissingChunk(1, 0))
+        ));
+        assert!(matches!(
+            world.read_chunk(0, 1),
+            Err(ImportError::MissingChunk(0, 1))
+        ));
+        assert!(matches!(
+            world.read_chunk(1, 1),
+            Err(ImportError::Malformed("truncated chunk"))
+        ));
+        // chunks of regions that don't exist haven't been generated
+        assert!(matches!(
+            world.read_chunk(32, 0),
+            Err(ImportError::MissingChunk(32, 0))
+        ));
+
+        // only zlib, gzip and uncompressed chunks exist
+        region[sector + 4] = 4;
+        world.regions.insert((0, 0), Some(region));
+        assert!(matches!(
+            world.read_chunk(0, 0),
+            Err(ImportError::Malformed("unsupported chunk compression"))
+        ));
+    }
+}